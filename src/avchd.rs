@@ -0,0 +1,158 @@
+use crate::video::VideoMetadata;
+use chrono::NaiveDate;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The date is in the first frames, so only the start of the stream is searched.
+const MAX_SEARCH_SIZE: u64 = 4 * 1024 * 1024;
+/// UUID of the H.264 user data holding the camera metadata (`MDPM`) of AVCHD streams.
+const MDPM_MARKER: [u8; 20] = [
+    0x17, 0xee, 0x8c, 0x60, 0xf8, 0x4d, 0x11, 0xd9, 0x8c, 0xd6, 0x08, 0x00, 0x20, 0x0c, 0x9a, 0x66,
+    b'M', b'D', b'P', b'M',
+];
+const DATE_TAG: u8 = 0x18;
+const TIME_TAG: u8 = 0x19;
+
+/// Reads the recording time of an AVCHD transport stream (MTS or M2TS) from the camera metadata
+/// in the H.264 stream. It is the local time of the camera.
+pub(crate) fn read_avchd_metadata(path: &Path) -> Result<VideoMetadata, String> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(MAX_SEARCH_SIZE).read_to_end(&mut data))
+        .map_err(|e| e.to_string())?;
+    read_camera_metadata(&data)
+}
+
+/// Reads the recording time from the start of a stream.
+fn read_camera_metadata(data: &[u8]) -> Result<VideoMetadata, String> {
+    let start = data
+        .windows(MDPM_MARKER.len())
+        .position(|window| window == MDPM_MARKER)
+        .ok_or("No AVCHD camera metadata found.".to_string())?;
+    let entries = unescape(&data[start + MDPM_MARKER.len()..]);
+
+    let mut metadata = VideoMetadata::default();
+    let count = entries.first().copied().unwrap_or_default() as usize;
+    let entries: Vec<&[u8]> = entries
+        .get(1..)
+        .unwrap_or_default()
+        .chunks_exact(5)
+        .take(count)
+        .collect();
+    let value = |tag: u8| {
+        entries
+            .iter()
+            .find(|entry| entry[0] == tag)
+            .map(|entry| &entry[1..])
+    };
+    if let Some((date, time)) = value(DATE_TAG).zip(value(TIME_TAG)) {
+        // The first byte of the date is the time zone, the others are BCD digits.
+        let digits: Vec<u32> = date[1..].iter().chain(time).map(|&b| bcd(b)).collect();
+        metadata.recording_time =
+            NaiveDate::from_ymd_opt((digits[0] * 100 + digits[1]) as i32, digits[2], digits[3])
+                .and_then(|date| date.and_hms_opt(digits[4], digits[5], digits[6]));
+        metadata.tags.insert(
+            "MDPM:DateTimeOriginal".to_string(),
+            format!(
+                "{:02}{:02}:{:02}:{:02} {:02}:{:02}:{:02}",
+                digits[0], digits[1], digits[2], digits[3], digits[4], digits[5], digits[6]
+            ),
+        );
+    }
+    Ok(metadata)
+}
+
+/// Removes the emulation prevention bytes of the H.264 stream, the 3 in `00 00 03`.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::new();
+    let mut zeros = 0;
+    for &byte in data.iter().take(256) {
+        if byte == 3 && zeros >= 2 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        unescaped.push(byte);
+    }
+    unescaped
+}
+
+fn bcd(byte: u8) -> u32 {
+    (byte >> 4) as u32 * 10 + (byte & 0x0F) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream with camera metadata of four entries, one of them escaped.
+    fn stream() -> Vec<u8> {
+        let mut stream = vec![0x47, 0x40, 0x11, 0x10, 0, 0, 1, 6];
+        stream.extend(MDPM_MARKER);
+        stream.push(4);
+        stream.extend([0xE0, 0, 0, 3, 1, 0]);
+        stream.extend([DATE_TAG, 0x09, 0x20, 0x20, 0x01]);
+        stream.extend([TIME_TAG, 0x02, 0x03, 0x04, 0x05]);
+        stream.extend([0xE1, 0x12, 0x34, 0x56, 0x78]);
+        stream
+    }
+
+    #[test]
+    fn reads_recording_time() {
+        let metadata = read_camera_metadata(&stream()).unwrap();
+        assert_eq!(
+            metadata.recording_time.unwrap().to_string(),
+            "2020-01-02 03:04:05"
+        );
+        assert_eq!(
+            metadata
+                .tags
+                .get("MDPM:DateTimeOriginal")
+                .map(String::as_str),
+            Some("2020:01:02 03:04:05")
+        );
+    }
+
+    #[test]
+    fn reads_truncated_streams() {
+        let stream = stream();
+        let marker_end = stream.len() - 22;
+        let time_end = stream.len() - 5;
+        for length in 0..stream.len() {
+            let metadata = read_camera_metadata(&stream[..length]);
+            if length < marker_end {
+                assert!(metadata.is_err(), "{}", length);
+            } else {
+                let recording_time = metadata.unwrap().recording_time;
+                assert_eq!(recording_time.is_some(), length >= time_end, "{}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_invalid_dates() {
+        let mut invalid_month = stream();
+        let month = invalid_month.len() - 11;
+        invalid_month[month] = 0x13;
+        let metadata = read_camera_metadata(&invalid_month).unwrap();
+        assert_eq!(metadata.recording_time, None);
+        // Entries beyond the count are not read.
+        let mut fewer_entries = stream();
+        fewer_entries[MDPM_MARKER.len() + 8] = 2;
+        assert!(read_camera_metadata(&fewer_entries)
+            .unwrap()
+            .recording_time
+            .is_none());
+    }
+
+    #[test]
+    fn removes_emulation_prevention_bytes() {
+        assert_eq!(
+            unescape(&[0, 0, 3, 1, 0, 3, 0, 0, 3]),
+            [0, 0, 1, 0, 3, 0, 0]
+        );
+        assert_eq!(unescape(&[0; 300]).len(), 256);
+        assert_eq!(bcd(0x59), 59);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::video::VideoMetadata;
+use chrono::NaiveDateTime;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Caps the memory used for an `hdrl` or `INFO` list, real ones hold a few kilobytes of stream
+/// headers and tags.
+const MAX_LIST_SIZE: u32 = 16 * 1024 * 1024;
+/// Formats of the `IDIT` chunk, the first one is the usual one of `ctime`.
+const IDIT_FORMATS: [&str; 3] = [
+    "%a %b %d %H:%M:%S %Y",
+    "%Y:%m:%d %H:%M:%S",
+    "%Y/%m/%d %H:%M:%S",
+];
+
+/// Reads the `IDIT` date and the `INFO` tags of an AVI file. The movie data in between is skipped.
+pub(crate) fn read_avi_metadata(path: &Path) -> Result<VideoMetadata, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    read_avi(&mut file)
+}
+
+fn read_avi<R: Read + Seek>(reader: &mut R) -> Result<VideoMetadata, String> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"AVI " {
+        return Err("Not an AVI file.".to_string());
+    }
+    let mut metadata = VideoMetadata::default();
+    while let Some(list) = read_next_list(reader).map_err(|e| e.to_string())? {
+        read_chunks(&list, &mut metadata);
+    }
+    metadata.recording_time = metadata.tags.get("IDIT").and_then(|idit| parse_idit(idit));
+    Ok(metadata)
+}
+
+/// Skips the chunks up to the next `hdrl` or `INFO` list and reads its content.
+fn read_next_list<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = [0; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        // Chunks are padded to an even size.
+        let padded_size = size as i64 + (size % 2) as i64;
+        if &header[0..4] == b"LIST" && size >= 4 {
+            let mut list_type = [0; 4];
+            reader.read_exact(&mut list_type)?;
+            if matches!(&list_type, b"hdrl" | b"INFO") && size <= MAX_LIST_SIZE {
+                let mut content = vec![0; size as usize - 4];
+                reader.read_exact(&mut content)?;
+                reader.seek(SeekFrom::Current(padded_size - size as i64))?;
+                return Ok(Some(content));
+            }
+            reader.seek(SeekFrom::Current(padded_size - 4))?;
+        } else {
+            reader.seek(SeekFrom::Current(padded_size))?;
+        }
+    }
+}
+
+/// Collects the text chunks of a list and its sub lists, e.g. `IDIT` or `ICRD`.
+fn read_chunks(mut data: &[u8], metadata: &mut VideoMetadata) {
+    while data.len() >= 8 {
+        let kind = &data[0..4];
+        let size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let Some(content) = data.get(8..8 + size) else {
+            return;
+        };
+        if kind == b"LIST" {
+            read_chunks(content.get(4..).unwrap_or_default(), metadata);
+        } else if kind[0] == b'I' {
+            // The date and the `INFO` tags, all starting with `I`.
+            let text = String::from_utf8_lossy(content)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string();
+            if !text.is_empty() && text.chars().all(|c| !c.is_control()) {
+                metadata
+                    .tags
+                    .insert(String::from_utf8_lossy(kind).into_owned(), text);
+            }
+        }
+        data = data.get(8 + size + size % 2..).unwrap_or_default();
+    }
+}
+
+fn parse_idit(text: &str) -> Option<NaiveDateTime> {
+    // Some cameras write two spaces before single digit days.
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    IDIT_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(list_type: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = list_type.to_vec();
+        data.extend(chunks.concat());
+        chunk(b"LIST", &data)
+    }
+
+    fn avi() -> Vec<u8> {
+        let hdrl = list(
+            b"hdrl",
+            &[
+                chunk(b"avih", &[0; 56]),
+                list(b"strl", &[chunk(b"strh", &[0; 7])]),
+                chunk(b"IDIT", b"Thu Jan  2 03:04:05 2020\n\0"),
+            ],
+        );
+        let movi = list(b"movi", &[chunk(b"00dc", &[0xff; 101])]);
+        let info = list(
+            b"INFO",
+            &[chunk(b"ISFT", b"Camera\0"), chunk(b"ICMT", b"\x01\x02")],
+        );
+        let mut riff = b"AVI ".to_vec();
+        riff.extend([hdrl, movi, info].concat());
+        chunk(b"RIFF", &riff)
+    }
+
+    fn read(data: &[u8]) -> Result<VideoMetadata, String> {
+        read_avi(&mut Cursor::new(data))
+    }
+
+    #[test]
+    fn reads_idit_and_info_tags() {
+        let metadata = read(&avi()).unwrap();
+        assert_eq!(
+            metadata.recording_time.unwrap().to_string(),
+            "2020-01-02 03:04:05"
+        );
+        assert_eq!(
+            metadata.tags.get("ISFT").map(String::as_str),
+            Some("Camera")
+        );
+        // Binary data is no text.
+        assert!(!metadata.tags.contains_key("ICMT"));
+    }
+
+    #[test]
+    fn reads_truncated_files() {
+        let avi = avi();
+        for length in 0..avi.len() {
+            if let Ok(metadata) = read(&avi[..length]) {
+                assert!(length >= 12);
+                assert!(metadata
+                    .recording_time
+                    .is_none_or(|date_time| date_time.to_string() == "2020-01-02 03:04:05"));
+            }
+        }
+        assert!(read(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+
+    #[test]
+    fn skips_malformed_lists() {
+        // Lists smaller than their type, too large and odd sized, in between the header lists.
+        let mut data = chunk(b"LIST", b"hd");
+        data.extend(b"LIST\xff\xff\xff\xffhdrl");
+        let mut cursor = Cursor::new(data);
+        assert_eq!(read_next_list(&mut cursor).unwrap(), None);
+
+        let mut data = list(b"movi", &[chunk(b"00dc", &[0; 3])]);
+        data.extend(list(b"INFO", &[chunk(b"ISFT", b"x")]));
+        let list = read_next_list(&mut Cursor::new(data)).unwrap().unwrap();
+        assert_eq!(list, chunk(b"ISFT", b"x"));
+    }
+
+    #[test]
+    fn reads_malformed_chunks() {
+        let mut metadata = VideoMetadata::default();
+        read_chunks(b"ISFT\xff\0\0\0text", &mut metadata);
+        read_chunks(b"LIST\x02\0\0\0IN", &mut metadata);
+        read_chunks(b"ISF", &mut metadata);
+        assert!(metadata.tags.is_empty());
+    }
+
+    #[test]
+    fn parses_idit_formats() {
+        for text in [
+            "Thu Jan  2 03:04:05 2020",
+            "Thu Jan 02 03:04:05 2020",
+            "2020:01:02 03:04:05",
+            "2020/01/02 03:04:05",
+        ] {
+            assert_eq!(
+                parse_idit(text).unwrap().to_string(),
+                "2020-01-02 03:04:05",
+                "{}",
+                text
+            );
+        }
+        assert_eq!(parse_idit("2020-13-02 03:04:05"), None);
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Caps the memory used for the `moov` box of videos and CR3 files. Besides the metadata it holds
+/// the sample tables, which grow with the length of a video.
+pub(crate) const MAX_MOOV_SIZE: u64 = 256 * 1024 * 1024;
+
+/// A box of an ISO base media file, the container of MP4, MOV and HEIF files.
+pub(crate) struct Atom<'a> {
+    pub(crate) kind: [u8; 4],
+    pub(crate) data: &'a [u8],
+}
+
+/// Skips the top level boxes up to the first one of `kind` and reads its content. A box larger
+/// than `max_size` is an error.
+pub(crate) fn read_top_level_box<R: Read + Seek>(
+    reader: &mut R,
+    kind: &[u8; 4],
+    max_size: u64,
+) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = [0; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let box_kind = &header[4..8];
+        let (size, header_size) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            0 => {
+                let position = reader.stream_position()?;
+                let end = reader.seek(SeekFrom::End(0))?;
+                reader.seek(SeekFrom::Start(position))?;
+                (end - position + 8, 8)
+            }
+            1 => {
+                let mut large_size = [0; 8];
+                reader.read_exact(&mut large_size)?;
+                (u64::from_be_bytes(large_size), 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_size {
+            return Ok(None);
+        }
+        let content_size = size - header_size;
+        if box_kind == kind {
+            if content_size > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} box too large", String::from_utf8_lossy(kind)),
+                ));
+            }
+            let mut content = vec![0; content_size as usize];
+            reader.read_exact(&mut content)?;
+            return Ok(Some(content));
+        }
+        let Ok(content_size) = i64::try_from(content_size) else {
+            return Ok(None);
+        };
+        reader.seek(SeekFrom::Current(content_size))?;
+    }
+}
+
+/// The child boxes in `data`, ending at the first malformed one.
+pub(crate) fn atoms(mut data: &[u8]) -> impl Iterator<Item = Atom<'_>> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let kind: [u8; 4] = data[4..8].try_into().unwrap();
+        let (size, header_size) = match u32::from_be_bytes(data[0..4].try_into().unwrap()) {
+            0 => (data.len(), 8),
+            1 if data.len() >= 16 => (
+                usize::try_from(u64::from_be_bytes(data[8..16].try_into().unwrap())).ok()?,
+                16,
+            ),
+            size => (size as usize, 8),
+        };
+        if size < header_size || size > data.len() {
+            return None;
+        }
+        let atom = Atom {
+            kind,
+            data: &data[header_size..size],
+        };
+        data = &data[size..];
+        Some(atom)
+    })
+}
+
+/// A box of `kind` holding `data`, to build files for tests.
+#[cfg(test)]
+pub(crate) fn atom(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut atom = (data.len() as u32 + 8).to_be_bytes().to_vec();
+    atom.extend_from_slice(kind);
+    atom.extend_from_slice(data);
+    atom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read(data: &[u8], kind: &[u8; 4], max_size: u64) -> io::Result<Option<Vec<u8>>> {
+        read_top_level_box(&mut Cursor::new(data), kind, max_size)
+    }
+
+    #[test]
+    fn reads_top_level_boxes() {
+        let mut file = atom(b"ftyp", b"isom");
+        file.extend(atom(b"moov", b"movie"));
+        assert_eq!(read(&file, b"moov", 5).unwrap(), Some(b"movie".to_vec()));
+        assert_eq!(
+            read(&file, b"moov", 4).unwrap_err().to_string(),
+            "moov box too large"
+        );
+        assert_eq!(read(&file, b"mdat", 100).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_boxes_with_large_and_open_sizes() {
+        let mut file = b"\0\0\0\x01free\0\0\0\0\0\0\0\x11x".to_vec();
+        file.extend(b"\0\0\0\0moovmovie");
+        assert_eq!(read(&file, b"moov", 100).unwrap(), Some(b"movie".to_vec()));
+    }
+
+    #[test]
+    fn stops_at_malformed_top_level_boxes() {
+        let file = atom(b"moov", b"movie");
+        for length in 0..file.len() {
+            assert!(!matches!(read(&file[..length], b"moov", 100), Ok(Some(_))));
+        }
+        // Smaller than their header.
+        assert_eq!(read(b"\0\0\0\x04moov", b"moov", 100).unwrap(), None);
+        assert_eq!(
+            read(b"\0\0\0\x01moov\0\0\0\0\0\0\0\x08", b"moov", 100).unwrap(),
+            None
+        );
+        // Larger than any file.
+        let file = b"\0\0\0\x01free\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x0dmoovx";
+        assert_eq!(read(file, b"moov", 100).unwrap(), None);
+    }
+
+    #[test]
+    fn iterates_child_boxes() {
+        let mut data = atom(b"mvhd", b"header");
+        data.extend(atom(b"trak", &atom(b"tkhd", b"track")));
+        let kinds: Vec<[u8; 4]> = atoms(&data).map(|atom| atom.kind).collect();
+        assert_eq!(kinds, [*b"mvhd", *b"trak"]);
+        let trak = atoms(&data).nth(1).unwrap();
+        assert_eq!(atoms(trak.data).next().unwrap().data, b"track");
+    }
+
+    #[test]
+    fn stops_at_malformed_child_boxes() {
+        let mut data = atom(b"mvhd", b"header");
+        data.extend(atom(b"udta", b"user data"));
+        for length in 0..data.len() {
+            let expected = if length < 14 { 0 } else { 1 };
+            assert_eq!(atoms(&data[..length]).count(), expected, "{}", length);
+        }
+        assert_eq!(atoms(b"\0\0\0\x07free").count(), 0);
+        assert_eq!(atoms(b"\0\0\0\x01free\0\0\0\0\0\0\0").count(), 0);
+        assert_eq!(
+            atoms(b"\0\0\0\x01free\xff\xff\xff\xff\xff\xff\xff\xff").count(),
+            0
+        );
+        // Size 0 extends to the end.
+        assert_eq!(atoms(b"\0\0\0\0freexyz").next().unwrap().data, b"xyz");
+    }
+}
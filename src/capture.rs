@@ -0,0 +1,562 @@
+use crate::filename::{default_filename_patterns, FilenamePattern, YearRange};
+use crate::heif::{is_heif, read_heif_exif};
+use crate::image_text::{has_image_text, read_image_text};
+use crate::raw::{is_raw, read_raw_exif};
+use crate::takeout::{find_takeout_json, read_takeout_metadata};
+use crate::timezone::{parse_offset, CaptureZone};
+use crate::video::{read_video_metadata, VideoBackend};
+use crate::xmp::{find_sidecar, read_embedded_xmp, xmp_capture_date, xmp_properties};
+use crate::{is_image, is_video};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use clap::ValueEnum;
+use exif::{In, Tag};
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+/// When and with what a media file was captured.
+#[derive(Debug, Clone)]
+pub struct CaptureInfo {
+    pub date_time: NaiveDateTime,
+    /// Fractional seconds as stored in the metadata, e.g. `042`.
+    pub subsec: Option<String>,
+    pub camera: Option<String>,
+    /// UTC offset of `date_time`, if known.
+    pub offset: Option<FixedOffset>,
+}
+
+/// A capture date together with where it came from and how far it can be trusted.
+#[derive(Debug, Clone)]
+pub struct ExtractedDate {
+    pub capture_info: CaptureInfo,
+    /// Name of the extractor, e.g. `exif`.
+    pub source: String,
+    pub precision: DatePrecision,
+    pub confidence: Confidence,
+}
+
+/// The smallest unit of an extracted date that is actually known. Smaller units are zero, or one
+/// for days.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DatePrecision {
+    Year,
+    Month,
+    Day,
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// Possibly unrelated to the capture, like the modification time of the file. Only used after
+    /// asking, unless the file creation fallback is enabled.
+    Low,
+    /// Written by other software, like dates in file names.
+    Medium,
+    /// Written by the camera.
+    High,
+}
+
+/// Reads the capture date from one source.
+pub trait DateExtractor {
+    /// Name of the source, used in messages and to identify runs with the same options.
+    fn name(&self) -> &str;
+
+    /// The capture date, or the reason why this source has none for the file.
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String>;
+}
+
+/// The built-in date sources, selectable with `--date-sources`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateSource {
+    /// EXIF date of images, or the date in the text metadata of PNG, WebP and GIF images
+    Exif,
+    /// Date in the XMP sidecar, e.g. IMG_1234.jpg.xmp, or in the XMP packet embedded in images
+    Xmp,
+    /// Time the photo was taken from the JSON file of a Google Takeout archive, e.g.
+    /// IMG_1234.jpg.json
+    Takeout,
+    /// Creation time of videos
+    Video,
+    /// Date in the file name, e.g. IMG_20210314_120000.jpg or signal-2021-03-14-120000.jpg
+    Filename,
+    /// Modification time of the file
+    Mtime,
+}
+
+pub const DEFAULT_DATE_SOURCES: [DateSource; 6] = [
+    DateSource::Exif,
+    DateSource::Xmp,
+    DateSource::Takeout,
+    DateSource::Video,
+    DateSource::Filename,
+    DateSource::Mtime,
+];
+
+/// Settings of the built-in extractors.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractorSettings {
+    pub video_backend: VideoBackend,
+    pub time_zone: CaptureZone,
+    /// Patterns of file names that are tried before the built-in ones.
+    pub filename_patterns: Vec<FilenamePattern>,
+    /// The years dates in file names may have.
+    pub filename_years: YearRange,
+}
+
+/// Extractors that are asked in order, the first date found wins. Dates are converted to the
+/// time zone of the pipeline, so files are sorted the same way whatever source their date has.
+pub struct DatePipeline {
+    extractors: Vec<Box<dyn DateExtractor>>,
+    time_zone: CaptureZone,
+}
+
+struct ExifExtractor;
+struct XmpExtractor;
+struct TakeoutExtractor;
+struct VideoExtractor {
+    backend: VideoBackend,
+}
+struct FilenameExtractor {
+    patterns: Vec<FilenamePattern>,
+    years: YearRange,
+}
+struct MtimeExtractor;
+
+impl DateSource {
+    pub fn extractor(&self, settings: &ExtractorSettings) -> Box<dyn DateExtractor> {
+        match self {
+            DateSource::Exif => Box::new(ExifExtractor),
+            DateSource::Xmp => Box::new(XmpExtractor),
+            DateSource::Takeout => Box::new(TakeoutExtractor),
+            DateSource::Video => Box::new(VideoExtractor {
+                backend: settings.video_backend,
+            }),
+            DateSource::Filename => Box::new(FilenameExtractor {
+                patterns: settings
+                    .filename_patterns
+                    .iter()
+                    .chain(default_filename_patterns())
+                    .cloned()
+                    .collect(),
+                years: settings.filename_years,
+            }),
+            DateSource::Mtime => Box::new(MtimeExtractor),
+        }
+    }
+}
+
+impl DatePipeline {
+    /// A pipeline without extractors, to be filled with [`DatePipeline::push`].
+    pub fn empty() -> DatePipeline {
+        DatePipeline {
+            extractors: Vec::new(),
+            time_zone: CaptureZone::Local,
+        }
+    }
+
+    pub fn from_sources(sources: &[DateSource], settings: &ExtractorSettings) -> DatePipeline {
+        DatePipeline {
+            extractors: sources
+                .iter()
+                .map(|source| source.extractor(settings))
+                .collect(),
+            time_zone: settings.time_zone,
+        }
+    }
+
+    pub fn set_time_zone(&mut self, time_zone: CaptureZone) {
+        self.time_zone = time_zone;
+    }
+
+    /// Adds an extractor that is asked after all others.
+    pub fn push(&mut self, extractor: Box<dyn DateExtractor>) {
+        self.extractors.push(extractor);
+    }
+
+    pub fn extractors(&self) -> &[Box<dyn DateExtractor>] {
+        &self.extractors
+    }
+
+    /// Whether an extractor with this name is asked.
+    pub fn uses(&self, name: &str) -> bool {
+        self.extractors
+            .iter()
+            .any(|extractor| extractor.name() == name)
+    }
+
+    /// The date of the first extractor that finds one. The camera is taken from the EXIF data if
+    /// that extractor does not know it.
+    pub fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let mut reasons = Vec::new();
+        for extractor in &self.extractors {
+            match extractor.extract(path) {
+                Ok(mut extracted) => {
+                    if extracted.capture_info.camera.is_none() && is_image(path) {
+                        extracted.capture_info.camera = read_exif(path)
+                            .ok()
+                            .and_then(|exif| exif_text(&exif, Tag::Model));
+                    }
+                    extracted.normalize(&self.time_zone);
+                    return Ok(extracted);
+                }
+                Err(reason) => reasons.push(format!("{}: {}", extractor.name(), reason)),
+            }
+        }
+        Err(format!(
+            "Could not determine a media file creation date ({}).",
+            reasons.join(", ")
+        ))
+    }
+}
+
+impl Default for DatePipeline {
+    fn default() -> DatePipeline {
+        DatePipeline::from_sources(&DEFAULT_DATE_SOURCES, &ExtractorSettings::default())
+    }
+}
+
+impl fmt::Debug for DatePipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatePipeline")
+            .field(
+                "extractors",
+                &self.extractors.iter().map(|e| e.name()).collect::<Vec<_>>(),
+            )
+            .field("time_zone", &self.time_zone)
+            .finish()
+    }
+}
+
+impl ExtractedDate {
+    fn new(
+        source: &str,
+        date_time: NaiveDateTime,
+        precision: DatePrecision,
+        confidence: Confidence,
+    ) -> ExtractedDate {
+        ExtractedDate {
+            capture_info: CaptureInfo {
+                date_time,
+                subsec: None,
+                camera: None,
+                offset: None,
+            },
+            source: source.to_string(),
+            precision,
+            confidence,
+        }
+    }
+
+    /// Converts the date to `time_zone`. Dates without offset are taken to be in that zone.
+    pub fn normalize(&mut self, time_zone: &CaptureZone) {
+        let capture_info = &mut self.capture_info;
+        match capture_info.offset {
+            Some(offset) => {
+                let utc = capture_info.date_time - offset;
+                let (date_time, offset) = time_zone.from_utc(&utc);
+                capture_info.date_time = date_time;
+                capture_info.offset = Some(offset);
+            }
+            None => capture_info.offset = time_zone.offset_of(&capture_info.date_time),
+        }
+    }
+}
+
+impl DateExtractor for ExifExtractor {
+    fn name(&self) -> &str {
+        "exif"
+    }
+
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        if !is_image(path) {
+            return Err("not an image".to_string());
+        }
+        match read_exif(path).and_then(|exif| self.extract_exif(&exif)) {
+            Err(exif_error) if has_image_text(path) => self
+                .extract_image_text(path)
+                .map_err(|text_error| format!("{} {}", exif_error, text_error)),
+            extracted => extracted,
+        }
+    }
+}
+
+impl ExifExtractor {
+    fn extract_exif(&self, exif: &exif::Exif) -> Result<ExtractedDate, String> {
+        let (date_time, offset_tag) = [
+            (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+            (Tag::DateTime, Tag::OffsetTime),
+            (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+        ]
+        .into_iter()
+        .find_map(|(tag, offset_tag)| {
+            exif.get_field(tag, In::PRIMARY)
+                .map(|field| (field.display_value().to_string(), offset_tag))
+        })
+        .ok_or("DateTime tag is missing.".to_string())?;
+        let date_time = NaiveDateTime::parse_from_str(date_time.trim(), "%Y-%m-%d %H:%M:%S")
+            .map_err(|e| e.to_string())?;
+        let mut extracted = ExtractedDate::new(
+            self.name(),
+            date_time,
+            DatePrecision::Second,
+            Confidence::High,
+        );
+        extracted.capture_info.subsec =
+            exif_text(exif, Tag::SubSecTimeOriginal).or_else(|| exif_text(exif, Tag::SubSecTime));
+        extracted.capture_info.camera = exif_text(exif, Tag::Model);
+        extracted.capture_info.offset =
+            exif_text(exif, offset_tag).and_then(|offset| parse_offset(&offset));
+        Ok(extracted)
+    }
+
+    /// PNG, WebP and GIF images often have their date in text chunks or XMP instead.
+    fn extract_image_text(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let (date_time, offset, precision) = read_image_text(path)?
+            .capture_date()
+            .ok_or("No date in the text metadata.".to_string())?;
+        let mut extracted = ExtractedDate::new(self.name(), date_time, precision, Confidence::High);
+        extracted.capture_info.offset = offset;
+        Ok(extracted)
+    }
+}
+
+impl DateExtractor for XmpExtractor {
+    fn name(&self) -> &str {
+        "xmp"
+    }
+
+    /// The sidecar wins over the embedded packet, it may have been corrected by hand.
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let sidecar = find_sidecar(path)
+            .map(|sidecar| {
+                fs::read_to_string(&sidecar)
+                    .map_err(|e| format!("Can't read sidecar {:?}: {}", sidecar, e))
+            })
+            .transpose()?;
+        let embedded = is_image(path)
+            .then(|| read_embedded_xmp(path).ok())
+            .flatten();
+        if sidecar.is_none() && embedded.is_none() {
+            return Err("no XMP sidecar or packet".to_string());
+        }
+        let (properties, (date_time, offset, precision)) = sidecar
+            .iter()
+            .chain(&embedded)
+            .map(|packet| xmp_properties(packet))
+            .find_map(|properties| {
+                let date = xmp_capture_date(&properties)?;
+                Some((properties, date))
+            })
+            .ok_or("No date in the XMP packet.".to_string())?;
+        let mut extracted = ExtractedDate::new(self.name(), date_time, precision, Confidence::High);
+        extracted.capture_info.offset = offset;
+        extracted.capture_info.camera = properties.get("tiff:Model").cloned();
+        Ok(extracted)
+    }
+}
+
+impl DateExtractor for TakeoutExtractor {
+    fn name(&self) -> &str {
+        "takeout"
+    }
+
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let json = find_takeout_json(path).ok_or("no Takeout metadata")?;
+        let date_time = read_takeout_metadata(&json)?
+            .photo_taken_time
+            .ok_or_else(|| format!("No photoTakenTime in {:?}.", json))?;
+        let mut extracted = ExtractedDate::new(
+            self.name(),
+            date_time,
+            DatePrecision::Second,
+            Confidence::High,
+        );
+        extracted.capture_info.offset = FixedOffset::east_opt(0);
+        Ok(extracted)
+    }
+}
+
+impl DateExtractor for VideoExtractor {
+    fn name(&self) -> &str {
+        "video"
+    }
+
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        if !is_video(path) {
+            return Err("not a video".to_string());
+        }
+        match self.backend {
+            VideoBackend::Native => self.extract_native(path),
+            VideoBackend::Ffprobe => self.extract_ffprobe(path),
+            VideoBackend::Auto => self.extract_native(path).or_else(|native| {
+                self.extract_ffprobe(path)
+                    .map_err(|ffprobe| format!("{} {}", native, ffprobe))
+            }),
+        }
+    }
+}
+
+impl VideoExtractor {
+    fn extract_native(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let metadata = read_video_metadata(path)?;
+        let (date_time, offset) = metadata
+            .capture_time()
+            .ok_or("No creation date in the video metadata.".to_string())?;
+        let mut extracted = ExtractedDate::new(
+            self.name(),
+            date_time,
+            DatePrecision::Second,
+            Confidence::High,
+        );
+        extracted.capture_info.camera = metadata.camera();
+        extracted.capture_info.offset = offset;
+        Ok(extracted)
+    }
+
+    fn extract_ffprobe(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let date_time = ffprobe::ffprobe(path)
+            .map_err(|e| format!("ffprobe failed: {}", e))?
+            .format
+            .tags
+            .and_then(|tag| tag.creation_time)
+            .ok_or("Can't read video creation date time.".to_string())?;
+        let date_time = DateTime::parse_from_rfc3339(&date_time).map_err(|e| e.to_string())?;
+        let mut extracted = ExtractedDate::new(
+            self.name(),
+            date_time.naive_local(),
+            DatePrecision::Second,
+            Confidence::High,
+        );
+        extracted.capture_info.offset = Some(*date_time.offset());
+        Ok(extracted)
+    }
+}
+
+impl DateExtractor for FilenameExtractor {
+    fn name(&self) -> &str {
+        "filename"
+    }
+
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let file_name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .ok_or("invalid file name")?;
+        let found = self
+            .patterns
+            .iter()
+            .find_map(|pattern| pattern.find(file_name, &self.years))
+            .ok_or_else(|| "no date in the file name".to_string())?;
+        let mut extracted = ExtractedDate::new(
+            self.name(),
+            found.date_time,
+            found.precision,
+            Confidence::Medium,
+        );
+        if found.utc {
+            extracted.capture_info.offset = FixedOffset::east_opt(0);
+        }
+        Ok(extracted)
+    }
+}
+
+impl DateExtractor for MtimeExtractor {
+    fn name(&self) -> &str {
+        "mtime"
+    }
+
+    fn extract(&self, path: &Path) -> Result<ExtractedDate, String> {
+        let date_time = path
+            .metadata()
+            .and_then(|m| m.modified().or(m.created()))
+            .map_err(|e| e.to_string())?
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|duration| DateTime::from_timestamp(duration.as_secs() as i64, 0))
+            .map(|date_time| date_time.naive_utc())
+            .ok_or("invalid modification time")?;
+        let mut extracted = ExtractedDate::new(
+            self.name(),
+            date_time,
+            DatePrecision::Second,
+            Confidence::Low,
+        );
+        extracted.capture_info.offset = FixedOffset::east_opt(0);
+        Ok(extracted)
+    }
+}
+
+/// Parses ISO 8601 dates as used by XMP and video tags, from a year up to fractional seconds with
+/// UTC offset, e.g. `2021`, `2021-03-14T12:00` or `2021-03-14T12:00:00.25+01:00`.
+pub(crate) fn parse_iso8601(
+    value: &str,
+) -> Option<(NaiveDateTime, Option<FixedOffset>, DatePrecision)> {
+    static ISO_REGEX: OnceLock<Regex> = OnceLock::new();
+    let iso_regex = ISO_REGEX.get_or_init(|| {
+        Regex::new(
+            r"^(?P<y>\d{4})(?:-(?P<m>\d{2})(?:-(?P<d>\d{2})(?:[T ](?P<h>\d{2}):(?P<min>\d{2})(?::(?P<s>\d{2})(?:\.\d+)?)?)?)?)?\s*(?P<offset>Z|[+-]\d{2}(?::?\d{2})?)?$",
+        )
+        .unwrap()
+    });
+    let captures = iso_regex.captures(value.trim())?;
+    let number = |name: &str| {
+        captures
+            .name(name)
+            .map(|m| m.as_str().parse::<u32>().unwrap())
+    };
+    let precision = match (number("m"), number("d"), number("h")) {
+        (None, _, _) => DatePrecision::Year,
+        (Some(_), None, _) => DatePrecision::Month,
+        (Some(_), Some(_), None) => DatePrecision::Day,
+        _ => DatePrecision::Second,
+    };
+    let date_time = NaiveDate::from_ymd_opt(
+        number("y")? as i32,
+        number("m").unwrap_or(1),
+        number("d").unwrap_or(1),
+    )?
+    .and_hms_opt(
+        number("h").unwrap_or(0),
+        number("min").unwrap_or(0),
+        number("s").unwrap_or(0),
+    )?;
+    let offset = match captures.name("offset") {
+        Some(offset) => Some(parse_offset(offset.as_str())?),
+        None => None,
+    };
+    Some((date_time, offset, precision))
+}
+
+pub fn read_exif(path: &Path) -> Result<exif::Exif, String> {
+    if is_heif(path) {
+        return read_heif_exif(path);
+    }
+    if is_raw(path) {
+        return read_raw_exif(path);
+    }
+    let exifreader = exif::Reader::new();
+    File::open(path)
+        .map_err(|e| e.to_string())
+        .map(BufReader::new)
+        .and_then(|mut inner| {
+            exifreader
+                .read_from_container(&mut inner)
+                .map_err(|e| e.to_string())
+        })
+}
+
+pub(crate) fn exif_text(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    exif.get_field(tag, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            exif::Value::Ascii(values) => values.first(),
+            _ => None,
+        })
+        .map(|value| String::from_utf8_lossy(value).trim().to_string())
+        .filter(|value| !value.is_empty())
+}
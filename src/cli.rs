@@ -0,0 +1,339 @@
+use clap::{Args, Parser, Subcommand};
+use image_sorter::{
+    CaptureZone, CollisionSuffix, CompanionGroup, CompareMode, ConfigValues, DateSource,
+    FileConflictResolutionMode, FilenamePattern, Mode, VideoBackend, YearRange,
+};
+use std::path::PathBuf;
+
+/// Sorts images and videos into folders by the date they were captured.
+#[derive(Parser)]
+#[command(name = "image-sorter", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Without a command the arguments of `sort` are expected.
+    #[command(flatten)]
+    pub sort: SortArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Sort the media files of a source folder into the target folder (default)
+    Sort(Box<SortArgs>),
+    /// List the media files of a folder with their capture dates without changing anything
+    Scan(ScanArgs),
+    /// Check that the files recorded in the journal are still unchanged at their destination
+    Verify(VerifyArgs),
+    /// Reverse a run with the help of the journal
+    Undo(UndoArgs),
+    /// Show how many files the target folder holds
+    Stats(StatsArgs),
+    /// Show the metadata that is read from files
+    Inspect(InspectArgs),
+}
+
+#[derive(Args)]
+pub struct SortArgs {
+    /// Folder with the files to sort [default: .]
+    pub source: Option<PathBuf>,
+    /// Folder the files are sorted into
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Print every step
+    #[arg(short, long, overrides_with = "no_verbose")]
+    pub verbose: bool,
+    /// Only print the summary, even if the config file enables verbose output
+    #[arg(long, overrides_with = "verbose")]
+    pub no_verbose: bool,
+    /// Only print what would be done
+    #[arg(short, long, conflicts_with_all = ["copy", "move_files"])]
+    pub dry_run: bool,
+    /// Copy the files instead of moving them
+    #[arg(short, long, conflicts_with = "move_files")]
+    pub copy: bool,
+    /// Move the files (default)
+    #[arg(short = 'm', long = "move")]
+    pub move_files: bool,
+    /// What to do if a file with the same name exists at the target [default: choose]
+    #[arg(short = 'k', long, value_enum)]
+    pub conflict_mode: Option<FileConflictResolutionMode>,
+    /// How files are compared to detect duplicates [default: size, hash when deleting duplicates]
+    #[arg(short = 'e', long, value_enum)]
+    pub compare: Option<CompareMode>,
+    /// Index the target folder to find duplicates under any name
+    #[arg(short, long, overrides_with = "no_index_target")]
+    pub index_target: bool,
+    /// Only find duplicates with the same name
+    #[arg(long, overrides_with = "index_target")]
+    pub no_index_target: bool,
+    /// Detect similar images up to this perceptual hash distance
+    #[arg(short = 'n', long, value_name = "DISTANCE", value_parser = clap::value_parser!(u32).range(0..=64))]
+    pub similar: Option<u32>,
+    /// What to do if a similar image exists at the target [default: choose]
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub similar_mode: Option<FileConflictResolutionMode>,
+    /// Folders below the target folder, e.g. "{year}/{month:02}" [default: {year}/{month}]
+    #[arg(short, long, value_name = "TEMPLATE")]
+    pub path_template: Option<String>,
+    /// New file name, e.g. "{date:%Y%m%d_%H%M%S}_{camera}.{ext}"
+    #[arg(short, long, value_name = "TEMPLATE")]
+    pub rename: Option<String>,
+    /// Suffix of renamed files when both files are kept [default: number]
+    #[arg(short = 'x', long, value_enum)]
+    pub collision_suffix: Option<CollisionSuffix>,
+    /// Where capture dates are read from, the first source with a date wins
+    /// [default: exif,xmp,takeout,video,filename,mtime]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub date_sources: Option<Vec<DateSource>>,
+    /// How the dates of videos are read [default: native]
+    #[arg(long, value_enum)]
+    pub video_backend: Option<VideoBackend>,
+    /// Time zone capture dates are converted to, e.g. "Europe/Berlin" or "+02:00" [default: local]
+    #[arg(long, value_name = "ZONE")]
+    pub timezone: Option<CaptureZone>,
+    /// Pattern of file names with a date, tried before the built-in ones, e.g.
+    /// "scan=^scan_(?P<year>\d{4})-(?P<month>\d{2})". Can be given more than once
+    #[arg(long = "filename-pattern", value_name = "NAME=REGEX")]
+    pub filename_patterns: Vec<FilenamePattern>,
+    /// Years dates in file names may have, e.g. "1950-2030", or "1950-" for dates up to today
+    /// [default: 1900-]
+    #[arg(long, value_name = "RANGE")]
+    pub filename_years: Option<YearRange>,
+    /// Files that follow their media file, e.g. "mp4,mov:thm,lrv" or "*:xmp" for all media
+    /// files. Can be given more than once [default: *:xmp,aae,json mp4,mov,avi,mts:thm,lrv]
+    #[arg(long = "companions", value_name = "PRIMARIES:COMPANIONS")]
+    pub companion_groups: Vec<CompanionGroup>,
+    /// Sort sidecars and other companion files on their own
+    #[arg(long, conflicts_with = "companion_groups")]
+    pub no_companions: bool,
+    /// Sort the RAW and the JPEG file of RAW+JPEG pairs as one unit (default)
+    #[arg(long, overrides_with = "no_raw_pairs")]
+    pub raw_pairs: bool,
+    /// Sort the RAW and the JPEG file of RAW+JPEG pairs on their own
+    #[arg(
+        long,
+        overrides_with = "raw_pairs",
+        conflicts_with_all = ["raw_pair_folder", "jpeg_pair_folder"]
+    )]
+    pub no_raw_pairs: bool,
+    /// Folder below the path template folder the RAW files of RAW+JPEG pairs go to, e.g. "raw"
+    #[arg(long, value_name = "FOLDER")]
+    pub raw_pair_folder: Option<PathBuf>,
+    /// Folder below the path template folder the JPEG files of RAW+JPEG pairs go to, e.g. "jpeg"
+    #[arg(long, value_name = "FOLDER")]
+    pub jpeg_pair_folder: Option<PathBuf>,
+    /// Sort the image and the video of Live Photos as one unit (default)
+    #[arg(long, overrides_with = "no_live_photos")]
+    pub live_photos: bool,
+    /// Sort the image and the video of Live Photos on their own
+    #[arg(
+        long,
+        overrides_with = "live_photos",
+        conflicts_with = "live_photo_folder"
+    )]
+    pub no_live_photos: bool,
+    /// Folder below the path template folder the videos of Live Photos go to, e.g. "live"
+    #[arg(long, value_name = "FOLDER")]
+    pub live_photo_folder: Option<PathBuf>,
+    /// Use the file modification time if a file has no capture date instead of asking
+    #[arg(short = 's', long, overrides_with = "no_file_creation_fallback")]
+    pub file_creation_fallback: bool,
+    /// Ask for the date of files without capture date
+    #[arg(long, overrides_with = "file_creation_fallback")]
+    pub no_file_creation_fallback: bool,
+    /// Delete source files that are skipped because they already exist at the target
+    #[arg(
+        short = 'q',
+        long,
+        overrides_with = "no_delete_skipped_source_duplicates"
+    )]
+    pub delete_skipped_source_duplicates: bool,
+    /// Keep source files that are skipped because they already exist at the target
+    #[arg(long, overrides_with = "delete_skipped_source_duplicates")]
+    pub no_delete_skipped_source_duplicates: bool,
+    /// Also sort files that are neither images nor videos
+    #[arg(
+        short = 'u',
+        long,
+        overrides_with = "no_include_unsupported_file_types"
+    )]
+    pub include_unsupported_file_types: bool,
+    /// Only sort images and videos
+    #[arg(long, overrides_with = "include_unsupported_file_types")]
+    pub no_include_unsupported_file_types: bool,
+    /// Config file [default: $XDG_CONFIG_HOME/image-sorter/config.toml]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Profile of the config file to use
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Folder with the files to scan
+    #[arg(default_value = ".")]
+    pub source: PathBuf,
+    /// Where capture dates are read from, the first source with a date wins
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "exif,xmp,takeout,video,filename,mtime"
+    )]
+    pub date_sources: Vec<DateSource>,
+    /// How the dates of videos are read
+    #[arg(long, value_enum, default_value = "native")]
+    pub video_backend: VideoBackend,
+    /// Time zone capture dates are converted to, e.g. "Europe/Berlin" or "+02:00"
+    #[arg(long, value_name = "ZONE", default_value = "local")]
+    pub timezone: CaptureZone,
+    /// Pattern of file names with a date, tried before the built-in ones. Can be given more than
+    /// once
+    #[arg(long = "filename-pattern", value_name = "NAME=REGEX")]
+    pub filename_patterns: Vec<FilenamePattern>,
+    /// Years dates in file names may have, e.g. "1950-2030", or "1950-" for dates up to today
+    #[arg(long, value_name = "RANGE", default_value = "1900-")]
+    pub filename_years: YearRange,
+    /// Also list files that are neither images nor videos
+    #[arg(short = 'u', long)]
+    pub include_unsupported_file_types: bool,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Only verify this run
+    pub run_id: Option<String>,
+    #[arg(short, long)]
+    pub target: PathBuf,
+    /// Print every step
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+    pub run_id: String,
+    #[arg(short, long)]
+    pub target: PathBuf,
+    /// Print every step
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Only print what would be done
+    #[arg(short, long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    #[arg(short, long)]
+    pub target: PathBuf,
+}
+
+#[derive(Args)]
+pub struct InspectArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Print all EXIF fields, text chunks, XMP properties and video tags
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+impl SortArgs {
+    /// The values given on the command line. Flags that are not set are left to the config file.
+    pub fn config_values(&self) -> ConfigValues {
+        let mode = if self.dry_run {
+            Some(Mode::DryRun)
+        } else if self.copy {
+            Some(Mode::Copy)
+        } else if self.move_files {
+            Some(Mode::Move)
+        } else {
+            None
+        };
+        ConfigValues {
+            verbose: flag(self.verbose, self.no_verbose),
+            mode,
+            source: self.source.clone(),
+            target: self.target.clone(),
+            include_unsupported_file_types: flag(
+                self.include_unsupported_file_types,
+                self.no_include_unsupported_file_types,
+            ),
+            conflict_mode: self.conflict_mode,
+            file_creation_fallback: flag(
+                self.file_creation_fallback,
+                self.no_file_creation_fallback,
+            ),
+            delete_skipped_source_duplicates: flag(
+                self.delete_skipped_source_duplicates,
+                self.no_delete_skipped_source_duplicates,
+            ),
+            compare: self.compare,
+            index_target: flag(self.index_target, self.no_index_target),
+            similar: self.similar,
+            similar_mode: self.similar_mode,
+            path_template: self.path_template.clone(),
+            rename: self.rename.clone(),
+            collision_suffix: self.collision_suffix,
+            date_sources: self.date_sources.clone(),
+            video_backend: self.video_backend,
+            timezone: self.timezone,
+            filename_patterns: (!self.filename_patterns.is_empty())
+                .then(|| self.filename_patterns.clone()),
+            filename_years: self.filename_years,
+            companions: if self.no_companions {
+                Some(Vec::new())
+            } else {
+                (!self.companion_groups.is_empty()).then(|| self.companion_groups.clone())
+            },
+            raw_pairs: flag(self.raw_pairs, self.no_raw_pairs),
+            raw_pair_folder: self.raw_pair_folder.clone(),
+            jpeg_pair_folder: self.jpeg_pair_folder.clone(),
+            live_photos: flag(self.live_photos, self.no_live_photos),
+            live_photo_folder: self.live_photo_folder.clone(),
+        }
+    }
+}
+
+/// The value of a flag and its `--no-` counterpart, `None` if neither was given so the config
+/// file decides. Of both flags only the last one given is set.
+fn flag(set: bool, unset: bool) -> Option<bool> {
+    match (set, unset) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_the_config_file_with_the_command_line() {
+        let cli = Cli::try_parse_from(["image-sorter", "--copy", "--no-verbose", "-p", "{year}"])
+            .unwrap();
+        let values = cli.sort.config_values().or(ConfigValues {
+            verbose: Some(true),
+            mode: Some(Mode::Move),
+            path_template: Some("{year}/{month}".to_string()),
+            rename: Some("{date:%Y%m%d}.{ext}".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(values.verbose, Some(false));
+        assert_eq!(values.mode, Some(Mode::Copy));
+        assert_eq!(values.path_template.as_deref(), Some("{year}"));
+        assert_eq!(values.rename.as_deref(), Some("{date:%Y%m%d}.{ext}"));
+    }
+
+    #[test]
+    fn leaves_flags_that_are_not_given_to_the_config_file() {
+        let cli = Cli::try_parse_from(["image-sorter", "--raw-pairs", "--no-raw-pairs"]).unwrap();
+        let values = cli.sort.config_values();
+        assert_eq!(values.raw_pairs, Some(false));
+        assert_eq!(values.verbose, None);
+        assert_eq!(values.mode, None);
+        assert_eq!(values.companions, None);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::raw::{is_raw, RAW_EXTENSIONS};
+use crate::takeout::{find_takeout_json, takeout_media};
+use crate::{is_image, is_supported_file_type, media_extensions, IMAGE_EXTENSIONS};
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Extensions of companion files and of the media files they belong to, written as
+/// `PRIMARIES:COMPANIONS`, e.g. `mp4,mov:thm,lrv`, or `*:xmp` for all media files.
+///
+/// Companions share the name of their media file, like `IMG_1234.xmp` or `IMG_1234.JPG.xmp`, and
+/// are moved, copied and renamed together with it. JSON companions may also be the metadata of a
+/// Google Takeout archive.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CompanionGroup {
+    /// Lowercase extensions of the media files, `None` for all of them.
+    primaries: Option<Vec<String>>,
+    /// Lowercase extensions of the companion files.
+    companions: Vec<String>,
+}
+
+impl CompanionGroup {
+    fn accepts(&self, primary: &Path) -> bool {
+        match &self.primaries {
+            Some(primaries) => {
+                lowercase_extension(primary).is_some_and(|extension| primaries.contains(&extension))
+            }
+            None => is_supported_file_type(primary),
+        }
+    }
+
+    /// Extensions of the media files, all known ones for `*`.
+    fn primary_extensions(&self) -> Vec<&str> {
+        match &self.primaries {
+            Some(primaries) => primaries.iter().map(String::as_str).collect(),
+            None => media_extensions().collect(),
+        }
+    }
+}
+
+impl FromStr for CompanionGroup {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<CompanionGroup, String> {
+        let invalid = || {
+            format!(
+                "Invalid companion group {:?}, use PRIMARIES:COMPANIONS like mp4,mov:thm or *:xmp.",
+                value
+            )
+        };
+        let extensions = |list: &str| -> Vec<String> {
+            list.split(',')
+                .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                .filter(|extension| !extension.is_empty())
+                .collect()
+        };
+        let (primaries, companions) = value.split_once(':').ok_or_else(invalid)?;
+        let primaries = match primaries.trim() {
+            "*" => None,
+            primaries => Some(extensions(primaries)),
+        };
+        let companions = extensions(companions);
+        if companions.is_empty() || primaries.as_ref().is_some_and(Vec::is_empty) {
+            return Err(invalid());
+        }
+        Ok(CompanionGroup {
+            primaries,
+            companions,
+        })
+    }
+}
+
+impl TryFrom<String> for CompanionGroup {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<CompanionGroup, String> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for CompanionGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.primaries {
+            Some(primaries) => write!(f, "{}", primaries.join(","))?,
+            None => write!(f, "*")?,
+        }
+        write!(f, ":{}", self.companions.join(","))
+    }
+}
+
+/// Sidecars of editors and Google Takeout for all media files, and the thumbnails of cameras and
+/// the low resolution copies of GoPros for videos. `AAE` files hold the edits of Apple Photos.
+pub fn default_companion_groups() -> &'static [CompanionGroup] {
+    static GROUPS: OnceLock<Vec<CompanionGroup>> = OnceLock::new();
+    GROUPS.get_or_init(|| {
+        ["*:xmp,aae,json", "mp4,mov,avi,mts:thm,lrv"]
+            .iter()
+            .map(|group| group.parse().unwrap())
+            .collect()
+    })
+}
+
+/// The existing companion files of `primary`.
+pub(crate) fn find_companions(primary: &Path, groups: &[CompanionGroup]) -> Vec<PathBuf> {
+    let Some(file_name) = primary.file_name().and_then(OsStr::to_str) else {
+        return Vec::new();
+    };
+    let mut companions = Vec::new();
+    for group in groups.iter().filter(|group| group.accepts(primary)) {
+        for extension in &group.companions {
+            let mut candidates = Vec::new();
+            if extension == "json" {
+                // Edited copies share the JSON file of the original, which keeps it.
+                candidates.extend(
+                    find_takeout_json(primary)
+                        .filter(|json| takeout_media(json).as_deref() == Some(primary)),
+                );
+            }
+            for extension in [extension.clone(), extension.to_uppercase()] {
+                candidates.push(primary.with_file_name(format!("{}.{}", file_name, extension)));
+                candidates.push(primary.with_extension(extension));
+            }
+            for candidate in candidates {
+                if candidate.is_file() && !companions.contains(&candidate) {
+                    companions.push(candidate);
+                }
+            }
+        }
+    }
+    companions
+}
+
+/// The existing media file `path` is a companion of, if any.
+pub(crate) fn find_primary(path: &Path, groups: &[CompanionGroup]) -> Option<PathBuf> {
+    let extension = lowercase_extension(path)?;
+    for group in groups
+        .iter()
+        .filter(|group| group.companions.contains(&extension))
+    {
+        let mut candidates = Vec::new();
+        if extension == "json" {
+            candidates.extend(takeout_media(path));
+        }
+        // `IMG_1234.JPG.xmp`
+        candidates.push(path.with_extension(""));
+        // `IMG_1234.xmp`
+        for primary_extension in group.primary_extensions() {
+            candidates.push(path.with_extension(primary_extension));
+            candidates.push(path.with_extension(primary_extension.to_uppercase()));
+        }
+        let primary = candidates.into_iter().find(|candidate| {
+            group.accepts(candidate)
+                && candidate.is_file()
+                && find_companions(candidate, groups).contains(&path.to_path_buf())
+        });
+        if primary.is_some() {
+            return primary;
+        }
+    }
+    None
+}
+
+/// The other file of a RAW+JPEG pair, a RAW file and an image sharing the stem, if it exists.
+pub(crate) fn find_pair_partner(path: &Path) -> Option<PathBuf> {
+    let extensions = if is_raw(path) {
+        IMAGE_EXTENSIONS.as_slice()
+    } else if is_image(path) {
+        RAW_EXTENSIONS.as_slice()
+    } else {
+        return None;
+    };
+    extensions
+        .iter()
+        .flat_map(|extension| [extension.to_string(), extension.to_uppercase()])
+        .map(|extension| path.with_extension(extension))
+        .find(|partner| partner.is_file())
+}
+
+/// Where `companion` goes when its primary file is sorted from `primary_source` to
+/// `primary_target`, keeping the part of its name after the name or the stem of the primary file,
+/// e.g. `IMG_1234.xmp` becomes `2020-01-01_120000.xmp` when the primary is renamed.
+pub(crate) fn companion_target(
+    companion: &Path,
+    primary_source: &Path,
+    primary_target: &Path,
+) -> PathBuf {
+    let name = |path: &Path| {
+        path.file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let stem = |path: &Path| {
+        path.file_stem()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let companion_name = name(companion);
+    let target_name = if let Some(suffix) = companion_name.strip_prefix(&name(primary_source)) {
+        format!("{}{}", name(primary_target), suffix)
+    } else if let Some(suffix) = companion_name.strip_prefix(&stem(primary_source)) {
+        format!("{}{}", stem(primary_target), suffix)
+    } else {
+        // Truncated Takeout names, e.g. `IMG_1234.jpg(1).json` of `IMG_1234(1).jpg`.
+        let extension = companion
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        format!("{}.{}", name(primary_target), extension)
+    };
+    primary_target.with_file_name(target_name)
+}
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A folder with empty files of the given names.
+    fn folder(test: &str, files: &[&str]) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("companion_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        for file in files {
+            fs::write(folder.join(file), b"").unwrap();
+        }
+        folder
+    }
+
+    #[test]
+    fn finds_primaries_of_companions() {
+        let folder = folder(
+            "primaries",
+            &[
+                "IMG_0001.JPG",
+                "IMG_0001.XMP",
+                "IMG_0002.jpg",
+                "IMG_0002.jpg.xmp",
+                "IMG_0003.HEIC",
+                "IMG_0003.HEIC.XMP",
+                "IMG_0004.jpg",
+                "IMG_0004.thm",
+                "clip.MP4",
+                "clip.THM",
+                "orphan.xmp",
+            ],
+        );
+        let groups = default_companion_groups();
+        let primary = |companion: &str| find_primary(&folder.join(companion), groups);
+        assert_eq!(primary("IMG_0001.XMP"), Some(folder.join("IMG_0001.JPG")));
+        assert_eq!(
+            primary("IMG_0002.jpg.xmp"),
+            Some(folder.join("IMG_0002.jpg"))
+        );
+        assert_eq!(
+            primary("IMG_0003.HEIC.XMP"),
+            Some(folder.join("IMG_0003.HEIC"))
+        );
+        assert_eq!(primary("clip.THM"), Some(folder.join("clip.MP4")));
+        // Thumbnails only belong to videos.
+        assert_eq!(primary("IMG_0004.thm"), None);
+        assert_eq!(primary("orphan.xmp"), None);
+        assert_eq!(primary("IMG_0001.JPG"), None);
+        let groups = ["jpg:txt".parse().unwrap()];
+        assert_eq!(find_primary(&folder.join("IMG_0001.XMP"), &groups), None);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn finds_companions_of_primaries() {
+        let folder = folder(
+            "companions",
+            &["clip.MP4", "clip.MP4.xmp", "clip.THM", "clip.lrv"],
+        );
+        assert_eq!(
+            find_companions(&folder.join("clip.MP4"), default_companion_groups()),
+            ["clip.MP4.xmp", "clip.THM", "clip.lrv"].map(|name| folder.join(name))
+        );
+        assert!(find_companions(&folder.join("clip.MP4"), &[]).is_empty());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_names_of_companions_next_to_their_primary() {
+        let target = |companion: &str, source: &str, target: &str| {
+            companion_target(
+                &Path::new("/in").join(companion),
+                &Path::new("/in").join(source),
+                &Path::new("/out").join(target),
+            )
+        };
+        assert_eq!(
+            target("IMG_0001.JPG.xmp", "IMG_0001.JPG", "IMG_0001.JPG"),
+            Path::new("/out/IMG_0001.JPG.xmp")
+        );
+        // Renamed by the template or a collision suffix, keeping the case of the extension.
+        assert_eq!(
+            target("IMG_0001.XMP", "IMG_0001.JPG", "20200101_120000.jpg"),
+            Path::new("/out/20200101_120000.XMP")
+        );
+        assert_eq!(
+            target("IMG_0001.JPG.xmp", "IMG_0001.JPG", "20200101_120000.jpg"),
+            Path::new("/out/20200101_120000.jpg.xmp")
+        );
+        assert_eq!(
+            target("IMG_0001.xmp", "IMG_0001.JPG", "IMG_0001_1.JPG"),
+            Path::new("/out/IMG_0001_1.xmp")
+        );
+        // Takeout moves the counter of duplicates behind the extension.
+        assert_eq!(
+            target("IMG_0001.jpg(1).json", "IMG_0001(1).jpg", "IMG_0001(1).jpg"),
+            Path::new("/out/IMG_0001(1).jpg.json")
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareMode {
+    /// Files of the same size are considered identical
+    Size,
+    /// Compare the blake3 hashes of the contents
+    Hash,
+    /// Compare the contents byte by byte
+    Bytes,
+}
+
+pub fn files_are_identical(source: &Path, target: &Path, mode: &CompareMode) -> io::Result<bool> {
+    if source.metadata()?.len() != target.metadata()?.len() {
+        return Ok(false);
+    }
+    match mode {
+        CompareMode::Size => Ok(true),
+        CompareMode::Hash => Ok(hash_file(source)? == hash_file(target)?),
+        CompareMode::Bytes => compare_bytes(source, target),
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+fn compare_bytes(source: &Path, target: &Path) -> io::Result<bool> {
+    let mut source_reader = BufReader::new(File::open(source)?);
+    let mut target_reader = BufReader::new(File::open(target)?);
+    loop {
+        let source_buf = source_reader.fill_buf()?;
+        let target_buf = target_reader.fill_buf()?;
+        if source_buf.is_empty() || target_buf.is_empty() {
+            return Ok(source_buf.is_empty() && target_buf.is_empty());
+        }
+        let len = source_buf.len().min(target_buf.len());
+        if source_buf[..len] != target_buf[..len] {
+            return Ok(false);
+        }
+        source_reader.consume(len);
+        target_reader.consume(len);
+    }
+}
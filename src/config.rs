@@ -0,0 +1,214 @@
+use crate::capture::DateSource;
+use crate::companion::CompanionGroup;
+use crate::compare::CompareMode;
+use crate::filename::{FilenamePattern, YearRange};
+use crate::options::{CollisionSuffix, FileConflictResolutionMode, Mode};
+use crate::timezone::CaptureZone;
+use crate::video::VideoBackend;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Values of the options as they are given in the config file or on the command line. Unset
+/// values fall back to the next source: command line, profile, top level of the config file.
+///
+/// ```toml
+/// target = "/media/library"
+/// compare = "hash"
+///
+/// [profiles.camera]
+/// path-template = "{year}/{month:02}"
+/// rename = "{date:%Y%m%d_%H%M%S}_{camera}.{ext}"
+/// filename-patterns = ['scan=^scan_(?P<year>\d{4})-(?P<month>\d{2})']
+/// filename-years = "1950-"
+/// companions = ["*:xmp", "mp4,mov:thm,lrv"]
+/// raw-pair-folder = "raw"
+/// live-photo-folder = "live"
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigValues {
+    pub verbose: Option<bool>,
+    pub mode: Option<Mode>,
+    pub source: Option<PathBuf>,
+    pub target: Option<PathBuf>,
+    pub include_unsupported_file_types: Option<bool>,
+    pub conflict_mode: Option<FileConflictResolutionMode>,
+    pub file_creation_fallback: Option<bool>,
+    pub delete_skipped_source_duplicates: Option<bool>,
+    pub compare: Option<CompareMode>,
+    pub index_target: Option<bool>,
+    pub similar: Option<u32>,
+    pub similar_mode: Option<FileConflictResolutionMode>,
+    pub path_template: Option<String>,
+    pub rename: Option<String>,
+    pub collision_suffix: Option<CollisionSuffix>,
+    pub date_sources: Option<Vec<DateSource>>,
+    pub video_backend: Option<VideoBackend>,
+    pub timezone: Option<CaptureZone>,
+    pub filename_patterns: Option<Vec<FilenamePattern>>,
+    pub filename_years: Option<YearRange>,
+    pub companions: Option<Vec<CompanionGroup>>,
+    pub raw_pairs: Option<bool>,
+    pub raw_pair_folder: Option<PathBuf>,
+    pub jpeg_pair_folder: Option<PathBuf>,
+    pub live_photos: Option<bool>,
+    pub live_photo_folder: Option<PathBuf>,
+}
+
+impl ConfigValues {
+    /// Takes every value that is not set in `self` from `fallback`.
+    pub fn or(self, fallback: ConfigValues) -> ConfigValues {
+        ConfigValues {
+            verbose: self.verbose.or(fallback.verbose),
+            mode: self.mode.or(fallback.mode),
+            source: self.source.or(fallback.source),
+            target: self.target.or(fallback.target),
+            include_unsupported_file_types: self
+                .include_unsupported_file_types
+                .or(fallback.include_unsupported_file_types),
+            conflict_mode: self.conflict_mode.or(fallback.conflict_mode),
+            file_creation_fallback: self
+                .file_creation_fallback
+                .or(fallback.file_creation_fallback),
+            delete_skipped_source_duplicates: self
+                .delete_skipped_source_duplicates
+                .or(fallback.delete_skipped_source_duplicates),
+            compare: self.compare.or(fallback.compare),
+            index_target: self.index_target.or(fallback.index_target),
+            similar: self.similar.or(fallback.similar),
+            similar_mode: self.similar_mode.or(fallback.similar_mode),
+            path_template: self.path_template.or(fallback.path_template),
+            rename: self.rename.or(fallback.rename),
+            collision_suffix: self.collision_suffix.or(fallback.collision_suffix),
+            date_sources: self.date_sources.or(fallback.date_sources),
+            video_backend: self.video_backend.or(fallback.video_backend),
+            timezone: self.timezone.or(fallback.timezone),
+            filename_patterns: self.filename_patterns.or(fallback.filename_patterns),
+            filename_years: self.filename_years.or(fallback.filename_years),
+            companions: self.companions.or(fallback.companions),
+            raw_pairs: self.raw_pairs.or(fallback.raw_pairs),
+            raw_pair_folder: self.raw_pair_folder.or(fallback.raw_pair_folder),
+            jpeg_pair_folder: self.jpeg_pair_folder.or(fallback.jpeg_pair_folder),
+            live_photos: self.live_photos.or(fallback.live_photos),
+            live_photo_folder: self.live_photo_folder.or(fallback.live_photo_folder),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/image-sorter/config.toml`, defaulting to `~/.config`.
+pub fn default_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config_home| config_home.join("image-sorter").join("config.toml"))
+}
+
+/// Loads the config file with the values of `profile` taking precedence over the top level ones.
+pub fn load_config(path: &Path, profile: Option<&str>) -> Result<ConfigValues, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Can't read config file {:?}: {}", path, e))?;
+    let mut table: toml::Table = content
+        .parse()
+        .map_err(|e| format!("Invalid config file {:?}: {}", path, e))?;
+
+    let mut profiles: BTreeMap<String, ConfigValues> = match table.remove("profiles") {
+        Some(profiles) => profiles
+            .try_into()
+            .map_err(|e| format!("Invalid profiles in config file {:?}: {}", path, e))?,
+        None => BTreeMap::new(),
+    };
+    let values: ConfigValues = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| format!("Invalid config file {:?}: {}", path, e))?;
+
+    match profile {
+        Some(name) => match profiles.remove(name) {
+            Some(profile_values) => Ok(profile_values.or(values)),
+            None => Err(format!(
+                "Profile {:?} not found in config file {:?}. Available profiles: {}",
+                name,
+                path,
+                profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            )),
+        },
+        None => Ok(values),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_file(test: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("config_{}_{}.toml", std::process::id(), test));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    const CONFIG: &str = r#"
+        target = "/media/library"
+        path-template = "{year}"
+        raw-pairs = true
+
+        [profiles.camera]
+        path-template = "{year}/{month:02}"
+        raw-pairs = false
+    "#;
+
+    #[test]
+    fn takes_the_values_of_the_profile_first() {
+        let path = config_file("profile", CONFIG);
+
+        let values = load_config(&path, Some("camera")).unwrap();
+        assert_eq!(values.path_template.as_deref(), Some("{year}/{month:02}"));
+        assert_eq!(values.raw_pairs, Some(false));
+        assert_eq!(values.target, Some(PathBuf::from("/media/library")));
+
+        let values = load_config(&path, None).unwrap();
+        assert_eq!(values.path_template.as_deref(), Some("{year}"));
+        assert_eq!(values.raw_pairs, Some(true));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn takes_unset_values_from_the_fallback() {
+        let values = ConfigValues {
+            path_template: Some("{year}".to_string()),
+            raw_pairs: Some(false),
+            ..Default::default()
+        }
+        .or(ConfigValues {
+            path_template: Some("{year}/{month}".to_string()),
+            raw_pairs: Some(true),
+            rename: Some("{date:%Y%m%d}.{ext}".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(values.path_template.as_deref(), Some("{year}"));
+        assert_eq!(values.raw_pairs, Some(false));
+        assert_eq!(values.rename.as_deref(), Some("{date:%Y%m%d}.{ext}"));
+        assert_eq!(values.target, None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_profiles() {
+        let path = config_file("unknown_profile", CONFIG);
+        let error = load_config(&path, Some("phone")).err().unwrap();
+        assert!(error.contains("Profile \"phone\" not found"), "{}", error);
+        assert!(error.ends_with("Available profiles: camera"), "{}", error);
+        fs::remove_file(path).unwrap();
+
+        let path = config_file("unknown_key", "path-templat = \"{year}\"");
+        let error = load_config(&path, None).err().unwrap();
+        assert!(error.contains("unknown field `path-templat`"), "{}", error);
+        fs::remove_file(path).unwrap();
+
+        let path = config_file("unknown_profile_key", "[profiles.camera]\nrenames = \"x\"");
+        let error = load_config(&path, Some("camera")).err().unwrap();
+        assert!(error.starts_with("Invalid profiles"), "{}", error);
+        assert!(error.contains("unknown field `renames`"), "{}", error);
+        fs::remove_file(path).unwrap();
+    }
+}
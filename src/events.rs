@@ -0,0 +1,165 @@
+use crate::capture::ExtractedDate;
+use crate::perceptual::SimilarImage;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Something that happened during a run, reported to [`SortHandler::event`].
+///
+/// Events of file system changes are reported in dry runs too, for what would have been done.
+#[derive(Debug, Clone)]
+pub enum SortEvent {
+    RunStarted {
+        run_id: String,
+    },
+    /// An interrupted run with the same options is continued.
+    RunResumed {
+        run_id: String,
+        done: usize,
+    },
+    TargetIndexed {
+        files: usize,
+    },
+    TargetImagesHashed {
+        images: usize,
+    },
+    /// An image of the target folder can't be compared to similar source images.
+    TargetImageNotHashed {
+        path: PathBuf,
+        error: String,
+    },
+    /// A supported source file is about to be sorted.
+    FileFound {
+        source: PathBuf,
+    },
+    CaptureDateFound {
+        source: PathBuf,
+        date: ExtractedDate,
+    },
+    Renamed {
+        source: PathBuf,
+        file_name: String,
+    },
+    /// A file with the same name already exists at the target.
+    NameCollision {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    SimilarImageFound {
+        source: PathBuf,
+        similar: SimilarImage,
+    },
+    FolderCreated {
+        path: PathBuf,
+    },
+    /// A skipped source file was deleted because its content already exists at the target.
+    SourceDeleted {
+        source: PathBuf,
+    },
+    /// A companion file like a sidecar, or the other file of a RAW+JPEG pair or a Live Photo,
+    /// followed its media file `primary` to `target`.
+    CompanionSorted {
+        source: PathBuf,
+        primary: PathBuf,
+        target: PathBuf,
+    },
+    /// A target file was deleted because it was replaced by a similar source image.
+    TargetDeleted {
+        target: PathBuf,
+    },
+    /// Handling the file is finished.
+    Handled(SortOutcome),
+    /// Something went wrong without affecting the file, e.g. the progress could not be stored.
+    Warning {
+        message: String,
+    },
+}
+
+/// What happened to a file of the source folder.
+#[derive(Debug, Clone)]
+pub enum SortOutcome {
+    /// Moved or copied to `target`, or would have been in a dry run.
+    Sorted {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Skipped {
+        source: PathBuf,
+        reason: SkipReason,
+    },
+    /// Neither an image nor a video, and unsupported file types are not included.
+    Unsupported {
+        source: PathBuf,
+    },
+    Failed {
+        source: PathBuf,
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum SkipReason {
+    /// Handled before the run was interrupted.
+    AlreadyHandled,
+    /// The same content already exists at the target.
+    Duplicate { existing: PathBuf },
+    /// The file at the target was kept instead, because of the conflict mode or a decision.
+    KeptTarget { target: PathBuf },
+    /// The JSON file of a Google Takeout archive, which is read as the metadata of `media`.
+    TakeoutMetadata { media: PathBuf },
+    /// A companion file like a sidecar, or the other file of a RAW+JPEG pair or a Live Photo,
+    /// which follows `primary` wherever it goes.
+    Companion { primary: PathBuf },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum ConflictResolution {
+    OverrideTarget,
+    SkipSource,
+    KeepBoth,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum SimilarImageResolution {
+    ReplaceTarget,
+    SkipSource,
+    KeepBoth,
+}
+
+/// Receives the events of a run and makes the decisions that need a user.
+///
+/// Decisions are only asked for with [`FileConflictResolutionMode::Choose`] and are stored in the
+/// progress of the run, so a resumed run does not ask again. The default implementations never
+/// change existing target files and leave files without capture date in place.
+///
+/// [`FileConflictResolutionMode::Choose`]: crate::FileConflictResolutionMode::Choose
+pub trait SortHandler {
+    fn event(&mut self, _event: SortEvent) {}
+
+    /// A different file with the same name exists at `target`. Keeping both moves the source file
+    /// to `alternative`.
+    fn resolve_conflict(
+        &mut self,
+        _source: &Path,
+        _target: &Path,
+        _alternative: &Path,
+    ) -> ConflictResolution {
+        ConflictResolution::SkipSource
+    }
+
+    fn resolve_similar_image(
+        &mut self,
+        _source: &Path,
+        _similar: &SimilarImage,
+    ) -> SimilarImageResolution {
+        SimilarImageResolution::SkipSource
+    }
+
+    /// The capture date of a file whose only date has [`Confidence::Low`], unless the file
+    /// creation fallback is enabled. `None` skips the file.
+    ///
+    /// [`Confidence::Low`]: crate::Confidence::Low
+    fn capture_date(&mut self, _source: &Path, _guess: &ExtractedDate) -> Option<NaiveDateTime> {
+        None
+    }
+}
@@ -0,0 +1,472 @@
+use crate::capture::DatePrecision;
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// A named regular expression finding the capture date in file names. The date is taken from the
+/// groups `year`, `month`, `day`, `hour`, `minute` and `second`, of which only `year` is required.
+///
+/// Patterns are written as `NAME=REGEX`, e.g.
+/// `scanner=^scan_(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct FilenamePattern {
+    name: String,
+    regex: Regex,
+    /// The time is in UTC instead of the local time of the camera.
+    utc: bool,
+}
+
+/// The years dates in file names may have, e.g. `1950-2030`. Without a last year, dates up to
+/// today are plausible, but none in the future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct YearRange {
+    pub first: i32,
+    pub last: Option<i32>,
+}
+
+impl Default for YearRange {
+    /// From 1900 up to today.
+    fn default() -> YearRange {
+        YearRange {
+            first: 1900,
+            last: None,
+        }
+    }
+}
+
+impl YearRange {
+    fn is_plausible(&self, date_time: &NaiveDateTime) -> bool {
+        if date_time.year() < self.first {
+            return false;
+        }
+        match self.last {
+            Some(last) => date_time.year() <= last,
+            // A day of tolerance for dates of time zones ahead of this one.
+            None => *date_time <= Local::now().naive_local() + Duration::days(1),
+        }
+    }
+}
+
+impl FromStr for YearRange {
+    type Err = String;
+
+    /// `FIRST-LAST` or `FIRST-` for dates up to today.
+    fn from_str(value: &str) -> Result<YearRange, String> {
+        let invalid = || {
+            format!(
+                "Invalid year range {:?}, use FIRST-LAST like 1950-2030 or FIRST- for dates up to today.",
+                value
+            )
+        };
+        let (first, last) = value.split_once('-').ok_or_else(invalid)?;
+        let first = first.trim().parse().map_err(|_| invalid())?;
+        let last = match last.trim() {
+            "" => None,
+            last => Some(last.parse().map_err(|_| invalid())?),
+        };
+        if last.is_some_and(|last| last < first) {
+            return Err(invalid());
+        }
+        Ok(YearRange { first, last })
+    }
+}
+
+impl TryFrom<String> for YearRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<YearRange, String> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for YearRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.last {
+            Some(last) => write!(f, "{}-{}", self.first, last),
+            None => write!(f, "{}-", self.first),
+        }
+    }
+}
+
+/// A date found in a file name.
+pub(crate) struct FilenameDate {
+    pub(crate) date_time: NaiveDateTime,
+    pub(crate) precision: DatePrecision,
+    pub(crate) utc: bool,
+}
+
+impl FilenamePattern {
+    pub fn new(name: &str, regex: &str) -> Result<FilenamePattern, String> {
+        let regex = Regex::new(regex)
+            .map_err(|e| format!("Invalid regex of filename pattern {:?}: {}", name, e))?;
+        if !regex.capture_names().any(|group| group == Some("year")) {
+            return Err(format!(
+                "Filename pattern {:?} has no group named year.",
+                name
+            ));
+        }
+        Ok(FilenamePattern {
+            name: name.to_string(),
+            regex,
+            utc: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The best match in `file_name` that is a plausible date. If there are several, the most
+    /// precise one wins, then the first one.
+    pub(crate) fn find(&self, file_name: &str, years: &YearRange) -> Option<FilenameDate> {
+        let names: Vec<Option<&str>> = self.regex.capture_names().collect();
+        let mut locations = self.regex.capture_locations();
+        let mut best: Option<(NaiveDateTime, DatePrecision)> = None;
+        let mut start = 0;
+        // Matches may overlap, as the patterns include the characters around the date.
+        while let Some(found) = self
+            .regex
+            .captures_read_at(&mut locations, file_name, start)
+        {
+            let group = |name: &str| {
+                let index = names.iter().position(|n| *n == Some(name))?;
+                let (start, end) = locations.get(index)?;
+                Some(&file_name[start..end])
+            };
+            let candidate = date_of(group).filter(|(date_time, _)| years.is_plausible(date_time));
+            if let Some(candidate) = candidate {
+                if best.is_none_or(|best| candidate.1 > best.1) {
+                    best = Some(candidate);
+                }
+            }
+            start = match file_name[found.start()..].chars().next() {
+                Some(c) => found.start() + c.len_utf8(),
+                None => break,
+            };
+        }
+        best.map(|(date_time, precision)| FilenameDate {
+            date_time,
+            precision,
+            utc: self.utc,
+        })
+    }
+}
+
+impl FromStr for FilenamePattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<FilenamePattern, String> {
+        let (name, regex) = value.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid filename pattern {:?}, use NAME=REGEX, e.g. scan=scan_(?P<year>\\d{{4}}).",
+                value
+            )
+        })?;
+        FilenamePattern::new(name.trim(), regex)
+    }
+}
+
+impl TryFrom<String> for FilenamePattern {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<FilenamePattern, String> {
+        value.parse()
+    }
+}
+
+/// The built-in patterns, most specific first. They are tried after the patterns of the user.
+pub fn default_filename_patterns() -> &'static [FilenamePattern] {
+    static PATTERNS: OnceLock<Vec<FilenamePattern>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        const TIME: &str = r"(?P<hour>\d{2})(?P<time_sep1>[-.:]?)(?P<minute>\d{2})(?P<time_sep2>[-.:]?)(?P<second>\d{2})";
+        const DATE: &str =
+            r"(?P<year>\d{4})(?P<date_sep1>-?)(?P<month>\d{2})(?P<date_sep2>-?)(?P<day>\d{2})";
+        let patterns = [
+            // Google Pixel, in UTC.
+            ("PXL", format!(r"PXL_{}_{}", DATE, TIME), true),
+            // Android cameras.
+            (
+                "IMG_YYYYMMDD_HHMMSS",
+                format!(r"(?:IMG|VID|MVIMG|PANO|BURST\d*)_{}_{}", DATE, TIME),
+                false,
+            ),
+            (
+                "WhatsApp",
+                format!(r"(?:IMG|VID|AUD|PTT)-{}-WA\d+", DATE),
+                false,
+            ),
+            (
+                "Screenshot",
+                format!(r"(?i:screenshot)[ _-]?{}(?:[ _-](?:at )?{})?", DATE, TIME),
+                false,
+            ),
+            ("signal", format!(r"signal-{}-{}", DATE, TIME), false),
+            ("DJI", format!(r"DJI_{}{}", DATE, TIME), false),
+            (
+                "date and time",
+                format!(r"(?:^|\D){}[ _T-]?{}(?:\D|$)", DATE, TIME),
+                false,
+            ),
+            ("date", format!(r"(?:^|\D){}(?:\D|$)", DATE), false),
+            // Scanned photos, e.g. 1987-06 Beach.jpg. Without the separator most numbers would
+            // match.
+            (
+                "year and month",
+                r"(?:^|\D)(?P<year>\d{4})[-_.](?P<month>\d{2})(?:\D|$)".to_string(),
+                false,
+            ),
+        ];
+        patterns
+            .into_iter()
+            .map(|(name, regex, utc)| FilenamePattern {
+                utc,
+                ..FilenamePattern::new(name, &regex).unwrap()
+            })
+            .collect()
+    })
+}
+
+/// The date of a match, from the numbers of its groups. The built-in patterns also capture the
+/// separators of the date and the time, which have to be the same within each, so that e.g.
+/// `2020-01-01_2020-02-02` is not read as 20:20:02.
+fn date_of<'a>(group: impl Fn(&str) -> Option<&'a str>) -> Option<(NaiveDateTime, DatePrecision)> {
+    if group("date_sep1") != group("date_sep2") || group("time_sep1") != group("time_sep2") {
+        return None;
+    }
+    let number = |name: &str| group(name).and_then(|text| text.parse::<u32>().ok());
+    let precision = match (number("month"), number("day"), number("hour")) {
+        (None, _, _) => DatePrecision::Year,
+        (Some(_), None, _) => DatePrecision::Month,
+        (Some(_), Some(_), None) => DatePrecision::Day,
+        _ => DatePrecision::Second,
+    };
+    let date_time = NaiveDate::from_ymd_opt(
+        number("year")? as i32,
+        number("month").unwrap_or(1),
+        number("day").unwrap_or(1),
+    )?
+    .and_hms_opt(
+        number("hour").unwrap_or(0),
+        number("minute").unwrap_or(0),
+        number("second").unwrap_or(0),
+    )?;
+    Some((date_time, precision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The date of the first built-in pattern that finds one, as the filename date source does.
+    fn find(file_name: &str) -> Option<(String, DatePrecision)> {
+        default_filename_patterns()
+            .iter()
+            .find_map(|pattern| pattern.find(file_name, &YearRange::default()))
+            .map(|date| (date.date_time.to_string(), date.precision))
+    }
+
+    #[test]
+    fn finds_dates_of_built_in_patterns() {
+        for (file_name, date_time, precision) in [
+            (
+                "PXL_20210314_120001123.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "IMG_20210314_120001.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "BURST20_20210314_120001.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "IMG-20210314-WA0001.jpg",
+                "2021-03-14 00:00:00",
+                DatePrecision::Day,
+            ),
+            (
+                "Screenshot_2021-03-14-12-00-01.png",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "Screenshot 2021-03-14 at 12.00.01.png",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "signal-2021-03-14-120001.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "DJI_20210314120001_0001.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "20210314_120001.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "2021-03-14 12.00.01.jpg",
+                "2021-03-14 12:00:01",
+                DatePrecision::Second,
+            ),
+            (
+                "holiday 2021-03-14.jpg",
+                "2021-03-14 00:00:00",
+                DatePrecision::Day,
+            ),
+        ] {
+            assert_eq!(
+                find(file_name),
+                Some((date_time.to_string(), precision)),
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn reads_pixel_dates_as_utc() {
+        let patterns = default_filename_patterns();
+        let date = patterns[0].find("PXL_20210314_120001123.jpg", &YearRange::default());
+        assert!(date.unwrap().utc);
+        let date = patterns[1].find("IMG_20210314_120001.jpg", &YearRange::default());
+        assert!(!date.unwrap().utc);
+    }
+
+    #[test]
+    fn skips_invalid_dates() {
+        assert_eq!(find("IMG_20211314_120001.jpg"), None);
+        assert_eq!(find("IMG_2021031.jpg"), None);
+        assert_eq!(find("DSC01234.jpg"), None);
+        // An invalid time leaves the date.
+        assert_eq!(
+            find("IMG_20210314_250001.jpg"),
+            Some(("2021-03-14 00:00:00".to_string(), DatePrecision::Day))
+        );
+    }
+
+    #[test]
+    fn finds_dates_of_custom_patterns() {
+        let pattern: FilenamePattern = r"scan=^scan_(?P<year>\d{4})-(?P<month>\d{2})"
+            .parse()
+            .unwrap();
+        assert_eq!(pattern.name(), "scan");
+        let date = pattern
+            .find("scan_1987-06.jpg", &YearRange::default())
+            .unwrap();
+        assert_eq!(date.date_time.to_string(), "1987-06-01 00:00:00");
+        assert_eq!(date.precision, DatePrecision::Month);
+        assert!(pattern
+            .find("scan_1987-13.jpg", &YearRange::default())
+            .is_none());
+        assert!(pattern
+            .find("old scan_1987-06.jpg", &YearRange::default())
+            .is_none());
+    }
+
+    #[test]
+    fn requires_matching_separators() {
+        assert_eq!(
+            find("2020-01-01_2020-02-02.jpg"),
+            Some(("2020-01-01 00:00:00".to_string(), DatePrecision::Day))
+        );
+        assert_eq!(
+            find("2020-01-01_2020-02-02_12-00-01.jpg"),
+            Some(("2020-02-02 12:00:01".to_string(), DatePrecision::Second))
+        );
+        assert_eq!(find("2020-0101.jpg"), None, "the date separators differ");
+    }
+
+    #[test]
+    fn finds_years_and_months_of_scans() {
+        assert_eq!(
+            find("1987-06 Beach.jpg"),
+            Some(("1987-06-01 00:00:00".to_string(), DatePrecision::Month))
+        );
+        assert_eq!(
+            find("Beach_1987.06.jpg"),
+            Some(("1987-06-01 00:00:00".to_string(), DatePrecision::Month))
+        );
+        assert_eq!(find("198706 Beach.jpg"), None);
+        assert_eq!(find("1987-13 Beach.jpg"), None);
+    }
+
+    #[test]
+    fn rejects_implausible_years() {
+        assert_eq!(find("IMG_20990101_120001.jpg"), None);
+        assert_eq!(find("IMG_18990101_120001.jpg"), None);
+        let tomorrow = Local::now().naive_local() + Duration::days(1);
+        assert!(find(&format!("{}.jpg", tomorrow.format("%Y-%m-%d"))).is_some());
+        let next_year = Local::now().naive_local() + Duration::days(400);
+        assert!(find(&format!("{}.jpg", next_year.format("%Y-%m-%d"))).is_none());
+
+        let years: YearRange = "1850-2100".parse().unwrap();
+        let date = default_filename_patterns()[1].find("IMG_20990101_120001.jpg", &years);
+        assert_eq!(date.unwrap().date_time.to_string(), "2099-01-01 12:00:01");
+        let date = default_filename_patterns()[1].find("IMG_18500101_120001.jpg", &years);
+        assert!(date.is_some());
+        let years: YearRange = "1990-2000".parse().unwrap();
+        let date = default_filename_patterns()[1].find("IMG_20000101_120001.jpg", &years);
+        assert!(date.is_some());
+        let date = default_filename_patterns()[1].find("IMG_20010101_120001.jpg", &years);
+        assert!(date.is_none());
+    }
+
+    #[test]
+    fn prefers_precise_then_first_dates() {
+        let pattern =
+            FilenamePattern::new("years", r"(?P<year>\d{4})(?:-(?P<month>\d{2}))?").unwrap();
+        let years = YearRange::default();
+        let date = pattern.find("1990 and 1991-05", &years).unwrap();
+        assert_eq!(date.date_time.to_string(), "1991-05-01 00:00:00");
+        assert_eq!(date.precision, DatePrecision::Month);
+        let date = pattern.find("1990 and 1991", &years).unwrap();
+        assert_eq!(date.date_time.to_string(), "1990-01-01 00:00:00");
+        // Implausible matches don't count, even if more precise.
+        let date = pattern.find("1990 and 2999-05", &years).unwrap();
+        assert_eq!(date.date_time.to_string(), "1990-01-01 00:00:00");
+    }
+
+    #[test]
+    fn parses_year_ranges() {
+        for (value, first, last) in [
+            ("1950-2030", 1950, Some(2030)),
+            ("1950-", 1950, None),
+            (" 1950 - 2030 ", 1950, Some(2030)),
+            ("2000-2000", 2000, Some(2000)),
+        ] {
+            let range: YearRange = value.parse().unwrap();
+            assert_eq!(range, YearRange { first, last }, "{}", value);
+            assert_eq!(range.to_string().parse::<YearRange>(), Ok(range));
+        }
+        for value in ["1950", "-2030", "abc-", "1950-abc", "2030-1950", ""] {
+            assert!(value.parse::<YearRange>().is_err(), "{}", value);
+        }
+        assert_eq!(YearRange::default().to_string(), "1900-");
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(r"scan_(?P<year>\d{4})".parse::<FilenamePattern>().is_err());
+        assert!(r"scan=scan_(?P<year>\d{4}"
+            .parse::<FilenamePattern>()
+            .is_err());
+        assert!(r"scan=scan_(?P<yyyy>\d{4})"
+            .parse::<FilenamePattern>()
+            .is_err());
+    }
+}
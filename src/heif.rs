@@ -0,0 +1,288 @@
+use crate::bmff::{atoms, read_top_level_box};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Caps the memory used for the `meta` box, which holds the item tables and small items but not
+/// the image data.
+const MAX_META_SIZE: u64 = 16 * 1024 * 1024;
+/// Caps the Exif item read from the file, cameras write well below 64 KiB.
+const MAX_EXIF_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Where the data of an item is stored, from the `iloc` box.
+struct ItemLocation {
+    /// 0 for offsets in the file, 1 for offsets in the `idat` box.
+    construction_method: u16,
+    extents: Vec<(u64, u64)>,
+}
+
+/// Reads big endian numbers of the sizes used in HEIF boxes.
+struct ByteReader<'a> {
+    data: &'a [u8],
+}
+
+pub(crate) fn is_heif(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .filter(|&e| ["heic", "heif", "hif", "avif"].contains(&e.to_lowercase().as_str()))
+        .is_some()
+}
+
+/// Reads the EXIF data of a HEIF file, which is stored as an item of the `meta` box.
+pub(crate) fn read_heif_exif(path: &Path) -> Result<exif::Exif, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    read_exif_item(&mut file)
+}
+
+fn read_exif_item<R: Read + Seek>(reader: &mut R) -> Result<exif::Exif, String> {
+    let meta = read_top_level_box(reader, b"meta", MAX_META_SIZE)
+        .map_err(|e| e.to_string())?
+        .ok_or("No meta box found.".to_string())?;
+    // Version and flags.
+    let children = meta.get(4..).unwrap_or_default();
+    let item_id = atoms(children)
+        .find(|atom| &atom.kind == b"iinf")
+        .and_then(|iinf| exif_item_id(iinf.data))
+        .ok_or("No Exif item found.".to_string())?;
+    let location = atoms(children)
+        .find(|atom| &atom.kind == b"iloc")
+        .and_then(|iloc| item_location(iloc.data, item_id))
+        .ok_or("The Exif item has no location.".to_string())?;
+
+    let mut data = Vec::new();
+    for (offset, length) in location.extents {
+        let size = (data.len() as u64).checked_add(length);
+        if size.is_none_or(|size| size > MAX_EXIF_SIZE) {
+            return Err("The Exif item is too large.".to_string());
+        }
+        match location.construction_method {
+            0 => {
+                reader
+                    .seek(SeekFrom::Start(offset))
+                    .map_err(|e| e.to_string())?;
+                let mut extent = vec![0; length as usize];
+                reader.read_exact(&mut extent).map_err(|e| e.to_string())?;
+                data.extend(extent);
+            }
+            1 => {
+                let extent = atoms(children)
+                    .find(|atom| &atom.kind == b"idat")
+                    .and_then(|idat| {
+                        let start = usize::try_from(offset).ok()?;
+                        idat.data.get(start..start.checked_add(length as usize)?)
+                    })
+                    .ok_or("The Exif item is outside of the idat box.".to_string())?;
+                data.extend_from_slice(extent);
+            }
+            _ => return Err("The Exif item is stored in an unsupported way.".to_string()),
+        }
+    }
+    // The item starts with the offset of the TIFF header, counted after that field.
+    let tiff_start = data
+        .get(0..4)
+        .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()) as usize + 4)
+        .filter(|&start| start < data.len())
+        .ok_or("The Exif item is empty.".to_string())?;
+    exif::Reader::new()
+        .read_raw(data.split_off(tiff_start))
+        .map_err(|e| e.to_string())
+}
+
+/// Width and height of the largest image of a HEIF file, which is the primary one unless it is
+/// split into tiles.
+pub(crate) fn heif_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    let meta = read_top_level_box(&mut file, b"meta", MAX_META_SIZE).ok()??;
+    let properties = atoms(meta.get(4..)?)
+        .find(|atom| &atom.kind == b"iprp")
+        .and_then(|iprp| atoms(iprp.data).find(|atom| &atom.kind == b"ipco"))?;
+    atoms(properties.data)
+        .filter(|atom| &atom.kind == b"ispe")
+        .filter_map(|ispe| {
+            let mut reader = ByteReader { data: ispe.data };
+            reader.skip(4)?;
+            Some((reader.uint(4)? as u32, reader.uint(4)? as u32))
+        })
+        .max_by_key(|(width, height)| *width as u64 * *height as u64)
+}
+
+fn exif_item_id(iinf: &[u8]) -> Option<u32> {
+    let mut reader = ByteReader { data: iinf };
+    let version = reader.uint(1)?;
+    reader.skip(3)?;
+    reader.skip(if version == 0 { 2 } else { 4 })?;
+    atoms(reader.data)
+        .filter(|atom| &atom.kind == b"infe")
+        .find_map(|infe| {
+            let mut reader = ByteReader { data: infe.data };
+            let version = reader.uint(1)?;
+            reader.skip(3)?;
+            // Older versions have no item type.
+            let item_id = match version {
+                2 => reader.uint(2)?,
+                3 => reader.uint(4)?,
+                _ => return None,
+            };
+            reader.skip(2)?;
+            (reader.bytes(4)? == b"Exif").then_some(item_id as u32)
+        })
+}
+
+fn item_location(iloc: &[u8], item_id: u32) -> Option<ItemLocation> {
+    let mut reader = ByteReader { data: iloc };
+    let version = reader.uint(1)?;
+    reader.skip(3)?;
+    let sizes = reader.uint(2)?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = (sizes >> 8 & 0xF) as usize;
+    let base_offset_size = (sizes >> 4 & 0xF) as usize;
+    let index_size = if version == 1 || version == 2 {
+        (sizes & 0xF) as usize
+    } else {
+        0
+    };
+    let id_size = if version < 2 { 2 } else { 4 };
+    let item_count = reader.uint(id_size)?;
+    for _ in 0..item_count {
+        let id = reader.uint(id_size)?;
+        let construction_method = if version == 1 || version == 2 {
+            (reader.uint(2)? & 0xF) as u16
+        } else {
+            0
+        };
+        // Data reference index.
+        reader.skip(2)?;
+        let base_offset = reader.uint(base_offset_size)?;
+        let extent_count = reader.uint(2)?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            reader.skip(index_size)?;
+            let offset = reader.uint(offset_size)?;
+            let length = reader.uint(length_size)?;
+            extents.push((base_offset.checked_add(offset)?, length));
+        }
+        if id == item_id as u64 {
+            return Some(ItemLocation {
+                construction_method,
+                extents,
+            });
+        }
+    }
+    None
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(..count)?;
+        self.data = &self.data[count..];
+        Some(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.bytes(count).map(|_| ())
+    }
+
+    /// A number of `size` bytes, which is 0 for a size of 0.
+    fn uint(&mut self, size: usize) -> Option<u64> {
+        if size > 8 {
+            return None;
+        }
+        Some(
+            self.bytes(size)?
+                .iter()
+                .fold(0, |value, &byte| value << 8 | byte as u64),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bmff::atom;
+    use std::io::Cursor;
+
+    fn iinf() -> Vec<u8> {
+        let infe = atom(b"infe", b"\x02\0\0\0\0\x01\0\0Exif\0");
+        let mut iinf = b"\0\0\0\0\0\x01".to_vec();
+        iinf.extend(infe);
+        iinf
+    }
+
+    /// An `iloc` box of version 1 with item 1 stored in `extents` of the `idat` box.
+    fn iloc(extents: &[(u64, u64)]) -> Vec<u8> {
+        let mut iloc = b"\x01\0\0\0\x88\0\0\x01\0\x01\0\x01\0\0".to_vec();
+        iloc.extend((extents.len() as u16).to_be_bytes());
+        for (offset, length) in extents {
+            iloc.extend(offset.to_be_bytes());
+            iloc.extend(length.to_be_bytes());
+        }
+        iloc
+    }
+
+    fn heif(extents: &[(u64, u64)]) -> Vec<u8> {
+        // The offset of the TIFF header and a TIFF header with an empty IFD.
+        let exif = b"\0\0\0\0MM\0\x2a\0\0\0\x08\0\0\0\0\0\0";
+        let mut meta = b"\0\0\0\0".to_vec();
+        meta.extend(atom(b"iinf", &iinf()));
+        meta.extend(atom(b"iloc", &iloc(extents)));
+        meta.extend(atom(b"idat", exif));
+        let mut heif = atom(b"ftyp", b"heic\0\0\0\0");
+        heif.extend(atom(b"meta", &meta));
+        heif
+    }
+
+    fn read(data: &[u8]) -> Result<exif::Exif, String> {
+        read_exif_item(&mut Cursor::new(data))
+    }
+
+    #[test]
+    fn reads_exif_items() {
+        assert!(read(&heif(&[(0, 18)])).is_ok());
+        assert!(read(&heif(&[(0, 8), (8, 10)])).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let heif = heif(&[(0, 18)]);
+        for length in 0..heif.len() {
+            assert!(read(&heif[..length]).is_err(), "{}", length);
+        }
+    }
+
+    #[test]
+    fn rejects_too_large_extents() {
+        assert!(read(&heif(&[(0, MAX_EXIF_SIZE + 1)])).is_err());
+        assert!(read(&heif(&[(0, 8), (0, u64::MAX)])).is_err());
+        assert!(read(&heif(&[(u64::MAX, 8)])).is_err());
+    }
+
+    #[test]
+    fn parses_truncated_boxes() {
+        let iinf = iinf();
+        assert_eq!(exif_item_id(&iinf), Some(1));
+        for length in 0..iinf.len() - 5 {
+            assert_eq!(exif_item_id(&iinf[..length]), None, "{}", length);
+        }
+        let iloc = iloc(&[(0, 8), (8, 10)]);
+        let location = item_location(&iloc, 1).unwrap();
+        assert_eq!(location.construction_method, 1);
+        assert_eq!(location.extents, vec![(0, 8), (8, 10)]);
+        assert!(item_location(&iloc, 2).is_none());
+        for length in 0..iloc.len() {
+            assert!(item_location(&iloc[..length], 1).is_none(), "{}", length);
+        }
+    }
+
+    #[test]
+    fn reads_uints_of_all_sizes() {
+        let mut reader = ByteReader {
+            data: &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+        };
+        assert_eq!(reader.uint(0), Some(0));
+        assert_eq!(reader.uint(2), Some(0x0102));
+        assert_eq!(reader.uint(9), None);
+        assert_eq!(reader.uint(8), Some(0x0304_0506_0708_090a));
+        assert_eq!(reader.uint(1), None);
+    }
+}
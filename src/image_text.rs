@@ -0,0 +1,457 @@
+use crate::capture::{parse_iso8601, DatePrecision};
+use crate::xmp::{xmp_capture_date, xmp_properties};
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use flate2::read::ZlibDecoder;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Caps the memory used for one text chunk, XMP chunk or GIF extension, and for inflated text.
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// Metadata of PNG, WebP and GIF images besides their EXIF data.
+#[derive(Debug, Clone, Default)]
+pub struct ImageText {
+    /// Text chunks of PNG images and comments of GIF images, by keyword.
+    pub entries: Vec<(String, String)>,
+    /// The embedded XMP packet.
+    pub xmp: Option<String>,
+}
+
+impl ImageText {
+    /// The `Creation Time` of PNG images, or the date of the XMP packet.
+    pub(crate) fn capture_date(
+        &self,
+    ) -> Option<(NaiveDateTime, Option<FixedOffset>, DatePrecision)> {
+        self.entries
+            .iter()
+            .filter(|(keyword, _)| keyword == "Creation Time")
+            .find_map(|(_, text)| parse_creation_time(text))
+            .or_else(|| {
+                self.xmp
+                    .as_deref()
+                    .and_then(|xmp| xmp_capture_date(&xmp_properties(xmp)))
+            })
+    }
+}
+
+pub(crate) fn has_image_text(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .filter(|&e| ["png", "webp", "gif"].contains(&e.to_lowercase().as_str()))
+        .is_some()
+}
+
+/// Reads the text metadata of a PNG, WebP or GIF image.
+pub fn read_image_text(path: &Path) -> Result<ImageText, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut signature = [0; 12];
+    file.read_exact(&mut signature).map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    let text = if signature.starts_with(b"\x89PNG\r\n\x1a\n") {
+        read_png_text(&mut file)
+    } else if signature.starts_with(b"RIFF") && &signature[8..12] == b"WEBP" {
+        read_webp_text(&mut file)
+    } else if signature.starts_with(b"GIF87a") || signature.starts_with(b"GIF89a") {
+        read_gif_text(&mut file)
+    } else {
+        return Err("Not a PNG, WebP or GIF image.".to_string());
+    };
+    text.map_err(|e| e.to_string())
+}
+
+/// `Creation Time` should be in the format of RFC 1123, but is often ISO 8601 or EXIF style.
+fn parse_creation_time(text: &str) -> Option<(NaiveDateTime, Option<FixedOffset>, DatePrecision)> {
+    if let Ok(date_time) = DateTime::parse_from_rfc2822(text.trim()) {
+        return Some((
+            date_time.naive_local(),
+            Some(*date_time.offset()),
+            DatePrecision::Second,
+        ));
+    }
+    NaiveDateTime::parse_from_str(text.trim(), "%Y:%m:%d %H:%M:%S")
+        .ok()
+        .map(|date_time| (date_time, None, DatePrecision::Second))
+        .or_else(|| parse_iso8601(text))
+}
+
+fn read_png_text<R: Read + Seek>(reader: &mut R) -> io::Result<ImageText> {
+    let mut text = ImageText::default();
+    reader.seek(SeekFrom::Start(8))?;
+    loop {
+        let mut header = [0; 8];
+        if read_or_end(reader, &mut header)?.is_none() {
+            break;
+        }
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let kind = &header[4..8];
+        if kind == b"IEND" {
+            break;
+        }
+        if !matches!(kind, b"tEXt" | b"zTXt" | b"iTXt") || length > MAX_CHUNK_SIZE {
+            // Skip the data and the CRC.
+            reader.seek(SeekFrom::Current(length as i64 + 4))?;
+            continue;
+        }
+        let mut data = vec![0; length as usize];
+        reader.read_exact(&mut data)?;
+        reader.seek(SeekFrom::Current(4))?;
+        if let Some((keyword, value)) = png_text_chunk(kind, &data) {
+            if keyword == XMP_KEYWORD {
+                text.xmp = Some(value);
+            } else {
+                text.entries.push((keyword, value));
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Keyword and text of a `tEXt`, `zTXt` or `iTXt` chunk.
+fn png_text_chunk(kind: &[u8], data: &[u8]) -> Option<(String, String)> {
+    let separator = data.iter().position(|&b| b == 0)?;
+    let keyword = latin1(&data[..separator]);
+    let rest = &data[separator + 1..];
+    let value = match kind {
+        b"tEXt" => latin1(rest),
+        // Compression method, only zlib exists.
+        b"zTXt" => latin1(&inflate(rest.get(1..)?)?),
+        _ => {
+            let compressed = *rest.first()? == 1;
+            // Compression method, language tag and translated keyword.
+            let rest = rest.get(2..)?;
+            let language_end = rest.iter().position(|&b| b == 0)?;
+            let rest = &rest[language_end + 1..];
+            let translated_end = rest.iter().position(|&b| b == 0)?;
+            let rest = &rest[translated_end + 1..];
+            let bytes = if compressed {
+                inflate(rest)?
+            } else {
+                rest.to_vec()
+            };
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+    };
+    Some((keyword, value))
+}
+
+fn read_webp_text<R: Read + Seek>(reader: &mut R) -> io::Result<ImageText> {
+    let mut text = ImageText::default();
+    reader.seek(SeekFrom::Start(12))?;
+    loop {
+        let mut header = [0; 8];
+        if read_or_end(reader, &mut header)?.is_none() {
+            break;
+        }
+        let size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        // Chunks are padded to an even size.
+        let padded_size = size as i64 + (size % 2) as i64;
+        if &header[0..4] == b"XMP " && size <= MAX_CHUNK_SIZE {
+            let mut data = vec![0; size as usize];
+            reader.read_exact(&mut data)?;
+            reader.seek(SeekFrom::Current(padded_size - size as i64))?;
+            text.xmp = Some(String::from_utf8_lossy(&data).into_owned());
+        } else {
+            reader.seek(SeekFrom::Current(padded_size))?;
+        }
+    }
+    Ok(text)
+}
+
+fn read_gif_text<R: Read + Seek>(reader: &mut R) -> io::Result<ImageText> {
+    let mut text = ImageText::default();
+    // Signature and logical screen descriptor.
+    let mut header = [0; 13];
+    reader.read_exact(&mut header)?;
+    skip_color_table(reader, header[10])?;
+    loop {
+        let mut introducer = [0; 1];
+        if read_or_end(reader, &mut introducer)?.is_none() {
+            break;
+        }
+        match introducer[0] {
+            0x21 => {
+                let mut label = [0; 1];
+                reader.read_exact(&mut label)?;
+                match label[0] {
+                    0xFF => {
+                        let mut application = [0; 12];
+                        reader.read_exact(&mut application)?;
+                        if &application[1..12] == b"XMP DataXMP" {
+                            // The packet is stored as is, the sizes of the sub blocks are part of
+                            // it and a trailer makes readers find the end.
+                            let raw = read_sub_blocks(reader, true)?;
+                            text.xmp = Some(xmp_packet(&raw));
+                        } else {
+                            read_sub_blocks(reader, false)?;
+                        }
+                    }
+                    0xFE => {
+                        let comment = read_sub_blocks(reader, false)?;
+                        text.entries.push(("Comment".to_string(), latin1(&comment)));
+                    }
+                    _ => {
+                        read_sub_blocks(reader, false)?;
+                    }
+                }
+            }
+            0x2C => {
+                let mut descriptor = [0; 9];
+                reader.read_exact(&mut descriptor)?;
+                skip_color_table(reader, descriptor[8])?;
+                // Minimum code size of the image data.
+                reader.seek(SeekFrom::Current(1))?;
+                read_sub_blocks(reader, false)?;
+            }
+            _ => break,
+        }
+    }
+    Ok(text)
+}
+
+fn skip_color_table<R: Seek>(reader: &mut R, flags: u8) -> io::Result<()> {
+    if flags & 0x80 != 0 {
+        reader.seek(SeekFrom::Current(3 << ((flags & 0x07) + 1)))?;
+    }
+    Ok(())
+}
+
+/// The data of GIF sub blocks, optionally with the size bytes.
+fn read_sub_blocks<R: Read>(reader: &mut R, with_sizes: bool) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        let mut size = [0; 1];
+        reader.read_exact(&mut size)?;
+        if size[0] == 0 {
+            return Ok(data);
+        }
+        if with_sizes {
+            data.push(size[0]);
+        }
+        let start = data.len();
+        data.resize(start + size[0] as usize, 0);
+        reader.read_exact(&mut data[start..])?;
+        if data.len() > MAX_CHUNK_SIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "GIF extension is too large",
+            ));
+        }
+    }
+}
+
+/// Cuts the trailer after the end of an XMP packet.
+fn xmp_packet(raw: &[u8]) -> String {
+    let packet = String::from_utf8_lossy(raw);
+    let end = packet
+        .find("<?xpacket end")
+        .and_then(|start| packet[start..].find("?>").map(|end| start + end + 2))
+        .or_else(|| {
+            packet
+                .find("</x:xmpmeta>")
+                .map(|start| start + "</x:xmpmeta>".len())
+        })
+        .unwrap_or(packet.len());
+    packet[..end].to_string()
+}
+
+/// Fills `buffer`, or returns `None` at the end of the file.
+fn read_or_end<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<Option<()>> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(Some(())),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_CHUNK_SIZE as u64)
+        .read_to_end(&mut inflated)
+        .ok()?;
+    Some(inflated)
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+
+    const XMP: &str = "<x:xmpmeta xmlns:x='adobe:ns:meta/'><rdf:RDF \
+        xmlns:rdf='http://www.w3.org/1999/02/22-rdf-syntax-ns#'><rdf:Description \
+        xmlns:xmp='http://ns.adobe.com/xap/1.0/' xmp:CreateDate='2020-01-02T03:04:05'/>\
+        </rdf:RDF></x:xmpmeta>";
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        // The CRC is not checked.
+        chunk.extend([0; 4]);
+        chunk
+    }
+
+    fn png() -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        png.extend(png_chunk(
+            b"tEXt",
+            b"Creation Time\0Thu, 02 Jan 2020 03:04:05 +0100",
+        ));
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend(deflate(b"compressed"));
+        png.extend(png_chunk(b"zTXt", &ztxt));
+        let mut itxt = b"XML:com.adobe.xmp\0\x01\0\0\0".to_vec();
+        itxt.extend(deflate(XMP.as_bytes()));
+        png.extend(png_chunk(b"iTXt", &itxt));
+        png.extend(png_chunk(b"IEND", b""));
+        png
+    }
+
+    fn webp() -> Vec<u8> {
+        let mut chunks = b"WEBPVP8 \x03\0\0\0abc\0XMP ".to_vec();
+        chunks.extend((XMP.len() as u32).to_le_bytes());
+        chunks.extend(XMP.as_bytes());
+        let mut webp = b"RIFF".to_vec();
+        webp.extend((chunks.len() as u32).to_le_bytes());
+        webp.extend(chunks);
+        webp
+    }
+
+    fn gif() -> Vec<u8> {
+        // A global color table of two colors.
+        let mut gif = b"GIF89a\x01\0\x01\0\x80\0\0".to_vec();
+        gif.extend([0; 6]);
+        gif.extend(b"\x21\xfe\x07comment\0");
+        gif.extend(b"\x21\xff\x0bXMP DataXMP");
+        // The packet is stored as is, followed by the trailer that makes readers of sub blocks find
+        // their end.
+        gif.extend(XMP.as_bytes());
+        gif.push(1);
+        gif.extend((0..=255u8).rev());
+        gif.push(0);
+        gif.extend(b"\x2c\0\0\0\0\x01\0\x01\0\0\x02\x02\x4c\x01\0\x3b");
+        gif
+    }
+
+    #[test]
+    fn reads_png_text_chunks() {
+        let text = read_png_text(&mut Cursor::new(png())).unwrap();
+        assert_eq!(
+            text.entries,
+            [
+                (
+                    "Creation Time".to_string(),
+                    "Thu, 02 Jan 2020 03:04:05 +0100".to_string()
+                ),
+                ("Comment".to_string(), "compressed".to_string()),
+            ]
+        );
+        assert_eq!(text.xmp.as_deref(), Some(XMP));
+        let (date_time, offset, _) = text.capture_date().unwrap();
+        assert_eq!(date_time.to_string(), "2020-01-02 03:04:05");
+        assert_eq!(offset, FixedOffset::east_opt(3600));
+    }
+
+    #[test]
+    fn reads_webp_xmp_chunks() {
+        let text = read_webp_text(&mut Cursor::new(webp())).unwrap();
+        assert_eq!(text.xmp.as_deref(), Some(XMP));
+        let (date_time, _, _) = text.capture_date().unwrap();
+        assert_eq!(date_time.to_string(), "2020-01-02 03:04:05");
+    }
+
+    #[test]
+    fn reads_gif_comments_and_xmp() {
+        let text = read_gif_text(&mut Cursor::new(gif())).unwrap();
+        assert_eq!(
+            text.entries,
+            [("Comment".to_string(), "comment".to_string())]
+        );
+        assert_eq!(text.xmp.as_deref(), Some(XMP));
+    }
+
+    fn read_truncated(image: &[u8], read: fn(&mut Cursor<Vec<u8>>) -> io::Result<ImageText>) {
+        for length in 0..image.len() {
+            if let Ok(text) = read(&mut Cursor::new(image[..length].to_vec())) {
+                assert!(text.xmp.is_none_or(|xmp| XMP.starts_with(&xmp)));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_truncated_images() {
+        read_truncated(&png(), read_png_text);
+        read_truncated(&webp(), read_webp_text);
+        read_truncated(&gif(), read_gif_text);
+    }
+
+    #[test]
+    fn skips_malformed_png_text_chunks() {
+        assert_eq!(png_text_chunk(b"tEXt", b"no keyword"), None);
+        assert_eq!(png_text_chunk(b"zTXt", b"Comment\0"), None);
+        assert_eq!(png_text_chunk(b"zTXt", b"Comment\0\0not zlib"), None);
+        assert_eq!(png_text_chunk(b"iTXt", b"Comment\0"), None);
+        assert_eq!(png_text_chunk(b"iTXt", b"Comment\0\0\0en"), None);
+        assert_eq!(
+            png_text_chunk(b"iTXt", b"Comment\0\x01\0\0\0not zlib"),
+            None
+        );
+        assert_eq!(
+            png_text_chunk(b"iTXt", b"Comment\0\0\0en\0Kommentar\0text"),
+            Some(("Comment".to_string(), "text".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_gif_extensions() {
+        let mut blocks = Vec::new();
+        for _ in 0..=MAX_CHUNK_SIZE / 255 {
+            blocks.push(255);
+            blocks.extend([0; 255]);
+        }
+        blocks.push(0);
+        assert!(read_sub_blocks(&mut Cursor::new(blocks), false).is_err());
+    }
+
+    #[test]
+    fn parses_creation_times() {
+        for (text, expected) in [
+            ("Thu, 02 Jan 2020 03:04:05 +0000", "2020-01-02 03:04:05"),
+            ("2020:01:02 03:04:05", "2020-01-02 03:04:05"),
+            ("2020-01-02T03:04:05Z", "2020-01-02 03:04:05"),
+        ] {
+            let (date_time, _, _) = parse_creation_time(text).unwrap();
+            assert_eq!(date_time.to_string(), expected);
+        }
+        assert!(parse_creation_time("yesterday").is_none());
+    }
+
+    #[test]
+    fn cuts_xmp_trailers() {
+        let packet = "<?xpacket begin?><x:xmpmeta/><?xpacket end='w'?>";
+        let mut raw = packet.as_bytes().to_vec();
+        raw.extend([1, 0xff, 0]);
+        assert_eq!(xmp_packet(&raw), packet);
+        assert_eq!(
+            xmp_packet(b"<x:xmpmeta></x:xmpmeta>\x01\xff"),
+            "<x:xmpmeta></x:xmpmeta>"
+        );
+        assert_eq!(xmp_packet(b"<x:xmpmeta>"), "<x:xmpmeta>");
+    }
+}
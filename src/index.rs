@@ -0,0 +1,103 @@
+use crate::compare::hash_file;
+use crate::{absolute_path, APP_FOLDER};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Content index of every file below the target folder.
+///
+/// Files are bucketed by size up front, hashes are only computed once a source file of the same
+/// size shows up. That keeps building the index of a large library cheap.
+pub struct TargetIndex {
+    by_size: HashMap<u64, Vec<IndexEntry>>,
+}
+
+struct IndexEntry {
+    path: PathBuf,
+    content_path: PathBuf,
+    hash: Option<blake3::Hash>,
+}
+
+impl TargetIndex {
+    pub fn build(target_folder: &Path) -> io::Result<TargetIndex> {
+        let mut index = TargetIndex {
+            by_size: HashMap::new(),
+        };
+        index.add_folder(&fs::canonicalize(target_folder)?)?;
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_size.values().map(Vec::len).sum()
+    }
+
+    /// Returns the path of a file in the index with the same content as `source`.
+    pub fn find_duplicate(&mut self, source: &Path) -> io::Result<Option<PathBuf>> {
+        let size = source.metadata()?.len();
+        let entries = match self.by_size.get_mut(&size) {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        // The source folder may be part of the target folder, a file is no duplicate of itself.
+        let source_path = fs::canonicalize(source)?;
+        let source_hash = hash_file(source)?;
+        for entry in entries.iter_mut().filter(|e| e.content_path != source_path) {
+            let hash = match entry.hash {
+                Some(hash) => hash,
+                None => {
+                    let hash = hash_file(&entry.content_path)?;
+                    entry.hash = Some(hash);
+                    hash
+                }
+            };
+            if hash == source_hash {
+                return Ok(Some(entry.path.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Adds a file to the index, replacing the entry of a file that was at `path` before.
+    /// `content_path` is where the content can currently be read from, which differs from `path`
+    /// during a dry run.
+    pub fn add(&mut self, path: PathBuf, content_path: &Path) -> io::Result<()> {
+        let path = absolute_path(&path);
+        self.remove(&path);
+        self.insert(path, absolute_path(content_path))
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        let path = absolute_path(path);
+        for entries in self.by_size.values_mut() {
+            entries.retain(|e| e.path != path);
+        }
+    }
+
+    fn insert(&mut self, path: PathBuf, content_path: PathBuf) -> io::Result<()> {
+        let size = content_path.metadata()?.len();
+        self.by_size.entry(size).or_default().push(IndexEntry {
+            path,
+            content_path,
+            hash: None,
+        });
+        Ok(())
+    }
+
+    fn add_folder(&mut self, dir: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name() == Some(OsStr::new(APP_FOLDER)) {
+                    continue;
+                }
+                self.add_folder(&path)?;
+            } else {
+                // Below the canonical target folder, so the path is canonical already.
+                self.insert(path.clone(), path)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use crate::compare::hash_file;
+use crate::{absolute_path, APP_FOLDER};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// Append-only record of every file system change of a run, stored in the target folder.
+pub struct Journal {
+    run_id: String,
+    file: Option<File>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub timestamp: String,
+    pub action: JournalAction,
+    #[serde(with = "stored_path")]
+    pub source: PathBuf,
+    #[serde(with = "stored_path::option")]
+    pub destination: Option<PathBuf>,
+    pub hash: String,
+    /// The destination already existed and was overwritten.
+    pub overwritten: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalAction {
+    Move,
+    Copy,
+    Delete,
+}
+
+/// Stores paths as JSON strings, or as their bytes if they are no valid Unicode.
+mod stored_path {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum StoredPath {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    impl StoredPath {
+        #[cfg(unix)]
+        fn new(path: &Path) -> StoredPath {
+            use std::os::unix::ffi::OsStrExt;
+            match path.to_str() {
+                Some(text) => StoredPath::Text(text.to_string()),
+                None => StoredPath::Bytes(path.as_os_str().as_bytes().to_vec()),
+            }
+        }
+
+        #[cfg(not(unix))]
+        fn new(path: &Path) -> StoredPath {
+            StoredPath::Text(path.to_string_lossy().into_owned())
+        }
+
+        fn into_path<E: serde::de::Error>(self) -> Result<PathBuf, E> {
+            match self {
+                StoredPath::Text(text) => Ok(PathBuf::from(text)),
+                #[cfg(unix)]
+                StoredPath::Bytes(bytes) => {
+                    use std::os::unix::ffi::OsStringExt;
+                    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+                }
+                #[cfg(not(unix))]
+                StoredPath::Bytes(_) => Err(E::custom("paths in bytes are only supported on Unix")),
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        StoredPath::new(path).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        StoredPath::deserialize(deserializer)?.into_path()
+    }
+
+    pub mod option {
+        use super::StoredPath;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::path::PathBuf;
+
+        pub fn serialize<S: Serializer>(
+            path: &Option<PathBuf>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            path.as_deref().map(StoredPath::new).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PathBuf>, D::Error> {
+            Option::<StoredPath>::deserialize(deserializer)?
+                .map(StoredPath::into_path)
+                .transpose()
+        }
+    }
+}
+
+impl Journal {
+    pub fn open(target_folder: &Path, run_id: String) -> io::Result<Journal> {
+        let folder = target_folder.join(APP_FOLDER);
+        fs::create_dir_all(&folder)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(folder.join(JOURNAL_FILE))?;
+        Ok(Journal {
+            run_id,
+            file: Some(file),
+        })
+    }
+
+    /// A journal that records nothing, used for dry runs.
+    pub fn disabled(run_id: String) -> Journal {
+        Journal { run_id, file: None }
+    }
+
+    /// A run id from the current time. Ids that the journal of `target_folder` holds already,
+    /// e.g. of a run that started in the same second, get a counter: `20210314-120000-2`.
+    pub fn new_run_id(target_folder: &Path) -> String {
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let used: HashSet<String> = read_journal(target_folder)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.run_id)
+            .collect();
+        let mut run_id = timestamp.clone();
+        let mut counter = 1;
+        while used.contains(&run_id) {
+            counter += 1;
+            run_id = format!("{}-{}", timestamp, counter);
+        }
+        run_id
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    pub fn record(
+        &self,
+        action: JournalAction,
+        source: &Path,
+        destination: Option<&Path>,
+        hash: &blake3::Hash,
+        overwritten: bool,
+    ) -> io::Result<()> {
+        let mut file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let entry = JournalEntry {
+            run_id: self.run_id.clone(),
+            timestamp: Local::now().to_rfc3339(),
+            action,
+            source: absolute_path(source),
+            destination: destination.map(absolute_path),
+            hash: hash.to_hex().to_string(),
+            overwritten,
+        };
+        let mut line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+pub fn read_journal(target_folder: &Path) -> Result<Vec<JournalEntry>, String> {
+    let path = target_folder.join(APP_FOLDER).join(JOURNAL_FILE);
+    let file = File::open(&path).map_err(|e| format!("Can't read journal {:?}: {}", path, e))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(i, line)| {
+            line.map_err(|e| e.to_string()).and_then(|line| {
+                serde_json::from_str(&line)
+                    .map_err(|e| format!("Invalid journal entry in line {}: {}", i + 1, e))
+            })
+        })
+        .collect()
+}
+
+/// Checks that the files moved or copied by a run, or by all runs without `run_id`, still exist
+/// with the recorded content. Files that a later run deleted are not checked. Returns the number of
+/// checked files and the entries that failed together with the reason.
+pub fn verify_runs(
+    target_folder: &Path,
+    run_id: Option<&str>,
+) -> Result<(usize, Vec<(JournalEntry, String)>), String> {
+    let entries = read_journal(target_folder)?;
+    if let Some(run_id) = run_id {
+        if !entries.iter().any(|e| e.run_id == run_id) {
+            return Err(format!("No journal entries found for run {}.", run_id));
+        }
+    }
+
+    // Only the latest entry of a destination describes what should be there now.
+    let mut current: HashMap<PathBuf, JournalEntry> = HashMap::new();
+    for entry in entries {
+        match (&entry.action, &entry.destination) {
+            (JournalAction::Delete, _) => {
+                current.remove(&entry.source);
+            }
+            (_, Some(destination)) => {
+                current.insert(destination.clone(), entry);
+            }
+            (_, None) => {}
+        }
+    }
+    let mut entries: Vec<JournalEntry> = current
+        .into_values()
+        .filter(|e| run_id.is_none_or(|run_id| e.run_id == run_id))
+        .collect();
+    entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    let checked = entries.len();
+    let mut failures = Vec::new();
+    for entry in entries {
+        if let Err(reason) = verify_entry(&entry) {
+            failures.push((entry, reason));
+        }
+    }
+    Ok((checked, failures))
+}
+
+fn verify_entry(entry: &JournalEntry) -> Result<(), String> {
+    let destination = entry
+        .destination
+        .as_ref()
+        .ok_or_else(|| "The entry has no destination.".to_string())?;
+    if !destination.exists() {
+        return Err(format!("{:?} does not exist anymore.", destination));
+    }
+    if hash_file(destination)
+        .map_err(|e| e.to_string())?
+        .to_hex()
+        .as_str()
+        != entry.hash
+    {
+        return Err(format!("{:?} was modified after the run.", destination));
+    }
+    Ok(())
+}
+
+/// Entries of a run that were reversed, or would have been in a dry run, and the ones that could
+/// not be reversed together with the reason.
+pub struct UndoReport {
+    pub undone: Vec<JournalEntry>,
+    /// Entries that were reversed, but had overwritten a file at their destination that can't be
+    /// restored.
+    pub overwritten: Vec<JournalEntry>,
+    pub failures: Vec<(JournalEntry, String)>,
+}
+
+/// Reverses all entries of a run, newest first.
+pub fn undo_run(target_folder: &Path, run_id: &str, dry_run: bool) -> Result<UndoReport, String> {
+    let entries: Vec<JournalEntry> = read_journal(target_folder)?
+        .into_iter()
+        .filter(|e| e.run_id == run_id)
+        .collect();
+    if entries.is_empty() {
+        return Err(format!("No journal entries found for run {}.", run_id));
+    }
+
+    let mut report = UndoReport {
+        undone: Vec::new(),
+        overwritten: Vec::new(),
+        failures: Vec::new(),
+    };
+    for entry in entries.into_iter().rev() {
+        match undo_entry(&entry, target_folder, dry_run) {
+            Err(reason) => report.failures.push((entry, reason)),
+            Ok(()) if entry.overwritten => report.overwritten.push(entry),
+            Ok(()) => report.undone.push(entry),
+        }
+    }
+    Ok(report)
+}
+
+fn undo_entry(entry: &JournalEntry, target_folder: &Path, dry_run: bool) -> Result<(), String> {
+    let destination = match (&entry.action, &entry.destination) {
+        (JournalAction::Delete, _) => return Err("Deleted files can't be restored.".to_string()),
+        (_, Some(destination)) => destination,
+        (_, None) => return Err("The entry has no destination.".to_string()),
+    };
+    verify_entry(entry)?;
+    match entry.action {
+        JournalAction::Move => {
+            if entry.source.exists() {
+                return Err(format!("{:?} exists again.", entry.source));
+            }
+            if dry_run {
+                return Ok(());
+            }
+            if let Some(parent) = entry.source.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::rename(destination, &entry.source).map_err(|e| e.to_string())?;
+        }
+        JournalAction::Copy => {
+            if dry_run {
+                return Ok(());
+            }
+            fs::remove_file(destination).map_err(|e| e.to_string())?;
+        }
+        JournalAction::Delete => unreachable!(),
+    }
+    remove_empty_parents(destination, target_folder);
+    Ok(())
+}
+
+/// Removes the year/month folders a run created, as long as they are empty now.
+fn remove_empty_parents(path: &Path, target_folder: &Path) {
+    let target_folder = absolute_path(target_folder);
+    let mut parent = path.parent();
+    while let Some(folder) = parent {
+        if !folder.starts_with(&target_folder) || folder == target_folder {
+            break;
+        }
+        if fs::remove_dir(folder).is_err() {
+            break;
+        }
+        parent = folder.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A target folder whose journal records run `run` moving `source/photo.jpg` to
+    /// `target/2021/photo.jpg`, returning the target folder, source and destination.
+    fn moved_file(test: &str, overwritten: bool) -> (PathBuf, PathBuf, PathBuf) {
+        let folder = std::env::temp_dir().join(format!("journal_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&folder);
+        let (source, target) = (folder.join("source"), folder.join("target"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(target.join("2021")).unwrap();
+        let (source, destination) = (source.join("photo.jpg"), target.join("2021/photo.jpg"));
+        fs::write(&source, b"photo").unwrap();
+        let hash = hash_file(&source).unwrap();
+        fs::rename(&source, &destination).unwrap();
+        Journal::open(&target, "run".to_string())
+            .unwrap()
+            .record(
+                JournalAction::Move,
+                &source,
+                Some(&destination),
+                &hash,
+                overwritten,
+            )
+            .unwrap();
+        (target, source, destination)
+    }
+
+    fn reasons(failures: &[(JournalEntry, String)]) -> Vec<&str> {
+        failures.iter().map(|(_, reason)| reason.as_str()).collect()
+    }
+
+    #[test]
+    fn verifies_and_undoes_a_run() {
+        let (target, source, destination) = moved_file("round_trip", false);
+        let (checked, failures) = verify_runs(&target, Some("run")).unwrap();
+        assert_eq!((checked, failures.len()), (1, 0));
+
+        let report = undo_run(&target, "run", false).unwrap();
+
+        assert_eq!(report.undone.len(), 1);
+        assert!(report.overwritten.is_empty() && report.failures.is_empty());
+        assert_eq!(fs::read(&source).unwrap(), b"photo");
+        assert!(!destination.parent().unwrap().exists());
+        assert!(verify_runs(&target, Some("other")).is_err());
+        assert!(undo_run(&target, "other", false).is_err());
+    }
+
+    #[test]
+    fn changes_nothing_in_dry_runs() {
+        let (target, source, destination) = moved_file("dry_run", false);
+
+        let report = undo_run(&target, "run", true).unwrap();
+
+        assert_eq!(report.undone.len(), 1);
+        assert!(!source.exists());
+        assert!(destination.exists());
+    }
+
+    #[test]
+    fn keeps_files_that_changed_after_the_run() {
+        let (target, source, destination) = moved_file("changed", false);
+        fs::write(&destination, b"edited photo").unwrap();
+
+        let (_, failures) = verify_runs(&target, None).unwrap();
+        assert_eq!(
+            reasons(&failures),
+            [format!("{:?} was modified after the run.", destination)]
+        );
+        let report = undo_run(&target, "run", false).unwrap();
+
+        assert!(report.undone.is_empty());
+        assert_eq!(reasons(&report.failures), reasons(&failures));
+        assert!(!source.exists());
+    }
+
+    #[test]
+    fn keeps_files_whose_source_exists_again() {
+        let (target, source, destination) = moved_file("source_exists", false);
+        fs::write(&source, b"another photo").unwrap();
+
+        let report = undo_run(&target, "run", false).unwrap();
+
+        assert_eq!(
+            reasons(&report.failures),
+            [format!("{:?} exists again.", source)]
+        );
+        assert_eq!(fs::read(&source).unwrap(), b"another photo");
+        assert_eq!(fs::read(&destination).unwrap(), b"photo");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_paths_that_are_no_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+        let (target, _, _) = moved_file("no_unicode", false);
+        let source = target.join(std::ffi::OsStr::from_bytes(b"photo\xff.jpg"));
+        let journal = Journal::open(&target, "other".to_string()).unwrap();
+        journal
+            .record(
+                JournalAction::Delete,
+                &source,
+                None,
+                &blake3::hash(b""),
+                false,
+            )
+            .unwrap();
+
+        let entries = read_journal(&target).unwrap();
+
+        assert_eq!(
+            entries[0].source,
+            target.parent().unwrap().join("source/photo.jpg")
+        );
+        assert_eq!(entries[1].source, source);
+        assert_eq!(entries[1].destination, None);
+    }
+
+    #[test]
+    fn reports_overwritten_files_once() {
+        let (target, source, _) = moved_file("overwritten", true);
+
+        let report = undo_run(&target, "run", false).unwrap();
+
+        assert_eq!(report.overwritten.len(), 1);
+        assert!(report.undone.is_empty() && report.failures.is_empty());
+        assert!(source.exists());
+    }
+}
@@ -0,0 +1,152 @@
+//! Sorts images and videos into folders by the date they were captured.
+//!
+//! A [`Sorter`] runs with [`Options`] and reports everything that happens to a [`SortHandler`],
+//! which also makes the decisions that would otherwise need a user.
+//!
+//! ```no_run
+//! use image_sorter::{Mode, Options, SortHandler, SortOutcome, Sorter};
+//!
+//! struct Handler;
+//!
+//! impl SortHandler for Handler {}
+//!
+//! let options = Options::builder("/media/library")
+//!     .source_folder("/media/camera")
+//!     .mode(Mode::Copy)
+//!     .build()
+//!     .unwrap();
+//! let summary = Sorter::new(options).run(&mut Handler).unwrap();
+//! for outcome in &summary.outcomes {
+//!     if let SortOutcome::Sorted { source, target } = outcome {
+//!         println!("{:?} -> {:?}", source, target);
+//!     }
+//! }
+//! ```
+
+mod avchd;
+mod avi;
+mod bmff;
+mod capture;
+mod companion;
+mod compare;
+mod config;
+mod events;
+mod filename;
+mod heif;
+mod image_text;
+mod index;
+mod journal;
+mod live_photo;
+mod matroska;
+mod options;
+mod perceptual;
+mod progress;
+mod raw;
+mod sorter;
+mod stats;
+mod takeout;
+mod template;
+mod timezone;
+mod video;
+mod xmp;
+
+pub use capture::{
+    read_exif, CaptureInfo, Confidence, DateExtractor, DatePipeline, DatePrecision, DateSource,
+    ExtractedDate, ExtractorSettings, DEFAULT_DATE_SOURCES,
+};
+pub use companion::{default_companion_groups, CompanionGroup};
+pub use compare::{hash_file, CompareMode};
+pub use config::{default_config_path, load_config, ConfigValues};
+pub use events::{
+    ConflictResolution, SimilarImageResolution, SkipReason, SortEvent, SortHandler, SortOutcome,
+};
+pub use filename::{default_filename_patterns, FilenamePattern, YearRange};
+pub use image_text::{read_image_text, ImageText};
+pub use journal::{read_journal, undo_run, verify_runs, JournalAction, JournalEntry, UndoReport};
+pub use live_photo::read_content_identifier;
+pub use options::{CollisionSuffix, FileConflictResolutionMode, Mode, Options, OptionsBuilder};
+pub use perceptual::{describe_image, pixel_count, SimilarImage};
+pub use sorter::{SortSummary, Sorter};
+pub use stats::{collect_stats, FileCount, TargetStats};
+pub use takeout::{find_takeout_json, read_takeout_metadata, takeout_media, TakeoutMetadata};
+pub use template::{PathTemplate, TemplateContext, DEFAULT_PATH_TEMPLATE};
+pub use timezone::CaptureZone;
+pub use video::{read_video_metadata, VideoBackend, VideoMetadata};
+pub use xmp::{find_sidecar, read_embedded_xmp, xmp_properties};
+
+use raw::{is_raw, RAW_EXTENSIONS};
+use std::ffi::OsStr;
+use std::fs::{self, DirEntry};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Folder in the target folder holding the journal and other data of the sorter itself.
+pub const APP_FOLDER: &str = ".image-sorter";
+
+const IMAGE_EXTENSIONS: [&str; 10] = [
+    "png", "jpg", "jpeg", "tif", "webp", "gif", "heic", "heif", "hif", "avif",
+];
+const VIDEO_EXTENSIONS: [&str; 8] = ["mp4", "mov", "3gp", "avi", "mkv", "webm", "mts", "m2ts"];
+
+/// Calls `cb` for every file below `dir`, leaving out the data folder of the sorter.
+pub fn visit_dirs(dir: &Path, cb: &mut dyn FnMut(&DirEntry)) -> io::Result<()> {
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() {
+                if path.file_name() == Some(OsStr::new(APP_FOLDER)) {
+                    continue;
+                }
+                visit_dirs(&path, cb)?;
+            } else {
+                cb(&entry);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn is_supported_file_type(source_path: &Path) -> bool {
+    is_image(source_path) || is_video(source_path)
+}
+
+/// Images including WebP, GIF, HEIF and camera RAW files.
+pub fn is_image(path: &Path) -> bool {
+    let is_common_image = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .filter(|&e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .is_some();
+    is_common_image || is_raw(path)
+}
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .filter(|&e| VIDEO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .is_some()
+}
+
+/// Lowercase extensions of all supported file types.
+pub(crate) fn media_extensions() -> impl Iterator<Item = &'static str> {
+    IMAGE_EXTENSIONS
+        .into_iter()
+        .chain(RAW_EXTENSIONS)
+        .chain(VIDEO_EXTENSIONS)
+}
+
+/// Canonical form of `path`, which works for already moved or deleted files too as long as their
+/// folder still exists.
+pub fn absolute_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .ok()
+        .or_else(|| {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
@@ -0,0 +1,305 @@
+use crate::capture::read_exif;
+use crate::video::read_video_metadata;
+use exif::{In, Tag, Value};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// QuickTime metadata key of the content identifier of Live Photo videos.
+const CONTENT_IDENTIFIER_KEY: &str = "com.apple.quicktime.content.identifier";
+/// Tag of the content identifier in the Apple maker note of Live Photo images.
+const CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+const ASCII_TYPE: u16 = 2;
+/// Apple maker notes start with this, followed by a version, the byte order and an IFD.
+const APPLE_MAKER_NOTE_HEADER: &[u8] = b"Apple iOS\0";
+const IMAGE_EXTENSIONS: [&str; 4] = ["heic", "heif", "jpg", "jpeg"];
+const VIDEO_EXTENSIONS: [&str; 1] = ["mov"];
+
+/// The identifier Apple writes into both the image and the video of a Live Photo, from the
+/// maker note of the image or the QuickTime metadata of the video.
+pub fn read_content_identifier(path: &Path) -> Option<String> {
+    if is_live_photo_video(path) {
+        read_video_metadata(path)
+            .ok()?
+            .tags
+            .remove(CONTENT_IDENTIFIER_KEY)
+    } else if is_live_photo_image(path) {
+        let exif = read_exif(path).ok()?;
+        match &exif.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+            Value::Undefined(data, _) => maker_note_content_identifier(data),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+/// Finds the other file of Live Photos, reading the content identifiers of the files of a folder
+/// only once.
+#[derive(Default)]
+pub(crate) struct LivePhotoIndex {
+    /// The Live Photo files of every searched folder with their content identifiers.
+    folders: HashMap<PathBuf, Vec<(PathBuf, String)>>,
+}
+
+/// The other file of a Live Photo.
+pub(crate) struct LivePhotoPartner {
+    pub(crate) path: PathBuf,
+    /// Both files have the same content identifier, otherwise only the same stem.
+    pub(crate) identified: bool,
+}
+
+impl LivePhotoIndex {
+    /// The video of a Live Photo image or the image of a Live Photo video, if it exists. Files
+    /// are paired by their content identifier, or else by their stem unless their identifiers
+    /// differ.
+    pub(crate) fn find_partner(&mut self, path: &Path) -> Option<LivePhotoPartner> {
+        let video = is_live_photo_video(path);
+        if !video && !is_live_photo_image(path) {
+            return None;
+        }
+        let identifier = read_content_identifier(path);
+        if let Some(identifier) = &identifier {
+            let partner = self.folder(path).iter().find(|(other, other_identifier)| {
+                other_identifier == identifier && is_live_photo_video(other) != video
+            });
+            if let Some((partner, _)) = partner.filter(|(partner, _)| partner.is_file()) {
+                return Some(LivePhotoPartner {
+                    path: partner.clone(),
+                    identified: true,
+                });
+            }
+        }
+        let extensions = if video {
+            IMAGE_EXTENSIONS.as_slice()
+        } else {
+            VIDEO_EXTENSIONS.as_slice()
+        };
+        extensions
+            .iter()
+            .flat_map(|extension| [extension.to_string(), extension.to_uppercase()])
+            .map(|extension| path.with_extension(extension))
+            .find(|partner| {
+                partner.is_file()
+                    && match (&identifier, read_content_identifier(partner)) {
+                        (Some(identifier), Some(other)) => *identifier == other,
+                        _ => true,
+                    }
+            })
+            .map(|partner| LivePhotoPartner {
+                path: partner,
+                identified: false,
+            })
+    }
+
+    /// The Live Photo files next to `path` with their content identifiers.
+    fn folder(&mut self, path: &Path) -> &[(PathBuf, String)] {
+        let folder = path.parent().unwrap_or(Path::new(""));
+        self.folders.entry(folder.to_path_buf()).or_insert_with(|| {
+            let read_folder = if folder.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                folder
+            };
+            fs::read_dir(read_folder)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| path.with_file_name(entry.file_name()))
+                .filter(|file| is_live_photo_image(file) || is_live_photo_video(file))
+                .filter_map(|file| {
+                    let identifier = read_content_identifier(&file)?;
+                    Some((file, identifier))
+                })
+                .collect()
+        })
+    }
+}
+
+pub(crate) fn is_live_photo_video(path: &Path) -> bool {
+    has_extension(path, &VIDEO_EXTENSIONS)
+}
+
+fn is_live_photo_image(path: &Path) -> bool {
+    has_extension(path, &IMAGE_EXTENSIONS)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Reads the content identifier from an Apple maker note, an IFD whose offsets are relative to
+/// the start of the maker note.
+fn maker_note_content_identifier(data: &[u8]) -> Option<String> {
+    if !data.starts_with(APPLE_MAKER_NOTE_HEADER) {
+        return None;
+    }
+    let big_endian = match data.get(12..14)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let count = u16_at(14)? as usize;
+    for index in 0..count {
+        let entry = 16 + index * 12;
+        if u16_at(entry)? != CONTENT_IDENTIFIER_TAG || u16_at(entry + 2)? != ASCII_TYPE {
+            continue;
+        }
+        let length = u32_at(entry + 4)? as usize;
+        // Values of up to four bytes are stored in the entry itself.
+        let value = if length <= 4 {
+            data.get(entry + 8..entry + 8 + length)?
+        } else {
+            let offset = u32_at(entry + 8)? as usize;
+            data.get(offset..offset.checked_add(length)?)?
+        };
+        let identifier = String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        return Some(identifier).filter(|identifier| !identifier.is_empty());
+    }
+    None
+}
+
+/// An Apple maker note with a big endian IFD of two entries, the second the content identifier.
+#[cfg(test)]
+fn maker_note(identifier: &str) -> Vec<u8> {
+    let value = [identifier.as_bytes(), b"\0"].concat();
+    let mut note = APPLE_MAKER_NOTE_HEADER.to_vec();
+    note.extend(b"\0\x01MM\0\x02");
+    note.extend([0, 1, 0, 9, 0, 0, 0, 1, 0, 0, 0, 14]);
+    note.extend(CONTENT_IDENTIFIER_TAG.to_be_bytes());
+    note.extend(ASCII_TYPE.to_be_bytes());
+    note.extend((value.len() as u32).to_be_bytes());
+    if value.len() <= 4 {
+        note.extend(&value);
+        note.resize(note.len() + 4 - value.len(), 0);
+        note.extend([0; 4]);
+    } else {
+        note.extend((note.len() as u32 + 8).to_be_bytes());
+        note.extend([0; 4]);
+        note.extend(&value);
+    }
+    note
+}
+
+/// A JPEG image of a Live Photo, with only the maker note in its EXIF data.
+#[cfg(test)]
+pub(crate) fn live_photo_image(identifier: &str) -> Vec<u8> {
+    let note = maker_note(identifier);
+    // IFD0 with the pointer to the Exif IFD, which has the maker note after it.
+    let mut tiff = b"II\x2a\0\x08\0\0\0\x01\0\x69\x87\x04\0\x01\0\0\0\x1a\0\0\0\0\0\0\0".to_vec();
+    tiff.extend(b"\x01\0\x7c\x92\x07\0");
+    tiff.extend((note.len() as u32).to_le_bytes());
+    tiff.extend(44u32.to_le_bytes());
+    tiff.extend([0; 4]);
+    tiff.extend(note);
+    let mut jpeg = b"\xff\xd8\xff\xe1".to_vec();
+    jpeg.extend((tiff.len() as u16 + 8).to_be_bytes());
+    jpeg.extend(b"Exif\0\0");
+    jpeg.extend(tiff);
+    jpeg.extend(b"\xff\xd9");
+    jpeg
+}
+
+/// A QuickTime video of a Live Photo, with only the content identifier in its `moov` box.
+#[cfg(test)]
+pub(crate) fn live_photo_video(identifier: &str) -> Vec<u8> {
+    use crate::bmff::atom;
+    let key = CONTENT_IDENTIFIER_KEY.as_bytes();
+    let mut keys = b"\0\0\0\0\0\0\0\x01".to_vec();
+    keys.extend((key.len() as u32 + 8).to_be_bytes());
+    keys.extend(b"mdta");
+    keys.extend(key);
+    let data = atom(
+        b"data",
+        &[b"\0\0\0\x01\0\0\0\0", identifier.as_bytes()].concat(),
+    );
+    let mut meta = atom(b"hdlr", b"\0\0\0\0\0\0\0\0mdta");
+    meta.extend(atom(b"keys", &keys));
+    meta.extend(atom(b"ilst", &atom(&1u32.to_be_bytes(), &data)));
+    let mut video = atom(b"ftyp", b"qt  \0\0\0\0");
+    video.extend(atom(b"moov", &atom(b"meta", &meta)));
+    video
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_content_identifiers_of_maker_notes() {
+        let identifier = "8C8B5B2B-1A8C-4C3A-9F5E-2E1D0C9B8A7F";
+        assert_eq!(
+            maker_note_content_identifier(&maker_note(identifier)).as_deref(),
+            Some(identifier)
+        );
+        // Short values are stored in the entry.
+        assert_eq!(
+            maker_note_content_identifier(&maker_note("AB")).as_deref(),
+            Some("AB")
+        );
+        assert_eq!(maker_note_content_identifier(&maker_note("")), None);
+    }
+
+    #[test]
+    fn rejects_truncated_maker_notes() {
+        let note = maker_note("8C8B5B2B-1A8C-4C3A-9F5E-2E1D0C9B8A7F");
+        for length in 0..note.len() {
+            assert_eq!(
+                maker_note_content_identifier(&note[..length]),
+                None,
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_offsets_and_headers() {
+        let note = maker_note("8C8B5B2B-1A8C-4C3A-9F5E-2E1D0C9B8A7F");
+        // The offset of the value is in the last four bytes of the second entry.
+        let offset = APPLE_MAKER_NOTE_HEADER.len() + 4 + 2 + 12 + 8;
+        for wrong_offset in [note.len() as u32 - 1, u32::MAX] {
+            let mut wrong = note.clone();
+            wrong[offset..offset + 4].copy_from_slice(&wrong_offset.to_be_bytes());
+            assert_eq!(maker_note_content_identifier(&wrong), None);
+        }
+        let mut wrong_byte_order = note.clone();
+        wrong_byte_order[12..14].copy_from_slice(b"XX");
+        assert_eq!(maker_note_content_identifier(&wrong_byte_order), None);
+        assert_eq!(maker_note_content_identifier(&note[1..]), None);
+    }
+
+    #[test]
+    fn reads_content_identifiers_of_images_and_videos() {
+        let folder = std::env::temp_dir().join(format!("live_photo_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let (image, video) = (folder.join("IMG_0001.JPG"), folder.join("IMG_0001.MOV"));
+        fs::write(&image, live_photo_image("ABC-123")).unwrap();
+        fs::write(&video, live_photo_video("ABC-123")).unwrap();
+        assert_eq!(read_content_identifier(&image).as_deref(), Some("ABC-123"));
+        assert_eq!(read_content_identifier(&video).as_deref(), Some("ABC-123"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -1,614 +1,730 @@
-use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime};
-use exif::{In, Tag};
+mod cli;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::Parser;
+use cli::{Cli, Command, InspectArgs, ScanArgs, SortArgs, StatsArgs, UndoArgs, VerifyArgs};
 use human_bytes::human_bytes;
-use regex::Regex;
-use std::collections::HashSet;
-use std::env;
-use std::ffi::OsStr;
+use image_sorter::{
+    collect_stats, default_config_path, describe_image, find_sidecar, find_takeout_json, hash_file,
+    is_image, is_supported_file_type, is_video, load_config, pixel_count, read_content_identifier,
+    read_embedded_xmp, read_exif, read_image_text, read_journal, read_takeout_metadata,
+    read_video_metadata, takeout_media, undo_run, verify_runs, visit_dirs, xmp_properties,
+    CompareMode, ConfigValues, ConflictResolution, DatePipeline, ExtractedDate, ExtractorSettings,
+    FileCount, JournalAction, JournalEntry, Mode, Options, SimilarImage, SimilarImageResolution,
+    SkipReason, SortEvent, SortHandler, SortOutcome, Sorter, DEFAULT_DATE_SOURCES,
+};
+use std::collections::BTreeMap;
 use std::fs::{self, DirEntry};
-use std::io;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::exit;
-use std::time::UNIX_EPOCH;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let options: Options = parse_options(args);
-
-    let date_regex = Regex::new(r"(?P<y>20[012]\d)\-?(?P<m>[01]\d)\-?(?P<d>\d{2})").unwrap();
-    let mut target_parents = HashSet::new();
-
-    visit_dirs(
-        &options.source_folder,
-        &mut handle_file(&options, &mut target_parents, &date_regex),
-    )
-    .unwrap();
-}
-
-struct Options {
-    pub verbose: bool,
-    pub mode: Mode,
-    pub source_folder: PathBuf,
-    pub target_folder: PathBuf,
-    pub include_unsupported_file_types: bool,
-    pub file_conflict_resolution_mode: FileConflictResolutionMode,
-    pub media_creation_date_file_creation_fallback: bool,
-    pub delete_skipped_source_duplicates: bool,
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Sort(Box::new(cli.sort))) {
+        Command::Sort(args) => sort(*args),
+        Command::Scan(args) => scan(args),
+        Command::Verify(args) => verify(args),
+        Command::Undo(args) => undo(args),
+        Command::Stats(args) => stats(args),
+        Command::Inspect(args) => inspect(args),
+    }
 }
 
-fn parse_options(args: Vec<String>) -> Options {
-    let mut mode = Mode::Move;
-    let mut mode_set = false;
-    let mut verbose: bool = false;
-    let mut source_folder_str = Option::None;
-    let mut target_folder_str = Option::None;
-    let mut file_conflict_resolution_mode = FileConflictResolutionMode::Choose;
-    let mut media_creation_date_file_creation_fallback = false;
-    let mut delete_skipped_source_duplicates = false;
-    let mut include_unsupported_file_types = false;
-
-    let mut skip_read_next_value = true;
-    for (i, arg) in args.iter().enumerate() {
-        if skip_read_next_value {
-            skip_read_next_value = false;
-            continue;
-        }
-        if arg == "--verbose" || arg == "-v" {
-            verbose = true;
-        } else if arg == "--dry-run" || arg == "-d" {
-            if mode_set {
-                exit_with_message::<bool>("Only one mode can be chosen.");
-            }
-            mode = Mode::DryRun;
-            mode_set = true;
-        } else if arg == "--copy" || arg == "-c" {
-            if mode_set {
-                exit_with_message::<bool>("Only one mode can be chosen.");
-            }
-            mode = Mode::Copy;
-            mode_set = true;
-        } else if arg == "--move" || arg == "-m" {
-            if mode_set {
-                exit_with_message::<bool>("Only one mode can be chosen.");
-            }
-            mode = Mode::Move;
-            mode_set = true;
-        } else if arg == "--target" || arg == "-t" {
-            target_folder_str = args.get(i + 1);
-            skip_read_next_value = true;
-        } else if arg == "--conflict-mode" || arg == "-k" {
-            let cm = args.get(i + 1).map(|s| s.as_str());
-            file_conflict_resolution_mode = match cm {
-                Some("both") => FileConflictResolutionMode::KeepBoth,
-                Some("source") => FileConflictResolutionMode::KeepSource,
-                Some("target") => FileConflictResolutionMode::KeepTarget,
-                _ => FileConflictResolutionMode::Choose,
-            };
-            skip_read_next_value = true;
-        } else if arg == "--file-creation-fallback" || arg == "-s" {
-            media_creation_date_file_creation_fallback = true
-        } else if arg == "--delete-skipped-source-duplicates" || arg == "-q" {
-            delete_skipped_source_duplicates = true
-        } else if arg == "--include-unsupported-file-types" || arg == "-u" {
-            include_unsupported_file_types = true
-        } else {
-            if source_folder_str.is_none() {
-                source_folder_str = Option::Some(arg.to_owned());
-            } else {
-                exit_with_message::<bool>("Too many arguments given.");
-            }
-        }
+fn sort(args: SortArgs) {
+    let config = match args
+        .config
+        .clone()
+        .or_else(|| default_config_path().filter(|p| p.exists()))
+    {
+        Some(path) => load_config(&path, args.profile.as_deref()),
+        None => match &args.profile {
+            Some(profile) => Err(format!(
+                "Profile {:?} can't be used without a config file.",
+                profile
+            )),
+            None => Ok(ConfigValues::default()),
+        },
     }
-
-    let source_folder = Path::new(source_folder_str.get_or_insert(".".to_string())).to_path_buf();
-    let target_folder = target_folder_str
-        .map(|s| Path::new(s))
-        .unwrap_or_else(|| exit_with_message("No target folder supplied."))
-        .to_path_buf();
-
-    if !target_folder.exists() {
-        exit_with_message::<bool>(
-            "Target folder does not exists or you are missing the required permissions.",
+    .unwrap_or_else(|e| exit_with_message(&e));
+    let values = args.config_values().or(config);
+    let verbose = values.verbose.unwrap_or(false);
+    let options = Options::from_config(values).unwrap_or_else(|e| exit_with_message(&e));
+
+    let mode = options.mode();
+    let compare_mode = options.compare_mode();
+    let target_folder = options.target_folder().to_path_buf();
+    let summary = Sorter::new(options)
+        .run(&mut ConsoleHandler {
+            verbose,
+            mode,
+            compare_mode,
+        })
+        .unwrap_or_else(|e| exit_with_message(&e));
+    if let Mode::Move | Mode::Copy = mode {
+        println!(
+            "Finished run {}. It can be reversed with: undo {} --target {:?}",
+            summary.run_id, summary.run_id, target_folder
         );
     }
-
-    Options {
-        verbose,
-        mode,
-        source_folder,
-        target_folder,
-        file_conflict_resolution_mode,
-        media_creation_date_file_creation_fallback,
-        delete_skipped_source_duplicates,
-        include_unsupported_file_types,
-    }
 }
 
-fn exit_with_message<T>(message: &str) -> T {
-    println!("{}", message);
-    exit(1);
+/// Prints the events of a run and asks on the console for decisions.
+struct ConsoleHandler {
+    verbose: bool,
+    mode: Mode,
+    compare_mode: CompareMode,
 }
 
-fn visit_dirs(dir: &Path, cb: &mut dyn FnMut(&DirEntry)) -> io::Result<()> {
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                visit_dirs(&path, cb)?;
-            } else {
-                cb(&entry);
+impl SortHandler for ConsoleHandler {
+    fn event(&mut self, event: SortEvent) {
+        let dry_run = self.mode == Mode::DryRun;
+        match event {
+            SortEvent::RunStarted { run_id } => println!("Starting run {}.", run_id),
+            SortEvent::RunResumed { run_id, done } => println!(
+                "Resuming run {}, {} files were already handled.",
+                run_id, done
+            ),
+            SortEvent::TargetIndexed { files } => {
+                println!("Indexed {} files of the target folder.", files)
             }
-        }
-    }
-    Ok(())
-}
-
-fn handle_file<'a>(
-    options: &'a Options,
-    target_parents: &'a mut HashSet<PathBuf>,
-    date_regex: &'a Regex,
-) -> impl FnMut(&DirEntry) + 'a {
-    move |dir_entry: &DirEntry| -> () {
-        let source_path = dir_entry.path();
-
-        if is_supported_file_type(&source_path) || options.include_unsupported_file_types {
-            let date_time = extract_date_time(
-                &source_path,
-                date_regex,
-                options.media_creation_date_file_creation_fallback,
-                options.verbose,
-            );
-            match date_time.and_then(|date_time| {
-                sort_file(options, &source_path, target_parents, &date_time)
-            }) {
-                Ok(Some(target_file)) => {
-                    let parent = target_file
-                        .parent()
-                        .expect("File and parent exist")
-                        .to_owned()
-                        .clone();
-                    if !target_parents.contains(&parent) {
-                        target_parents.insert(parent);
-                    }
+            SortEvent::TargetImagesHashed { images } => {
+                println!("Hashed {} images of the target folder.", images)
+            }
+            SortEvent::TargetImageNotHashed { path, error } => {
+                if self.verbose {
+                    println!("Could not hash image {:?}: {}", path, error);
                 }
-                Ok(None) => {
-                    if options.verbose {
-                        println!("Skipped file.");
-                    }
+            }
+            SortEvent::FileFound { source } => {
+                println!("---------------");
+                if self.verbose {
+                    println!("Found file {:?}.", source);
                 }
-                Err(e) => {
-                    println!("Error in {:?}: {}", source_path, e);
+            }
+            SortEvent::CaptureDateFound { source, date } => {
+                if self.verbose {
+                    println!(
+                        "Image {:?} was taken at DateTime {} ({})",
+                        source, date.capture_info.date_time, date.source
+                    )
                 }
             }
-        } else {
-            if options.verbose {
-                println!("=========");
-                println!("File {:?} is not a supported file type", source_path);
+            SortEvent::Renamed { source, file_name } => {
+                if self.verbose || dry_run {
+                    println!("Renaming {:?} to {:?}", source, file_name);
+                }
+            }
+            SortEvent::NameCollision { source, target } => {
+                println!("Filename collision detected.");
+                println!(
+                    "The file {:?} already exists at target {:?}",
+                    source, target
+                );
+            }
+            SortEvent::SimilarImageFound { source, similar } => {
+                println!("Similar image detected.");
+                println!(
+                    "The image {:?} looks like the existing image {:?} (distance {}).",
+                    source, similar.path, similar.distance
+                );
+            }
+            SortEvent::FolderCreated { path } => {
+                if self.verbose {
+                    println!("Created folder {:?}", path);
+                }
+            }
+            SortEvent::SourceDeleted { source } => {
+                if dry_run {
+                    println!("Dry run: Deleting skipped source file {:?}", source)
+                } else if self.verbose {
+                    println!("Deleted skipped source file {:?}", source);
+                }
+            }
+            SortEvent::CompanionSorted { source, target, .. } => match self.mode {
+                Mode::DryRun => println!(
+                    "Dry run: Copy/Move companion file {:?} to target {:?}",
+                    source, target
+                ),
+                Mode::Move if self.verbose => {
+                    println!("Moved companion file {:?} to target {:?}", source, target)
+                }
+                Mode::Copy if self.verbose => {
+                    println!("Copied companion file {:?} to target {:?}", source, target)
+                }
+                _ => {}
+            },
+            SortEvent::TargetDeleted { target } => {
+                if dry_run {
+                    println!("Dry run: Deleting replaced target file {:?}", target)
+                } else if self.verbose {
+                    println!("Deleted replaced target file {:?}", target);
+                }
             }
+            SortEvent::Handled(outcome) => self.print_outcome(outcome),
+            SortEvent::Warning { message } => println!("{}", message),
         }
     }
-}
 
-fn is_supported_file_type(source_path: &PathBuf) -> bool {
-    let is_supported = source_path
-        .extension()
-        .and_then(OsStr::to_str)
-        .filter(|&e| ["png", "jpg", "jpeg", "tif", "mp4", "mov"].contains(&e.to_lowercase().as_str()))
-        .is_some();
-    is_supported
-}
+    fn resolve_conflict(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        alternative: &Path,
+    ) -> ConflictResolution {
+        choose_conflict_resolution(source, target, alternative)
+    }
 
-fn sort_file(
-    options: &Options,
-    source_path: &PathBuf,
-    target_parents: &HashSet<PathBuf>,
-    date_time: &NaiveDateTime,
-) -> Result<Option<PathBuf>, String> {
-    println!("---------------");
-    if options.verbose {
-        println!("Found file {:?}.", source_path);
+    fn resolve_similar_image(
+        &mut self,
+        source: &Path,
+        similar: &SimilarImage,
+    ) -> SimilarImageResolution {
+        choose_similar_image_resolution(source, &similar.path)
     }
-    let target_path_unverified = options
-        .target_folder
-        .join(date_time.year().to_string())
-        .join(date_time.month().to_string())
-        .join(
-            source_path
-                .file_name()
-                .expect("we only supply valid files."),
-        );
 
-    let path_check_result =
-        validate_and_resolve_path_problems(options, target_path_unverified, source_path)?;
-    match path_check_result {
-        Some(valid_path) => {
-            match options.mode {
+    fn capture_date(&mut self, source: &Path, guess: &ExtractedDate) -> Option<NaiveDateTime> {
+        ask_for_media_creation_date(source, guess.capture_info.date_time)
+    }
+}
+
+impl ConsoleHandler {
+    fn print_outcome(&self, outcome: SortOutcome) {
+        match outcome {
+            SortOutcome::Sorted { source, target } => match self.mode {
                 Mode::DryRun => println!(
                     "Dry run: Copy/Move source file {:?} to target {:?}",
-                    source_path, valid_path
+                    source, target
                 ),
-                Mode::Move => {
-                    handle_missing_parents(options.verbose, &valid_path, target_parents)?;
-                    if options.verbose {
-                        println!(
-                            "Moving source file {:?} to target {:?}",
-                            source_path, valid_path
-                        );
-                    }
-                    fs::rename(&source_path, &valid_path).map_err(|e| e.to_string())?;
+                Mode::Move if self.verbose => {
+                    println!("Moved source file {:?} to target {:?}", source, target)
                 }
-                Mode::Copy => {
-                    handle_missing_parents(options.verbose, &valid_path, target_parents)?;
-                    if options.verbose {
-                        println!(
-                            "Copying source file {:?} to target {:?}",
-                            source_path, valid_path
-                        );
-                    }
-                    fs::copy(&source_path, &valid_path).map_err(|e| e.to_string())?;
+                Mode::Copy if self.verbose => {
+                    println!("Copied source file {:?} to target {:?}", source, target)
+                }
+                _ => {}
+            },
+            SortOutcome::Skipped {
+                source,
+                reason: SkipReason::AlreadyHandled,
+            } => {
+                if self.verbose {
+                    println!(
+                        "Skipping {:?}, it was handled before the run was interrupted.",
+                        source
+                    );
+                }
+            }
+            SortOutcome::Skipped {
+                source,
+                reason: SkipReason::Duplicate { existing },
+            } => match self.compare_mode {
+                CompareMode::Size => println!(
+                    "A file of the same size as {:?} already exists at target {:?}",
+                    source, existing
+                ),
+                CompareMode::Hash | CompareMode::Bytes => println!(
+                    "The content of {:?} already exists at target {:?}",
+                    source, existing
+                ),
+            },
+            SortOutcome::Skipped {
+                source,
+                reason: SkipReason::KeptTarget { target },
+            } => {
+                if self.verbose {
+                    println!("Skipping file {:?}, keeping {:?}", source, target);
+                }
+            }
+            SortOutcome::Skipped {
+                source,
+                reason: SkipReason::TakeoutMetadata { media },
+            } => {
+                if self.verbose {
+                    println!(
+                        "Skipping {:?}, it is the Takeout metadata of {:?}",
+                        source, media
+                    );
+                }
+            }
+            SortOutcome::Skipped {
+                source,
+                reason: SkipReason::Companion { primary },
+            } => {
+                if self.verbose {
+                    println!(
+                        "Skipping {:?}, it is a companion file of {:?}",
+                        source, primary
+                    );
+                }
+            }
+            SortOutcome::Unsupported { source } => {
+                if self.verbose {
+                    println!("=========");
+                    println!("File {:?} is not a supported file type", source);
                 }
             }
-            Ok(Some(valid_path))
+            SortOutcome::Failed { source, error } => {
+                println!("Error in {:?}: {}", source, error);
+            }
         }
-        None => Ok(None),
     }
 }
 
-fn validate_and_resolve_path_problems(
-    options: &Options,
-    target_path_unverified: PathBuf,
-    source_path: &PathBuf,
-) -> Result<Option<PathBuf>, String> {
-    if target_path_unverified.exists() {
-        match handle_file_exists_at_target(
-            &source_path,
-            &target_path_unverified,
-            &options.file_conflict_resolution_mode,
-            options.verbose,
-        ) {
-            Some(path_resolution) => {
-                validate_and_resolve_path_problems(options, path_resolution, source_path)
-            }
-            None => {
-                // None means file move/copy is skipped
-                if options.delete_skipped_source_duplicates {
-                    match options.mode {
-                        Mode::DryRun => {
-                            println!("Dry run: Deleting skipped source file {:?}", source_path)
-                        }
-                        Mode::Move => {
-                            if options.verbose {
-                                println!("Deleting skipped source file {:?}", source_path);
-                            }
-                            fs::remove_file(source_path).map_err(|e| e.to_string())?;
-                        }
-                        _ => {}
-                    }
-                }
-                Ok(None)
-            }
+fn undo(options: UndoArgs) {
+    println!("Undoing run {}.", options.run_id);
+    let report = undo_run(&options.target, &options.run_id, options.dry_run)
+        .unwrap_or_else(|e| exit_with_message(&e));
+    let prefix = if options.dry_run { "Dry run: " } else { "" };
+    if options.verbose || options.dry_run {
+        for entry in &report.undone {
+            println!("{}{}", prefix, describe_undo(entry));
         }
+    }
+    for entry in &report.overwritten {
+        println!(
+            "{}{}, the file it had replaced there can't be restored",
+            prefix,
+            describe_undo(entry)
+        );
+    }
+    let failures = report.failures;
+    for (entry, reason) in &failures {
+        println!(
+            "Could not undo {:?} of {:?}: {}",
+            entry.action, entry.source, reason
+        );
+    }
+    if failures.is_empty() {
+        println!("Run {} was reversed.", options.run_id);
     } else {
-        Ok(Some(target_path_unverified))
+        println!(
+            "Run {} was reversed except for {} entries.",
+            options.run_id,
+            failures.len()
+        );
     }
 }
 
-fn handle_missing_parents<'a>(
-    verbose: bool,
-    target_path: &'a PathBuf,
-    target_parents: &HashSet<PathBuf>,
-) -> Result<(), String> {
-    let parent = target_path.parent().expect("is valid.");
-    Ok(if !target_parents.contains(&parent.to_path_buf()) {
-        if verbose {
-            println!("Creating folder {:?}", parent);
+fn describe_undo(entry: &JournalEntry) -> String {
+    match (entry.action, &entry.destination) {
+        (JournalAction::Move, Some(destination)) => {
+            format!("Move {:?} back to {:?}", destination, entry.source)
         }
-        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
-    })
+        (JournalAction::Copy, Some(destination)) => format!("Delete copy {:?}", destination),
+        _ => format!("Undo {:?} of {:?}", entry.action, entry.source),
+    }
 }
 
-fn extract_date_time(
-    path: &PathBuf,
-    date_regex: &Regex,
-    file_creation_fallback: bool,
-    verbose: bool,
-) -> Result<NaiveDateTime, String> {
-    let result_from_media_metadata = if is_image(path) {
-        let exifreader = exif::Reader::new();
-        std::fs::File::open(path)
-            .map_err(|e| e.to_string())
-            .map(|inner| std::io::BufReader::new(inner))
-            .and_then(|mut inner| {
-                exifreader
-                    .read_from_container(&mut inner)
-                    .map_err(|e| e.to_string())
-            })
-            .and_then(|inner| {
-                inner
-                    .get_field(Tag::DateTimeOriginal, In::PRIMARY)
-                    .or(inner.get_field(Tag::DateTime, In::PRIMARY))
-                    .or(inner.get_field(Tag::DateTimeDigitized, In::PRIMARY))
-                    .map(|field| field.display_value().to_string())
-                    .ok_or("DateTime tag is missing.".to_string())
-            })
-            .and_then(|inner| {
-                NaiveDateTime::parse_from_str(inner.as_str().trim(), "%Y-%m-%d %H:%M:%S")
-                    .map_err(|e| e.to_string())
-            })
-    } else if is_video(path) {
-        ffprobe::ffprobe(path)
-            .map_err(|e| e.to_string())?
-            .format
-            .tags
-            .ok_or("Can't rad mp4 creation date time.".to_string())
-            .and_then(|tag| {
-                tag.creation_time
-                    .ok_or("Can't read mp4 creation date time.".to_string())
-            })
-            .and_then(|str| DateTime::parse_from_rfc3339(str.as_str()).map_err(|e| e.to_string()))
-            .map(|date_time| date_time.naive_local())
-    } else {
-        Err("Unsupported File Type".to_string())
-    };
+fn verify(options: VerifyArgs) {
+    let (checked, failures) = verify_runs(&options.target, options.run_id.as_deref())
+        .unwrap_or_else(|e| exit_with_message(&e));
+    for (entry, reason) in &failures {
+        println!(
+            "{:?} of {:?} in run {}: {}",
+            entry.action, entry.source, entry.run_id, reason
+        );
+    }
+    if options.verbose || failures.is_empty() {
+        println!(
+            "Verified {} files, {} are missing or modified.",
+            checked,
+            failures.len()
+        );
+    }
+    if !failures.is_empty() {
+        exit_with_message::<bool>(&format!(
+            "{} of {} files are missing or modified.",
+            failures.len(),
+            checked
+        ));
+    }
+}
 
-    let result = result_from_media_metadata
-        .ok()
-        .or_else(extract_media_creation_time_from_filename(date_regex, path))
-        .or_else(extract_media_creation_time_from_file_metadata(
-            path,
-            file_creation_fallback,
+fn stats(options: StatsArgs) {
+    let stats = collect_stats(&options.target).unwrap_or_else(|e| {
+        exit_with_message(&format!(
+            "Can't read target folder {:?}: {}",
+            options.target, e
         ))
-        .ok_or("Could not determine a media file creation date.".to_owned());
-
-    if let Ok(date_time) = result {
-        if verbose {
-            println!("Image {:?} was taken at DateTime {}", path, date_time)
+    });
+    let describe = |count: &FileCount| {
+        format!(
+            "{} files ({})",
+            count.files,
+            human_bytes(count.bytes as f64)
+        )
+    };
+    println!("Target folder {:?}", options.target);
+    println!("Total:  {}", describe(&stats.total));
+    println!("Images: {}", describe(&stats.images));
+    println!("Videos: {}", describe(&stats.videos));
+    println!("Other:  {}", describe(&stats.other));
+    println!("Folders:");
+    for (folder, count) in &stats.folders {
+        let folder = if folder.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            folder.as_path()
+        };
+        println!("  {}: {}", folder.display(), describe(count));
+    }
+    if let Ok(entries) = read_journal(&options.target) {
+        let mut runs: Vec<(String, usize)> = Vec::new();
+        for entry in entries {
+            match runs.last_mut() {
+                Some((run_id, count)) if *run_id == entry.run_id => *count += 1,
+                _ => runs.push((entry.run_id, 1)),
+            }
+        }
+        println!("Runs:");
+        for (run_id, count) in runs {
+            println!("  {}: {} journal entries", run_id, count);
         }
     }
-    result
 }
 
-fn extract_media_creation_time_from_filename<'a>(
-    date_regex: &'a Regex,
-    path: &'a PathBuf,
-) -> impl FnOnce() -> Option<NaiveDateTime> + 'a {
-    || {
-        let file_name = &path.file_name().map(|s| s.to_str()).unwrap().unwrap();
-        match date_regex.captures_iter(file_name).count() {
-            1 => date_regex
-                .captures(file_name)
-                .filter(|c| c.len() == 4)
-                .and_then(|c| {
-                    c.name("y")
-                        .and_then(|s| s.as_str().parse::<i32>().ok())
-                        .zip(c.name("m").and_then(|s| s.as_str().parse::<i32>().ok()))
-                })
-                .map(|s| {
-                    NaiveDateTime::new(
-                        NaiveDate::from_ymd(s.0, s.1 as u32, 1),
-                        NaiveTime::from_hms(0, 0, 0),
-                    )
-                }),
-            _ => None,
+fn scan(options: ScanArgs) {
+    let settings = ExtractorSettings {
+        video_backend: options.video_backend,
+        time_zone: options.timezone,
+        filename_patterns: options.filename_patterns,
+        filename_years: options.filename_years,
+    };
+    let pipeline = DatePipeline::from_sources(&options.date_sources, &settings);
+    let mut dated: BTreeMap<String, usize> = BTreeMap::new();
+    let mut undated = 0;
+    let mut unsupported = 0;
+    let mut takeout_metadata = 0;
+    visit_dirs(&options.source, &mut |dir_entry: &DirEntry| {
+        let path = dir_entry.path();
+        if pipeline.uses("takeout") && takeout_media(&path).is_some() {
+            takeout_metadata += 1;
+            return;
+        }
+        if !is_supported_file_type(&path) {
+            unsupported += 1;
+            if options.include_unsupported_file_types {
+                println!("{:?}: unsupported file type", path);
+            }
+            return;
+        }
+        match pipeline.extract(&path) {
+            Ok(date) => {
+                *dated.entry(date.source.clone()).or_default() += 1;
+                println!("{:?}: {}", path, describe_date(&date));
+            }
+            Err(e) => {
+                undated += 1;
+                println!("{:?}: no capture date, {}", path, e);
+            }
         }
+    })
+    .unwrap_or_else(|e| exit_with_message(&format!("Can't read {:?}: {}", options.source, e)));
+    println!(
+        "{} media files with capture date, {} without, {} unsupported files.",
+        dated.values().sum::<usize>(),
+        undated,
+        unsupported
+    );
+    if takeout_metadata > 0 {
+        println!("{} Takeout metadata files.", takeout_metadata);
+    }
+    for (source, count) in &dated {
+        println!("  {}: {}", source, count);
     }
 }
 
-fn extract_media_creation_time_from_file_metadata<'a>(
-    path: &'a PathBuf,
-    file_creation_fallback: bool,
-) -> impl FnOnce() -> Option<NaiveDateTime> + 'a {
-    move || {
-        let file_creation_date = path
-            .metadata()
-            .and_then(|m| m.modified().or(m.created()))
-            .ok()
-            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
-            .and_then(|duration| NaiveDateTime::from_timestamp_opt(duration.as_secs() as i64, 0));
-
-        if file_creation_fallback && file_creation_date.is_some() {
-            return file_creation_date;
+fn inspect(options: InspectArgs) {
+    for path in &options.files {
+        println!("{:?}", path);
+        let metadata = match path.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                println!("  Can't read the file: {}", e);
+                continue;
+            }
+        };
+        println!("  Size: {}", human_bytes(metadata.len() as f64));
+        if let Ok(hash) = hash_file(path) {
+            println!("  Hash: {}", hash.to_hex());
         }
-
-        match file_creation_date {
-            Some(date) => {
-                println!(
-                    "Could not determine creation time of media file {:?}",
-                    &path
-                );
-                println!("Choose a resolution:");
-                println!("1) Use the file creation time: {:?}", date);
-                println!("2) Enter year and month manually.");
-                println!("3) Skip file. (it will not be deleted if the delete-skipped-source-duplicates flag is set.)");
-                let answer = loop {
-                    let mut input = String::new();
-                    _ = std::io::stdin().read_line(&mut input);
-                    input = input.trim().to_string();
-                    if ["1", "2", "3"].contains(&input.as_str()) {
-                        println!("Your option: {}", input);
-                        break input;
-                    } else {
-                        println!("Invalid option {}. Choose 1, 2 or 3", input)
-                    }
-                };
-                if "1" == answer {
-                    return Some(date);
-                } else if "2" == answer {
-                    println!("Enter the year as number, e.g. 2022");
-                    let year = loop {
-                        let mut input = String::new();
-                        _ = std::io::stdin().read_line(&mut input);
-                        input = input.trim().to_string();
-                        if input.len() != 4 {
-                            println!(
-                                "Invalid input {}. expected a 4 digit number, e.g. 2022",
-                                input
-                            );
-                            continue;
-                        }
-                        if let Ok(year) = input.parse::<i32>() {
-                            println!("Your option: {}", input);
-                            break year;
-                        } else {
-                            println!("Invalid input {}. expected a number, e.g. 2022", input)
-                        }
-                    };
-                    println!("Enter the month as number, e.g. 12");
-                    let month = loop {
-                        let mut input = String::new();
-                        _ = std::io::stdin().read_line(&mut input);
-                        input = input.trim().to_string();
-                        if input.len() != 2 {
-                            println!(
-                                "Invalid input {}. expected a two digit number, e.g. 12",
-                                input
-                            );
-                            continue;
-                        }
-                        if let Ok(month) = input.parse::<u32>() {
-                            println!("Your option: {}", input);
-                            break month;
-                        } else {
-                            println!("Invalid input {}. expected a number, e.g. 12", input)
-                        }
-                    };
-                    return Some(NaiveDateTime::new(
-                        NaiveDate::from_ymd(year, month, 1),
-                        NaiveTime::from_hms(0, 0, 0),
-                    ));
-                } else if "3" == answer {
-                    None
-                } else {
-                    panic!("Unreachable.")
+        if is_image(path) {
+            println!("  Image: {}", describe_image(path));
+        }
+        if let Some(identifier) = read_content_identifier(path) {
+            println!("  Live Photo content identifier: {}", identifier);
+        }
+        println!("  Capture dates:");
+        for source in DEFAULT_DATE_SOURCES {
+            let extractor = source.extractor(&ExtractorSettings::default());
+            match extractor.extract(path) {
+                Ok(date) => println!("    {}: {}", extractor.name(), describe_date(&date)),
+                Err(e) => println!("    {}: {}", extractor.name(), e),
+            }
+        }
+        if options.verbose {
+            if let Ok(exif) = read_exif(path) {
+                println!("  EXIF:");
+                for field in exif.fields() {
+                    println!(
+                        "    {} ({}): {}",
+                        field.tag,
+                        field.ifd_num,
+                        field.display_value().with_unit(&exif)
+                    );
+                }
+            }
+            let text = read_image_text(path).map(|text| text.entries);
+            if let Some(entries) = text.ok().filter(|entries| !entries.is_empty()) {
+                println!("  Text:");
+                for (keyword, value) in &entries {
+                    println!("    {}: {}", keyword, value);
+                }
+            }
+            if let Some(xmp) = is_image(path)
+                .then(|| read_embedded_xmp(path).ok())
+                .flatten()
+            {
+                println!("  XMP:");
+                print_xmp(&xmp);
+            }
+            let sidecar = find_sidecar(path)
+                .and_then(|sidecar| Some((fs::read_to_string(&sidecar).ok()?, sidecar)));
+            if let Some((xmp, sidecar)) = sidecar {
+                println!("  XMP sidecar {:?}:", sidecar);
+                print_xmp(&xmp);
+            }
+            let takeout = find_takeout_json(path)
+                .and_then(|json| Some((read_takeout_metadata(&json).ok()?, json)));
+            if let Some((takeout, json)) = takeout {
+                println!("  Takeout {:?}:", json);
+                if let Some(title) = takeout.title {
+                    println!("    Title: {}", title);
+                }
+                if let Some(photo_taken_time) = takeout.photo_taken_time {
+                    println!("    Photo taken time: {} UTC", photo_taken_time);
+                }
+                if let Some((latitude, longitude)) = takeout.location {
+                    println!("    Location: {}, {}", latitude, longitude);
+                }
+            }
+            let video = is_video(path).then(|| read_video_metadata(path).ok());
+            if let Some(video) = video.flatten() {
+                println!("  Video:");
+                if let Some(creation_time) = video.creation_time {
+                    println!("    Movie creation time: {} UTC", creation_time);
+                }
+                if let Some(creation_time) = video.track_creation_time {
+                    println!("    Track creation time: {} UTC", creation_time);
+                }
+                if let Some(recording_time) = video.recording_time {
+                    println!("    Recording time: {}", recording_time);
+                }
+                for (tag, value) in &video.tags {
+                    println!("    {}: {}", tag, value);
                 }
             }
-            None => None,
         }
     }
 }
 
-fn is_image(path: &PathBuf) -> bool {
-    path.extension()
-        .and_then(OsStr::to_str)
-        .filter(|&e| ["png", "jpg", "jpeg", "tif"].contains(&e.to_lowercase().as_str()))
-        .is_some()
+fn print_xmp(packet: &str) {
+    for (property, value) in xmp_properties(packet) {
+        println!("    {}: {}", property, value);
+    }
 }
 
-fn is_video(path: &PathBuf) -> bool {
-    path.extension()
-        .and_then(OsStr::to_str)
-        .filter(|&e| ["mp4", "mov"].contains(&e.to_lowercase().as_str()))
-        .is_some()
+fn describe_date(date: &ExtractedDate) -> String {
+    let capture_info = &date.capture_info;
+    let mut description = capture_info.date_time.to_string();
+    if let Some(subsec) = &capture_info.subsec {
+        description.push_str(&format!(".{}", subsec));
+    }
+    if let Some(offset) = &capture_info.offset {
+        description.push_str(&format!(" {}", offset));
+    }
+    if let Some(camera) = &capture_info.camera {
+        description.push_str(&format!(", {}", camera));
+    }
+    format!(
+        "{} (from {}, {:?} precision, {:?} confidence)",
+        description, date.source, date.precision, date.confidence
+    )
 }
 
-fn handle_file_exists_at_target(
-    source_path: &PathBuf,
-    target_path: &PathBuf,
-    conflict_mode: &FileConflictResolutionMode,
-    verbose: bool,
-) -> Option<PathBuf> {
-    println!("Filename collision detected.");
+fn exit_with_message<T>(message: &str) -> T {
+    println!("{}", message);
+    exit(1);
+}
+
+fn ask_for_media_creation_date(path: &Path, date: NaiveDateTime) -> Option<NaiveDateTime> {
     println!(
-        "The file {:?} already exists at target {:?}",
-        source_path, target_path
+        "Could not determine creation time of media file {:?}",
+        &path
     );
-    if source_path.metadata().unwrap().len() == target_path.metadata().unwrap().len() {
-        if verbose {
-            println!("Skipping the file {:?} because they already existing file has the same size and is likely same.", source_path);
+    println!("Choose a resolution:");
+    println!("1) Use the file creation time: {:?}", date);
+    println!("2) Enter year and month manually.");
+    println!("3) Skip file. (it will not be deleted if the delete-skipped-source-duplicates flag is set.)");
+    let answer = loop {
+        let mut input = String::new();
+        _ = std::io::stdin().read_line(&mut input);
+        input = input.trim().to_string();
+        if ["1", "2", "3"].contains(&input.as_str()) {
+            println!("Your option: {}", input);
+            break input;
+        } else {
+            println!("Invalid option {}. Choose 1, 2 or 3", input)
         }
-        return None;
-    } else {
-        let alternative_new_path = create_alternative_path(&target_path);
-        match conflict_mode {
-            FileConflictResolutionMode::Choose => {
-                println!("Choose a resolution:");
+    };
+    if "1" == answer {
+        Some(date)
+    } else if "2" == answer {
+        println!("Enter the year as number, e.g. 2022");
+        let year = loop {
+            let mut input = String::new();
+            _ = std::io::stdin().read_line(&mut input);
+            input = input.trim().to_string();
+            if input.len() != 4 {
                 println!(
-                    "1) Override the target file with the source file (Size {:?}).",
-                    human_bytes(
-                        source_path
-                            .metadata()
-                            .expect("File should always exist")
-                            .len() as f64
-                    )
+                    "Invalid input {}. expected a 4 digit number, e.g. 2022",
+                    input
                 );
+                continue;
+            }
+            if let Ok(year) = input.parse::<i32>() {
+                println!("Your option: {}", input);
+                break year;
+            } else {
+                println!("Invalid input {}. expected a number, e.g. 2022", input)
+            }
+        };
+        println!("Enter the month as number, e.g. 12");
+        let date = loop {
+            let mut input = String::new();
+            _ = std::io::stdin().read_line(&mut input);
+            input = input.trim().to_string();
+            if input.len() != 2 {
                 println!(
-                "2) Skip the source file and keep the file (Size: {:?}) at the target location. (will delete source file if delete-skipped-source-duplicates flag is set)",
-                human_bytes(
-                    target_path
-                        .metadata()
-                        .expect("File should always exist")
-                        .len() as f64
-                )
-            );
-                println!(
-                    "3) Both files. The source file would be renamed to {:?}",
-                    alternative_new_path
-                        .file_name()
-                        .expect("Should always be a valid filename")
-                        .to_str()
-                        .expect("Should always be a valid filename")
+                    "Invalid input {}. expected a two digit number, e.g. 12",
+                    input
                 );
-                let answer = loop {
-                    let mut input = String::new();
-                    _ = std::io::stdin().read_line(&mut input);
-                    input = input.trim().to_string();
-                    if ["1", "2", "3"].contains(&input.as_str()) {
-                        println!("Your option: {}", input);
-                        break input;
-                    } else {
-                        println!("Invalid option {}. Choose 1, 2 or 3.", input)
-                    }
-                };
-                if "1" == answer {
-                    return Some(target_path.to_owned());
-                } else if "2" == answer {
-                    if verbose {
-                        println!("Skipping file {:?}", source_path);
-                    }
-                    return None;
-                } else if "3" == answer {
-                    return Some(alternative_new_path);
-                } else {
-                    panic!("Unreachable.")
-                }
+                continue;
             }
-            FileConflictResolutionMode::KeepSource => Some(target_path.to_owned()),
-            FileConflictResolutionMode::KeepTarget => None,
-            FileConflictResolutionMode::KeepBoth => Some(alternative_new_path),
-        }
+            let date = input
+                .parse::<u32>()
+                .ok()
+                .and_then(|month| NaiveDate::from_ymd_opt(year, month, 1));
+            if let Some(date) = date {
+                println!("Your option: {}", input);
+                break date;
+            } else {
+                println!("Invalid input {}. expected a number, e.g. 12", input)
+            }
+        };
+        date.and_hms_opt(0, 0, 0)
+    } else if "3" == answer {
+        None
+    } else {
+        panic!("Unreachable.")
     }
 }
 
-fn create_alternative_path(path: &PathBuf) -> PathBuf {
-    let new_name = path
-        .file_stem()
-        .expect("Should always have a file stem.")
-        .to_str()
-        .expect("Should always have a file stem.")
-        .to_owned()
-        + "_new";
-    change_file_name(path, new_name.as_str())
-}
-
-fn change_file_name(path: &PathBuf, name: &str) -> PathBuf {
-    let mut result = path.to_owned();
-    result.set_file_name(name);
-    if let Some(ext) = path.extension() {
-        result.set_extension(ext);
+fn choose_conflict_resolution(
+    source_path: &Path,
+    target_path: &Path,
+    alternative_new_path: &Path,
+) -> ConflictResolution {
+    println!("Choose a resolution:");
+    println!(
+        "1) Override the target file with the source file (Size {:?}).",
+        human_bytes(
+            source_path
+                .metadata()
+                .expect("File should always exist")
+                .len() as f64
+        )
+    );
+    println!(
+        "2) Skip the source file and keep the file (Size: {:?}) at the target location. (will delete source file if delete-skipped-source-duplicates flag is set)",
+        human_bytes(
+            target_path
+                .metadata()
+                .expect("File should always exist")
+                .len() as f64
+        )
+    );
+    println!(
+        "3) Both files. The source file would be renamed to {:?}",
+        alternative_new_path
+            .file_name()
+            .expect("Should always be a valid filename")
+            .to_str()
+            .expect("Should always be a valid filename")
+    );
+    let answer = loop {
+        let mut input = String::new();
+        _ = std::io::stdin().read_line(&mut input);
+        input = input.trim().to_string();
+        if ["1", "2", "3"].contains(&input.as_str()) {
+            println!("Your option: {}", input);
+            break input;
+        } else {
+            println!("Invalid option {}. Choose 1, 2 or 3.", input)
+        }
+    };
+    if "1" == answer {
+        ConflictResolution::OverrideTarget
+    } else if "2" == answer {
+        ConflictResolution::SkipSource
+    } else if "3" == answer {
+        ConflictResolution::KeepBoth
+    } else {
+        panic!("Unreachable.")
     }
-    result
 }
 
-enum Mode {
-    DryRun,
-    Move,
-    Copy,
-}
-
-enum FileConflictResolutionMode {
-    Choose,
-    KeepSource,
-    KeepTarget,
-    KeepBoth,
+fn choose_similar_image_resolution(
+    source_path: &Path,
+    target_path: &Path,
+) -> SimilarImageResolution {
+    let higher_resolution = |path: &Path, other: &Path| {
+        if pixel_count(path) > pixel_count(other) {
+            " Higher resolution."
+        } else {
+            ""
+        }
+    };
+    println!("Choose a resolution:");
+    println!(
+        "1) Replace the target image with the source image ({}).{}",
+        describe_image(source_path),
+        higher_resolution(source_path, target_path)
+    );
+    println!(
+        "2) Skip the source image and keep the target image ({}).{} (will delete source file if delete-skipped-source-duplicates flag is set)",
+        describe_image(target_path),
+        higher_resolution(target_path, source_path)
+    );
+    println!("3) Keep both images.");
+    let answer = loop {
+        let mut input = String::new();
+        _ = std::io::stdin().read_line(&mut input);
+        input = input.trim().to_string();
+        if ["1", "2", "3"].contains(&input.as_str()) {
+            println!("Your option: {}", input);
+            break input;
+        } else {
+            println!("Invalid option {}. Choose 1, 2 or 3.", input)
+        }
+    };
+    if "1" == answer {
+        SimilarImageResolution::ReplaceTarget
+    } else if "2" == answer {
+        SimilarImageResolution::SkipSource
+    } else if "3" == answer {
+        SimilarImageResolution::KeepBoth
+    } else {
+        panic!("Unreachable.")
+    }
 }
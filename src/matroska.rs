@@ -0,0 +1,247 @@
+use crate::video::VideoMetadata;
+use chrono::DateTime;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const EBML_ID: u32 = 0x1A45_DFA3;
+const SEGMENT_ID: u32 = 0x1853_8067;
+const INFO_ID: u32 = 0x1549_A966;
+const CLUSTER_ID: u32 = 0x1F43_B675;
+const DATE_UTC_ID: u32 = 0x4461;
+/// Text elements of the `Info` element, by their name.
+const TEXT_ELEMENTS: [(u32, &str); 3] = [
+    (0x7BA9, "Title"),
+    (0x4D80, "MuxingApp"),
+    (0x5741, "WritingApp"),
+];
+/// Seconds between 1970-01-01 and 2001-01-01, the epoch of Matroska dates.
+const MATROSKA_EPOCH_OFFSET: i64 = 978_307_200;
+/// Caps the memory used for the `Info` element, which only holds a date and a few titles.
+const MAX_INFO_SIZE: u64 = 1024 * 1024;
+
+/// Reads the `DateUTC` and the titles of the `Info` element of a Matroska (MKV) or WebM file.
+pub(crate) fn read_matroska_metadata(path: &Path) -> Result<VideoMetadata, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    read_matroska(&mut file)
+}
+
+fn read_matroska<R: Read + Seek>(reader: &mut R) -> Result<VideoMetadata, String> {
+    let info = read_info(reader).map_err(|e| e.to_string())?;
+    let info = info.ok_or("No Matroska info element found.".to_string())?;
+
+    let mut metadata = VideoMetadata::default();
+    let mut data = info.as_slice();
+    while let Some((id, size, rest)) = element(data) {
+        let Some(content) = rest.get(..size) else {
+            break;
+        };
+        if id == DATE_UTC_ID && size == 8 {
+            let nanoseconds = i64::from_be_bytes(content.try_into().unwrap());
+            metadata.creation_time = DateTime::from_timestamp(
+                MATROSKA_EPOCH_OFFSET + nanoseconds.div_euclid(1_000_000_000),
+                0,
+            )
+            .map(|date_time| date_time.naive_utc());
+        } else if let Some((_, name)) = TEXT_ELEMENTS.iter().find(|(text_id, _)| *text_id == id) {
+            let text = String::from_utf8_lossy(content)
+                .trim_end_matches('\0')
+                .to_string();
+            metadata.tags.insert(name.to_string(), text);
+        }
+        data = &rest[size..];
+    }
+    Ok(metadata)
+}
+
+/// Skips the children of the segment up to the `Info` element and reads its content. It comes
+/// before the clusters, which may be of unknown size.
+fn read_info<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    match read_element_header(reader)? {
+        Some((EBML_ID, Some(size))) => reader.seek(SeekFrom::Current(size as i64))?,
+        _ => return Ok(None),
+    };
+    if !matches!(read_element_header(reader)?, Some((SEGMENT_ID, _))) {
+        return Ok(None);
+    }
+    while let Some((id, size)) = read_element_header(reader)? {
+        match (id, size) {
+            (INFO_ID, Some(size)) if size <= MAX_INFO_SIZE => {
+                let mut content = vec![0; size as usize];
+                reader.read_exact(&mut content)?;
+                return Ok(Some(content));
+            }
+            (CLUSTER_ID, _) | (_, None) => return Ok(None),
+            (_, Some(size)) => {
+                reader.seek(SeekFrom::Current(size as i64))?;
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The ID and the size of the next element, `None` as size if it is unknown.
+fn read_element_header<R: Read>(reader: &mut R) -> io::Result<Option<(u32, Option<u64>)>> {
+    let mut first = [0; 1];
+    match reader.read_exact(&mut first) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let Some(id) = read_varint(reader, first[0], true)? else {
+        return Ok(None);
+    };
+    reader.read_exact(&mut first)?;
+    let length = first[0].leading_zeros() + 1;
+    let size = read_varint(reader, first[0], false)?;
+    // A size with all bits set means unknown.
+    let size = size.filter(|&size| size != (1 << (7 * length)) - 1);
+    Ok(Some((id as u32, size)))
+}
+
+/// Reads the rest of a variable length integer starting with `first`, optionally keeping the
+/// length marker as IDs do.
+fn read_varint<R: Read>(reader: &mut R, first: u8, keep_marker: bool) -> io::Result<Option<u64>> {
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 {
+        return Ok(None);
+    }
+    let mut rest = [0; 7];
+    reader.read_exact(&mut rest[..length - 1])?;
+    let first = if keep_marker {
+        first
+    } else {
+        first & 0xFF_u8.checked_shr(length as u32).unwrap_or(0)
+    };
+    Ok(Some(
+        rest[..length - 1]
+            .iter()
+            .fold(first as u64, |value, &byte| value << 8 | byte as u64),
+    ))
+}
+
+/// The ID, the size and the data starting with the content of the first element in `data`.
+fn element(data: &[u8]) -> Option<(u32, usize, &[u8])> {
+    let mut reader = data;
+    let (id, size) = read_element_header(&mut reader).ok()??;
+    Some((id, usize::try_from(size?).ok()?, reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// An element with a one byte size.
+    fn element_bytes(id: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut element = id.to_vec();
+        element.push(0x80 | content.len() as u8);
+        element.extend_from_slice(content);
+        element
+    }
+
+    fn info() -> Vec<u8> {
+        // 2020-01-02 03:04:05 UTC in nanoseconds since 2001.
+        let date = 599_627_045_000_000_000_i64.to_be_bytes();
+        [
+            element_bytes(&[0x44, 0x61], &date),
+            element_bytes(&[0x7B, 0xA9], b"Beach\0"),
+            element_bytes(&[0xEC], &[0; 3]),
+            element_bytes(&[0x57, 0x41], b"camera"),
+        ]
+        .concat()
+    }
+
+    fn mkv(segment_children: &[Vec<u8>]) -> Vec<u8> {
+        let mut mkv = element_bytes(&[0x1A, 0x45, 0xDF, 0xA3], &[0x42, 0x86, 0x81, 0x01]);
+        // Segment of unknown size.
+        mkv.extend([
+            0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ]);
+        mkv.extend(segment_children.concat());
+        mkv
+    }
+
+    fn valid_mkv() -> Vec<u8> {
+        mkv(&[
+            element_bytes(&[0x11, 0x4D, 0x9B, 0x74], &[0; 4]),
+            element_bytes(&[0x15, 0x49, 0xA9, 0x66], &info()),
+            vec![0x1F, 0x43, 0xB6, 0x75, 0xFF, 0xA3, 0x81, 0x00],
+        ])
+    }
+
+    fn read(data: &[u8]) -> Result<VideoMetadata, String> {
+        read_matroska(&mut Cursor::new(data))
+    }
+
+    #[test]
+    fn reads_info_element() {
+        let metadata = read(&valid_mkv()).unwrap();
+        assert_eq!(
+            metadata.creation_time.unwrap().to_string(),
+            "2020-01-02 03:04:05"
+        );
+        assert_eq!(
+            metadata.tags.get("Title").map(String::as_str),
+            Some("Beach")
+        );
+        assert_eq!(
+            metadata.tags.get("WritingApp").map(String::as_str),
+            Some("camera")
+        );
+    }
+
+    #[test]
+    fn reads_truncated_files() {
+        let mkv = valid_mkv();
+        for length in 0..mkv.len() {
+            if let Ok(metadata) = read(&mkv[..length]) {
+                assert!(metadata.creation_time.is_some(), "{}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn stops_at_clusters_and_unknown_sizes() {
+        let cluster = vec![0x1F, 0x43, 0xB6, 0x75, 0xFF];
+        let info = element_bytes(&[0x15, 0x49, 0xA9, 0x66], &info());
+        let unknown_size = vec![0x11, 0x4D, 0x9B, 0x74, 0xFF];
+        for children in [vec![cluster, info.clone()], vec![unknown_size, info]] {
+            assert_eq!(read_info(&mut Cursor::new(mkv(&children))).unwrap(), None);
+        }
+        // Not starting with the EBML header.
+        let mut data = valid_mkv();
+        data[0] = 0x1B;
+        assert_eq!(read_info(&mut Cursor::new(data)).unwrap(), None);
+    }
+
+    #[test]
+    fn skips_too_large_info_elements() {
+        let mut info = vec![0x15, 0x49, 0xA9, 0x66, 0x01];
+        info.extend((MAX_INFO_SIZE + 1).to_be_bytes()[1..].iter());
+        assert_eq!(read_info(&mut Cursor::new(mkv(&[info]))).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_element_headers() {
+        let mut reader: &[u8] = &[0x1A, 0x45, 0xDF, 0xA3, 0x40, 0x02];
+        assert_eq!(
+            read_element_header(&mut reader).unwrap(),
+            Some((EBML_ID, Some(2)))
+        );
+        // Unknown sizes of two bytes.
+        let mut reader: &[u8] = &[0xEC, 0x7F, 0xFF];
+        assert_eq!(
+            read_element_header(&mut reader).unwrap(),
+            Some((0xEC, None))
+        );
+        // IDs longer than eight bytes, and truncated ones.
+        let mut reader: &[u8] = &[0x00, 0x81];
+        assert_eq!(read_element_header(&mut reader).unwrap(), None);
+        let mut reader: &[u8] = &[0x1A, 0x45];
+        assert!(read_element_header(&mut reader).is_err());
+        let mut reader: &[u8] = &[0xEC, 0x40];
+        assert!(read_element_header(&mut reader).is_err());
+        assert_eq!(element(&[0xEC]), None);
+    }
+}
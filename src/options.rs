@@ -0,0 +1,498 @@
+use crate::absolute_path;
+use crate::capture::{DatePipeline, DateSource, ExtractorSettings, DEFAULT_DATE_SOURCES};
+use crate::companion::{default_companion_groups, CompanionGroup};
+use crate::compare::CompareMode;
+use crate::config::ConfigValues;
+use crate::filename::{FilenamePattern, YearRange};
+use crate::template::{PathTemplate, DEFAULT_PATH_TEMPLATE};
+use crate::timezone::CaptureZone;
+use crate::video::VideoBackend;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+/// Options of a run, created with [`Options::builder`] or from config values.
+#[derive(Debug)]
+pub struct Options {
+    pub(crate) mode: Mode,
+    pub(crate) source_folder: PathBuf,
+    pub(crate) target_folder: PathBuf,
+    pub(crate) include_unsupported_file_types: bool,
+    pub(crate) file_conflict_resolution_mode: FileConflictResolutionMode,
+    pub(crate) media_creation_date_file_creation_fallback: bool,
+    pub(crate) delete_skipped_source_duplicates: bool,
+    pub(crate) compare_mode: CompareMode,
+    pub(crate) index_target: bool,
+    pub(crate) similar_distance: Option<u32>,
+    pub(crate) similar_resolution_mode: FileConflictResolutionMode,
+    pub(crate) path_template: PathTemplate,
+    pub(crate) rename_template: Option<PathTemplate>,
+    pub(crate) collision_suffix: CollisionSuffix,
+    pub(crate) date_pipeline: DatePipeline,
+    pub(crate) extractor_settings: ExtractorSettings,
+    pub(crate) companion_groups: Vec<CompanionGroup>,
+    pub(crate) raw_pairs: bool,
+    pub(crate) raw_pair_folder: Option<PathBuf>,
+    pub(crate) jpeg_pair_folder: Option<PathBuf>,
+    pub(crate) live_photos: bool,
+    pub(crate) live_photo_folder: Option<PathBuf>,
+}
+
+pub struct OptionsBuilder {
+    mode: Mode,
+    source_folder: PathBuf,
+    target_folder: PathBuf,
+    include_unsupported_file_types: bool,
+    file_conflict_resolution_mode: FileConflictResolutionMode,
+    file_creation_fallback: bool,
+    delete_skipped_source_duplicates: bool,
+    compare_mode: Option<CompareMode>,
+    index_target: bool,
+    similar_distance: Option<u32>,
+    similar_resolution_mode: FileConflictResolutionMode,
+    path_template: String,
+    rename_template: Option<String>,
+    collision_suffix: CollisionSuffix,
+    date_sources: Vec<DateSource>,
+    extractor_settings: ExtractorSettings,
+    date_pipeline: Option<DatePipeline>,
+    companion_groups: Vec<CompanionGroup>,
+    raw_pairs: bool,
+    raw_pair_folder: Option<PathBuf>,
+    jpeg_pair_folder: Option<PathBuf>,
+    live_photos: bool,
+    live_photo_folder: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionSuffix {
+    /// `name_1.jpg`, `name_2.jpg`, ...
+    Number,
+    /// `name (1).jpg`, `name (2).jpg`, ...
+    Paren,
+    /// `name_<first 8 hex digits of the content hash>.jpg`
+    Hash,
+    /// `name_<modification time of the source file>.jpg`
+    Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    DryRun,
+    Move,
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+pub enum FileConflictResolutionMode {
+    /// Ask for every conflict
+    #[serde(rename = "choose")]
+    Choose,
+    /// Replace the file at the target
+    #[value(name = "source")]
+    #[serde(rename = "source")]
+    KeepSource,
+    /// Skip the source file
+    #[value(name = "target")]
+    #[serde(rename = "target")]
+    KeepTarget,
+    /// Keep both files, renaming the source file
+    #[value(name = "both")]
+    #[serde(rename = "both")]
+    KeepBoth,
+}
+
+impl Options {
+    pub fn builder(target_folder: impl Into<PathBuf>) -> OptionsBuilder {
+        OptionsBuilder {
+            mode: Mode::Move,
+            source_folder: PathBuf::from("."),
+            target_folder: target_folder.into(),
+            include_unsupported_file_types: false,
+            file_conflict_resolution_mode: FileConflictResolutionMode::Choose,
+            file_creation_fallback: false,
+            delete_skipped_source_duplicates: false,
+            compare_mode: None,
+            index_target: false,
+            similar_distance: None,
+            similar_resolution_mode: FileConflictResolutionMode::Choose,
+            path_template: DEFAULT_PATH_TEMPLATE.to_string(),
+            rename_template: None,
+            collision_suffix: CollisionSuffix::Number,
+            date_sources: DEFAULT_DATE_SOURCES.to_vec(),
+            extractor_settings: ExtractorSettings::default(),
+            date_pipeline: None,
+            companion_groups: default_companion_groups().to_vec(),
+            raw_pairs: true,
+            raw_pair_folder: None,
+            jpeg_pair_folder: None,
+            live_photos: true,
+            live_photo_folder: None,
+        }
+    }
+
+    /// Options from the merged values of the command line and the config file.
+    pub fn from_config(values: ConfigValues) -> Result<Options, String> {
+        let target_folder = values
+            .target
+            .ok_or_else(|| "No target folder supplied.".to_string())?;
+        let mut builder = Options::builder(target_folder)
+            .include_unsupported_file_types(values.include_unsupported_file_types.unwrap_or(false))
+            .file_creation_fallback(values.file_creation_fallback.unwrap_or(false))
+            .delete_skipped_source_duplicates(
+                values.delete_skipped_source_duplicates.unwrap_or(false),
+            )
+            .index_target(values.index_target.unwrap_or(false))
+            .raw_pairs(values.raw_pairs.unwrap_or(true))
+            .live_photos(values.live_photos.unwrap_or(true));
+        if let Some(mode) = values.mode {
+            builder = builder.mode(mode);
+        }
+        if let Some(source_folder) = values.source {
+            builder = builder.source_folder(source_folder);
+        }
+        if let Some(conflict_mode) = values.conflict_mode {
+            builder = builder.conflict_mode(conflict_mode);
+        }
+        if let Some(compare_mode) = values.compare {
+            builder = builder.compare_mode(compare_mode);
+        }
+        if let Some(distance) = values.similar {
+            builder = builder.similar_distance(distance);
+        }
+        if let Some(similar_mode) = values.similar_mode {
+            builder = builder.similar_mode(similar_mode);
+        }
+        if let Some(path_template) = values.path_template {
+            builder = builder.path_template(path_template);
+        }
+        if let Some(rename_template) = values.rename {
+            builder = builder.rename_template(rename_template);
+        }
+        if let Some(collision_suffix) = values.collision_suffix {
+            builder = builder.collision_suffix(collision_suffix);
+        }
+        if let Some(date_sources) = values.date_sources {
+            builder = builder.date_sources(&date_sources);
+        }
+        if let Some(video_backend) = values.video_backend {
+            builder = builder.video_backend(video_backend);
+        }
+        if let Some(time_zone) = values.timezone {
+            builder = builder.time_zone(time_zone);
+        }
+        if let Some(filename_patterns) = values.filename_patterns {
+            builder = builder.filename_patterns(filename_patterns);
+        }
+        if let Some(filename_years) = values.filename_years {
+            builder = builder.filename_years(filename_years);
+        }
+        if let Some(companion_groups) = values.companions {
+            builder = builder.companion_groups(companion_groups);
+        }
+        if let Some(raw_pair_folder) = values.raw_pair_folder {
+            builder = builder.raw_pair_folder(raw_pair_folder);
+        }
+        if let Some(jpeg_pair_folder) = values.jpeg_pair_folder {
+            builder = builder.jpeg_pair_folder(jpeg_pair_folder);
+        }
+        if let Some(live_photo_folder) = values.live_photo_folder {
+            builder = builder.live_photo_folder(live_photo_folder);
+        }
+        builder.build()
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn source_folder(&self) -> &Path {
+        &self.source_folder
+    }
+
+    pub fn target_folder(&self) -> &Path {
+        &self.target_folder
+    }
+
+    pub fn compare_mode(&self) -> CompareMode {
+        self.compare_mode
+    }
+
+    /// Identifies runs with the same options, so an interrupted run can be resumed.
+    pub(crate) fn fingerprint(&self) -> String {
+        let description = format!(
+            "{:?}|{:?}|{:?}|{:?}|{:?}|{}|{}|{}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{}|{:?}|{:?}|{}|{:?}",
+            absolute_path(&self.source_folder),
+            absolute_path(&self.target_folder),
+            self.mode,
+            self.file_conflict_resolution_mode,
+            self.compare_mode,
+            self.include_unsupported_file_types,
+            self.media_creation_date_file_creation_fallback,
+            self.delete_skipped_source_duplicates,
+            self.index_target,
+            self.similar_distance,
+            self.similar_resolution_mode,
+            self.path_template,
+            self.rename_template,
+            self.collision_suffix,
+            self.date_pipeline,
+            self.extractor_settings,
+            self.companion_groups,
+            self.raw_pairs,
+            self.raw_pair_folder,
+            self.jpeg_pair_folder,
+            self.live_photos,
+            self.live_photo_folder,
+        );
+        blake3::hash(description.as_bytes()).to_hex()[..16].to_string()
+    }
+}
+
+impl OptionsBuilder {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn source_folder(mut self, source_folder: impl Into<PathBuf>) -> Self {
+        self.source_folder = source_folder.into();
+        self
+    }
+
+    /// Also sort files that are neither images nor videos.
+    pub fn include_unsupported_file_types(mut self, include: bool) -> Self {
+        self.include_unsupported_file_types = include;
+        self
+    }
+
+    pub fn conflict_mode(mut self, conflict_mode: FileConflictResolutionMode) -> Self {
+        self.file_conflict_resolution_mode = conflict_mode;
+        self
+    }
+
+    /// Use the modification time of files without capture date instead of asking the handler.
+    pub fn file_creation_fallback(mut self, fallback: bool) -> Self {
+        self.file_creation_fallback = fallback;
+        self
+    }
+
+    /// Delete source files that are skipped because their content already exists at the target.
+    /// Only applies to moves.
+    pub fn delete_skipped_source_duplicates(mut self, delete: bool) -> Self {
+        self.delete_skipped_source_duplicates = delete;
+        self
+    }
+
+    /// Defaults to [`CompareMode::Size`], or [`CompareMode::Hash`] when deleting duplicates.
+    pub fn compare_mode(mut self, compare_mode: CompareMode) -> Self {
+        self.compare_mode = Some(compare_mode);
+        self
+    }
+
+    /// Index the target folder to find duplicates under any name.
+    pub fn index_target(mut self, index_target: bool) -> Self {
+        self.index_target = index_target;
+        self
+    }
+
+    /// Detect similar images up to this perceptual hash distance, at most 64.
+    pub fn similar_distance(mut self, distance: u32) -> Self {
+        self.similar_distance = Some(distance);
+        self
+    }
+
+    /// What to do with images that are similar to one at the target. Independent of the conflict
+    /// mode, so files at the target are only replaced by similar images when asked to.
+    pub fn similar_mode(mut self, similar_mode: FileConflictResolutionMode) -> Self {
+        self.similar_resolution_mode = similar_mode;
+        self
+    }
+
+    /// See [`PathTemplate`].
+    pub fn path_template(mut self, template: impl Into<String>) -> Self {
+        self.path_template = template.into();
+        self
+    }
+
+    /// See [`PathTemplate::parse_file_name`].
+    pub fn rename_template(mut self, template: impl Into<String>) -> Self {
+        self.rename_template = Some(template.into());
+        self
+    }
+
+    pub fn collision_suffix(mut self, collision_suffix: CollisionSuffix) -> Self {
+        self.collision_suffix = collision_suffix;
+        self
+    }
+
+    /// Where capture dates are read from, in order. Defaults to [`DEFAULT_DATE_SOURCES`].
+    ///
+    /// [`DEFAULT_DATE_SOURCES`]: crate::DEFAULT_DATE_SOURCES
+    pub fn date_sources(mut self, sources: &[DateSource]) -> Self {
+        self.date_sources = sources.to_vec();
+        self
+    }
+
+    /// How the dates of videos are read. Defaults to [`VideoBackend::Native`].
+    pub fn video_backend(mut self, video_backend: VideoBackend) -> Self {
+        self.extractor_settings.video_backend = video_backend;
+        self
+    }
+
+    /// The time zone capture dates are converted to. Defaults to the time zone of the system.
+    pub fn time_zone(mut self, time_zone: CaptureZone) -> Self {
+        self.extractor_settings.time_zone = time_zone;
+        self
+    }
+
+    /// Patterns of file names with a date, tried before the built-in ones.
+    pub fn filename_patterns(mut self, filename_patterns: Vec<FilenamePattern>) -> Self {
+        self.extractor_settings.filename_patterns = filename_patterns;
+        self
+    }
+
+    /// The years dates in file names may have, others are ignored. Defaults to 1900 up to today.
+    pub fn filename_years(mut self, filename_years: YearRange) -> Self {
+        self.extractor_settings.filename_years = filename_years;
+        self
+    }
+
+    /// Files that are moved, copied and renamed together with their media file. Defaults to
+    /// [`default_companion_groups`], an empty list sorts every file on its own.
+    pub fn companion_groups(mut self, companion_groups: Vec<CompanionGroup>) -> Self {
+        self.companion_groups = companion_groups;
+        self
+    }
+
+    /// Sort a RAW file and an image with the same stem and capture time, as cameras shooting
+    /// RAW+JPEG produce them, as one unit with the date of the better one. Enabled by default.
+    pub fn raw_pairs(mut self, raw_pairs: bool) -> Self {
+        self.raw_pairs = raw_pairs;
+        self
+    }
+
+    /// Folder below the rendered path template folder the RAW files of pairs go to, e.g. `raw`.
+    pub fn raw_pair_folder(mut self, folder: impl Into<PathBuf>) -> Self {
+        self.raw_pair_folder = Some(folder.into());
+        self
+    }
+
+    /// Folder below the rendered path template folder the images of RAW+JPEG pairs go to, e.g.
+    /// `jpeg`.
+    pub fn jpeg_pair_folder(mut self, folder: impl Into<PathBuf>) -> Self {
+        self.jpeg_pair_folder = Some(folder.into());
+        self
+    }
+
+    /// Sort the image and the video of Apple Live Photos as one unit with the date of the image.
+    /// Enabled by default.
+    pub fn live_photos(mut self, live_photos: bool) -> Self {
+        self.live_photos = live_photos;
+        self
+    }
+
+    /// Folder below the rendered path template folder the videos of Live Photos go to, e.g.
+    /// `live`.
+    pub fn live_photo_folder(mut self, folder: impl Into<PathBuf>) -> Self {
+        self.live_photo_folder = Some(folder.into());
+        self
+    }
+
+    /// Reads capture dates with custom extractors instead of the date sources.
+    pub fn date_pipeline(mut self, date_pipeline: DatePipeline) -> Self {
+        self.date_pipeline = Some(date_pipeline);
+        self
+    }
+
+    pub fn build(self) -> Result<Options, String> {
+        if self.similar_distance.is_some_and(|distance| distance > 64) {
+            return Err("Similarity distance must be a number between 0 and 64.".to_string());
+        }
+        let mut date_pipeline = self.date_pipeline.unwrap_or_else(|| {
+            DatePipeline::from_sources(&self.date_sources, &self.extractor_settings)
+        });
+        date_pipeline.set_time_zone(self.extractor_settings.time_zone);
+        if date_pipeline.extractors().is_empty() {
+            return Err("At least one date source is needed.".to_string());
+        }
+        let pair_folders = [
+            &self.raw_pair_folder,
+            &self.jpeg_pair_folder,
+            &self.live_photo_folder,
+        ];
+        for folder in pair_folders.into_iter().flatten() {
+            let is_relative = folder
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if folder.as_os_str().is_empty() || !is_relative {
+                return Err(format!(
+                    "The pair folder {:?} must be a relative path without '.' or '..'.",
+                    folder
+                ));
+            }
+        }
+        if !self.target_folder.exists() {
+            return Err(
+                "Target folder does not exists or you are missing the required permissions."
+                    .to_string(),
+            );
+        }
+        let compare_mode = match self.compare_mode {
+            Some(compare_mode) => compare_mode,
+            // Skipped duplicates may get deleted, so only trust a real content comparison then.
+            None if self.delete_skipped_source_duplicates => CompareMode::Hash,
+            None => CompareMode::Size,
+        };
+        Ok(Options {
+            mode: self.mode,
+            source_folder: self.source_folder,
+            target_folder: self.target_folder,
+            include_unsupported_file_types: self.include_unsupported_file_types,
+            file_conflict_resolution_mode: self.file_conflict_resolution_mode,
+            media_creation_date_file_creation_fallback: self.file_creation_fallback,
+            delete_skipped_source_duplicates: self.delete_skipped_source_duplicates,
+            compare_mode,
+            index_target: self.index_target,
+            similar_distance: self.similar_distance,
+            similar_resolution_mode: self.similar_resolution_mode,
+            path_template: PathTemplate::parse(&self.path_template)?,
+            rename_template: self
+                .rename_template
+                .as_deref()
+                .map(PathTemplate::parse_file_name)
+                .transpose()?,
+            collision_suffix: self.collision_suffix,
+            date_pipeline,
+            extractor_settings: self.extractor_settings,
+            companion_groups: self.companion_groups,
+            raw_pairs: self.raw_pairs,
+            raw_pair_folder: self.raw_pair_folder,
+            jpeg_pair_folder: self.jpeg_pair_folder,
+            live_photos: self.live_photos,
+            live_photo_folder: self.live_photo_folder,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_relative_pair_folders() {
+        let builder = || Options::builder(std::env::temp_dir());
+        assert!(builder().raw_pair_folder("raw").build().is_ok());
+        assert!(builder().jpeg_pair_folder("pairs/jpeg").build().is_ok());
+        for folder in ["", "/raw", "../raw", "raw/../..", "./raw"] {
+            assert!(
+                builder().raw_pair_folder(folder).build().is_err(),
+                "{}",
+                folder
+            );
+            assert!(builder().live_photo_folder(folder).build().is_err());
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::heif::{heif_dimensions, is_heif};
+use crate::is_image;
+use crate::raw::is_raw;
+use crate::APP_FOLDER;
+use image::imageops::FilterType;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Difference hashes of every image below the target folder, used to find recompressed or
+/// resized copies of images that are already part of the library.
+pub struct PerceptualIndex {
+    entries: Vec<PerceptualEntry>,
+}
+
+struct PerceptualEntry {
+    path: PathBuf,
+    hash: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimilarImage {
+    pub path: PathBuf,
+    pub distance: u32,
+}
+
+impl PerceptualIndex {
+    /// Images that can't be decoded are left out and passed to `on_unreadable`.
+    pub fn build(
+        target_folder: &Path,
+        on_unreadable: &mut dyn FnMut(&Path, String),
+    ) -> io::Result<PerceptualIndex> {
+        let mut index = PerceptualIndex {
+            entries: Vec::new(),
+        };
+        index.add_folder(&fs::canonicalize(target_folder)?, on_unreadable)?;
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the closest image within `max_distance`, ignoring `source` itself.
+    pub fn find_similar(
+        &self,
+        source: &Path,
+        hash: u64,
+        max_distance: u32,
+    ) -> Option<SimilarImage> {
+        let source_path = fs::canonicalize(source).ok();
+        self.entries
+            .iter()
+            .filter(|e| Some(&e.path) != source_path.as_ref())
+            .map(|e| SimilarImage {
+                path: e.path.clone(),
+                distance: (e.hash ^ hash).count_ones(),
+            })
+            .filter(|s| s.distance <= max_distance)
+            .min_by_key(|s| s.distance)
+    }
+
+    pub fn add(&mut self, path: PathBuf, hash: u64) {
+        self.entries.push(PerceptualEntry { path, hash });
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.entries.retain(|e| e.path != path);
+    }
+
+    fn add_folder(
+        &mut self,
+        dir: &Path,
+        on_unreadable: &mut dyn FnMut(&Path, String),
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name() == Some(OsStr::new(APP_FOLDER)) {
+                    continue;
+                }
+                self.add_folder(&path, on_unreadable)?;
+            } else if can_decode(&path) {
+                match difference_hash(&path) {
+                    Ok(hash) => self.add(path, hash),
+                    Err(e) => on_unreadable(&path, e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// dHash: compares the brightness of neighbouring pixels of a 9x8 grayscale thumbnail.
+pub fn difference_hash(path: &Path) -> Result<u64, String> {
+    let thumbnail = image::open(path)
+        .map_err(|e| e.to_string())?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y)[0] < thumbnail.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+pub fn describe_image(path: &Path) -> String {
+    let dimensions = image_dimensions(path)
+        .map(|(width, height)| format!("{}x{}", width, height))
+        .unwrap_or_else(|| "unknown resolution".to_string());
+    let size = path
+        .metadata()
+        .map(|m| human_bytes::human_bytes(m.len() as f64))
+        .unwrap_or_else(|_| "unknown size".to_string());
+    format!("{}, {}", dimensions, size)
+}
+
+pub fn pixel_count(path: &Path) -> u64 {
+    image_dimensions(path)
+        .map(|(width, height)| width as u64 * height as u64)
+        .unwrap_or(0)
+}
+
+/// HEIF and RAW images can't be decoded.
+pub(crate) fn can_decode(path: &Path) -> bool {
+    is_image(path) && !is_heif(path) && !is_raw(path)
+}
+
+/// The size of HEIF images is known from their metadata.
+fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    if is_heif(path) {
+        heif_dimensions(path)
+    } else {
+        image::image_dimensions(path).ok()
+    }
+}
@@ -0,0 +1,276 @@
+use crate::events::{ConflictResolution, SimilarImageResolution};
+use crate::{absolute_path, APP_FOLDER};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Progress of a run, persisted in the target folder so an interrupted run with the same options
+/// can skip already handled files and reuse the answers given to prompts.
+///
+/// The state file is removed once the run finished.
+pub struct RunProgress {
+    file: Option<(PathBuf, File)>,
+    run_id: Option<String>,
+    done: HashSet<PathBuf>,
+    conflicts: HashMap<(PathBuf, PathBuf), ConflictResolution>,
+    similar_images: HashMap<(PathBuf, PathBuf), SimilarImageResolution>,
+    dates: HashMap<PathBuf, Option<NaiveDateTime>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ProgressEntry {
+    Started {
+        run_id: String,
+    },
+    Done {
+        source: PathBuf,
+    },
+    Conflict {
+        source: PathBuf,
+        target: PathBuf,
+        resolution: ConflictResolution,
+    },
+    SimilarImage {
+        source: PathBuf,
+        target: PathBuf,
+        resolution: SimilarImageResolution,
+    },
+    Date {
+        source: PathBuf,
+        date_time: Option<NaiveDateTime>,
+    },
+}
+
+impl RunProgress {
+    /// Opens the progress of the run with the given options fingerprint, continuing it if a
+    /// previous run with the same options did not finish.
+    pub fn open(target_folder: &Path, fingerprint: &str) -> Result<RunProgress, String> {
+        let folder = target_folder.join(APP_FOLDER);
+        fs::create_dir_all(&folder).map_err(|e| e.to_string())?;
+        let path = folder.join(format!("progress-{}.jsonl", fingerprint));
+
+        let mut progress = RunProgress::disabled();
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path).map_err(|e| e.to_string())?);
+            for line in reader.lines() {
+                let line = line.map_err(|e| e.to_string())?;
+                // The last line may be incomplete if the run was killed while writing it.
+                if let Ok(entry) = serde_json::from_str(&line) {
+                    progress.apply(entry);
+                }
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        progress.file = Some((path, file));
+        Ok(progress)
+    }
+
+    /// Progress that is neither loaded nor stored, used for dry runs.
+    pub fn disabled() -> RunProgress {
+        RunProgress {
+            file: None,
+            run_id: None,
+            done: HashSet::new(),
+            conflicts: HashMap::new(),
+            similar_images: HashMap::new(),
+            dates: HashMap::new(),
+        }
+    }
+
+    /// The id of the interrupted run this progress continues.
+    pub fn resumed_run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+
+    pub fn done_count(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn start(&self, run_id: &str) -> io::Result<()> {
+        if self.run_id.is_none() {
+            self.write(&ProgressEntry::Started {
+                run_id: run_id.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn is_done(&self, source: &Path) -> bool {
+        self.done.contains(&absolute_path(source))
+    }
+
+    pub fn mark_done(&self, source: &Path) -> io::Result<()> {
+        self.write(&ProgressEntry::Done {
+            source: absolute_path(source),
+        })
+    }
+
+    pub fn conflict_resolution(&self, source: &Path, target: &Path) -> Option<ConflictResolution> {
+        self.conflicts
+            .get(&(absolute_path(source), absolute_path(target)))
+            .copied()
+    }
+
+    pub fn record_conflict_resolution(
+        &self,
+        source: &Path,
+        target: &Path,
+        resolution: ConflictResolution,
+    ) -> io::Result<()> {
+        self.write(&ProgressEntry::Conflict {
+            source: absolute_path(source),
+            target: absolute_path(target),
+            resolution,
+        })
+    }
+
+    pub fn similar_image_resolution(
+        &self,
+        source: &Path,
+        target: &Path,
+    ) -> Option<SimilarImageResolution> {
+        self.similar_images
+            .get(&(absolute_path(source), absolute_path(target)))
+            .copied()
+    }
+
+    pub fn record_similar_image_resolution(
+        &self,
+        source: &Path,
+        target: &Path,
+        resolution: SimilarImageResolution,
+    ) -> io::Result<()> {
+        self.write(&ProgressEntry::SimilarImage {
+            source: absolute_path(source),
+            target: absolute_path(target),
+            resolution,
+        })
+    }
+
+    /// The date entered for a file without creation date. `Some(None)` means it was skipped.
+    pub fn date(&self, source: &Path) -> Option<Option<NaiveDateTime>> {
+        self.dates.get(&absolute_path(source)).copied()
+    }
+
+    pub fn record_date(&self, source: &Path, date_time: Option<NaiveDateTime>) -> io::Result<()> {
+        self.write(&ProgressEntry::Date {
+            source: absolute_path(source),
+            date_time,
+        })
+    }
+
+    /// Removes the stored progress after the run completed.
+    pub fn finish(self) -> io::Result<()> {
+        match self.file {
+            Some((path, file)) => {
+                drop(file);
+                fs::remove_file(path)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn apply(&mut self, entry: ProgressEntry) {
+        match entry {
+            ProgressEntry::Started { run_id } => self.run_id = Some(run_id),
+            ProgressEntry::Done { source } => {
+                self.done.insert(source);
+            }
+            ProgressEntry::Conflict {
+                source,
+                target,
+                resolution,
+            } => {
+                self.conflicts.insert((source, target), resolution);
+            }
+            ProgressEntry::SimilarImage {
+                source,
+                target,
+                resolution,
+            } => {
+                self.similar_images.insert((source, target), resolution);
+            }
+            ProgressEntry::Date { source, date_time } => {
+                self.dates.insert(source, date_time);
+            }
+        }
+    }
+
+    fn write(&self, entry: &ProgressEntry) -> io::Result<()> {
+        let mut file = match &self.file {
+            Some((_, file)) => file,
+            None => return Ok(()),
+        };
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target_folder(test: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("progress_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&folder);
+        folder
+    }
+
+    #[test]
+    fn continues_the_progress_of_the_same_options() {
+        let target = target_folder("continue");
+        let progress = RunProgress::open(&target, "options").unwrap();
+        progress.start("run").unwrap();
+        progress.mark_done(Path::new("/photos/a.jpg")).unwrap();
+        progress
+            .record_date(Path::new("/photos/b.jpg"), None)
+            .unwrap();
+        drop(progress);
+
+        let progress = RunProgress::open(&target, "options").unwrap();
+        assert_eq!(progress.resumed_run_id(), Some("run"));
+        assert!(progress.is_done(Path::new("/photos/a.jpg")));
+        assert_eq!(progress.date(Path::new("/photos/b.jpg")), Some(None));
+        assert_eq!(progress.date(Path::new("/photos/a.jpg")), None);
+
+        let other = RunProgress::open(&target, "other options").unwrap();
+        assert_eq!(other.resumed_run_id(), None);
+        assert_eq!(other.done_count(), 0);
+
+        progress.finish().unwrap();
+        assert_eq!(
+            RunProgress::open(&target, "options")
+                .unwrap()
+                .resumed_run_id(),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_incomplete_lines() {
+        let target = target_folder("incomplete");
+        fs::create_dir_all(target.join(APP_FOLDER)).unwrap();
+        fs::write(
+            target.join(APP_FOLDER).join("progress-options.jsonl"),
+            "{\"type\":\"started\",\"run_id\":\"run\"}\n\
+             {\"type\":\"done\",\"source\":\"/photos/a.jpg\"}\n\
+             {\"type\":\"done\",\"sour",
+        )
+        .unwrap();
+
+        let progress = RunProgress::open(&target, "options").unwrap();
+
+        assert_eq!(progress.resumed_run_id(), Some("run"));
+        assert_eq!(progress.done_count(), 1);
+    }
+}
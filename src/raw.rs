@@ -0,0 +1,263 @@
+use crate::bmff::{atoms, read_top_level_box, MAX_MOOV_SIZE};
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub(crate) const RAW_EXTENSIONS: [&str; 8] =
+    ["cr2", "cr3", "nef", "arw", "dng", "orf", "rw2", "raf"];
+
+/// Caps the metadata read from the start of TIFF based RAW files, including the maker notes of
+/// the camera.
+const MAX_TIFF_METADATA_SIZE: u64 = 64 * 1024 * 1024;
+/// The EXIF reader reads no more IFDs of the main chain of a TIFF file.
+const MAX_TIFF_IFD_CHAIN_LENGTH: usize = 8;
+/// Tags of the pointers to the Exif, GPS and interoperability IFDs.
+const TIFF_CHILD_IFD_TAGS: [u16; 3] = [0x8769, 0x8825, 0xa005];
+/// The box of CR3 files that holds the metadata.
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+pub(crate) fn is_raw(path: &Path) -> bool {
+    raw_extension(path).is_some()
+}
+
+fn raw_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_lowercase)
+        .filter(|e| RAW_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// Reads the EXIF data of a camera RAW file.
+pub(crate) fn read_raw_exif(path: &Path) -> Result<exif::Exif, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    match raw_extension(path).as_deref() {
+        Some("cr3") => read_cr3_exif(&mut file),
+        Some("raf") => read_raf_exif(&mut file),
+        _ => read_tiff_exif(&mut file),
+    }
+}
+
+/// Most RAW formats are TIFF files, some with their own magic number instead of 42. Only the
+/// metadata at the start of the file is read, not the image data.
+fn read_tiff_exif<R: Read + Seek>(file: &mut R) -> Result<exif::Exif, String> {
+    let mut header = [0; 8];
+    file.read_exact(&mut header)
+        .map_err(|_| "Not a TIFF based RAW file.".to_string())?;
+    let big_endian = match &header[0..2] {
+        b"II" => false,
+        b"MM" => true,
+        _ => return Err("Not a TIFF based RAW file.".to_string()),
+    };
+    let length = tiff_metadata_length(file, big_endian, &header)?;
+    file.rewind().map_err(|e| e.to_string())?;
+    let mut data = Vec::new();
+    file.take(length)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    data[2..4].copy_from_slice(if big_endian { &[0, 42] } else { &[42, 0] });
+    exif::Reader::new()
+        .read_raw(data)
+        .map_err(|e| e.to_string())
+}
+
+/// The length of the start of a TIFF file up to the end of the last IFD or field value the EXIF
+/// reader reads. RAW files store their image data after it.
+fn tiff_metadata_length<R: Read + Seek>(
+    file: &mut R,
+    big_endian: bool,
+    header: &[u8; 8],
+) -> Result<u64, String> {
+    let u16_from = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    };
+    let u32_from = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let truncated = |_| "Truncated TIFF metadata.".to_string();
+
+    let mut end = header.len() as u64;
+    let mut visited = Vec::new();
+    // IFDs to read, and whether they are part of the main chain with a next IFD.
+    let mut pending = vec![(u32_from(&header[4..8]) as u64, true)];
+    let mut chain_length = 0;
+    while let Some((offset, in_chain)) = pending.pop() {
+        if offset == 0 || visited.contains(&offset) {
+            continue;
+        }
+        visited.push(offset);
+        file.seek(SeekFrom::Start(offset)).map_err(truncated)?;
+        let mut count = [0; 2];
+        file.read_exact(&mut count).map_err(truncated)?;
+        let mut entries = vec![0; u16_from(&count) as usize * 12 + 4];
+        file.read_exact(&mut entries).map_err(truncated)?;
+        end = end.max(offset + 2 + entries.len() as u64);
+
+        let (entries, next) = entries.split_at(entries.len() - 4);
+        for entry in entries.chunks_exact(12) {
+            let value_length =
+                tiff_unit_length(u16_from(&entry[2..4])) * u32_from(&entry[4..8]) as u64;
+            let value_offset = u32_from(&entry[8..12]) as u64;
+            if value_length > 4 {
+                end = end.max(value_offset + value_length);
+            }
+            if TIFF_CHILD_IFD_TAGS.contains(&u16_from(&entry[0..2])) {
+                pending.push((value_offset, false));
+            }
+        }
+        if in_chain {
+            chain_length += 1;
+            if chain_length < MAX_TIFF_IFD_CHAIN_LENGTH {
+                pending.push((u32_from(next) as u64, true));
+            }
+        }
+        if end > MAX_TIFF_METADATA_SIZE {
+            return Err("TIFF metadata too large.".to_string());
+        }
+    }
+    Ok(end)
+}
+
+/// The size in bytes of one value of a TIFF field type, 0 for unknown types.
+fn tiff_unit_length(field_type: u16) -> u64 {
+    match field_type {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+/// RAF files of Fujifilm embed a JPEG preview with the EXIF data of the photo.
+fn read_raf_exif<R: Read + Seek>(file: &mut R) -> Result<exif::Exif, String> {
+    let mut header = [0; 92];
+    file.read_exact(&mut header).map_err(|e| e.to_string())?;
+    if !header.starts_with(b"FUJIFILMCCD-RAW") {
+        return Err("Not a RAF file.".to_string());
+    }
+    let offset = u32::from_be_bytes(header[84..88].try_into().unwrap());
+    let length = u32::from_be_bytes(header[88..92].try_into().unwrap());
+    file.seek(SeekFrom::Start(offset as u64))
+        .map_err(|e| e.to_string())?;
+    let mut jpeg = Vec::new();
+    file.take(length as u64)
+        .read_to_end(&mut jpeg)
+        .map_err(|e| e.to_string())?;
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(jpeg))
+        .map_err(|e| e.to_string())
+}
+
+/// CR3 files of Canon are ISO base media files with the TIFF fields in `CMT1` and the Exif fields
+/// in `CMT2` of a `uuid` box, each stored as a TIFF file of its own. Both are joined into one.
+fn read_cr3_exif<R: Read + Seek>(file: &mut R) -> Result<exif::Exif, String> {
+    let moov = read_top_level_box(file, b"moov", MAX_MOOV_SIZE)
+        .map_err(|e| e.to_string())?
+        .ok_or("No moov box found.".to_string())?;
+    let metadata = atoms(&moov)
+        .filter(|atom| &atom.kind == b"uuid")
+        .find_map(|atom| atom.data.strip_prefix(&CANON_UUID[..]))
+        .ok_or("No Canon metadata found.".to_string())?;
+
+    let mut fields = Vec::new();
+    for (kind, context) in [(b"CMT1", Context::Tiff), (b"CMT2", Context::Exif)] {
+        let Some(tiff) = atoms(metadata).find(|atom| &atom.kind == kind) else {
+            continue;
+        };
+        let exif = exif::Reader::new()
+            .read_raw(tiff.data.to_vec())
+            .map_err(|e| e.to_string())?;
+        fields.extend(
+            exif.fields()
+                .filter(|field| field.ifd_num == In::PRIMARY)
+                .filter(|field| !matches!(field.value, exif::Value::Unknown(..)))
+                .map(|field| Field {
+                    tag: Tag(context, field.tag.number()),
+                    ifd_num: In::PRIMARY,
+                    value: field.value.clone(),
+                }),
+        );
+    }
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut joined = Cursor::new(Vec::new());
+    writer.write(&mut joined, true).map_err(|e| e.to_string())?;
+    exif::Reader::new()
+        .read_raw(joined.into_inner())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{DateSource, ExtractorSettings};
+    use crate::is_image;
+    use std::fs;
+
+    const FIXTURES: &str = "tests/fixtures/raw";
+
+    #[test]
+    fn reads_capture_dates_of_raw_files() {
+        let expected = [
+            ("canon.cr2", "2019-01-02 03:04:05", "Canon EOS 5D Mark IV"),
+            ("canon.cr3", "2019-08-09 10:11:12", "Canon EOS R5"),
+            ("nikon.nef", "2019-02-03 04:05:06", "NIKON D850"),
+            ("sony.arw", "2019-03-04 05:06:07", "ILCE-7M3"),
+            ("adobe.dng", "2019-04-05 06:07:08", "Pixel 7"),
+            ("olympus.orf", "2019-05-06 07:08:09", "E-M1MarkII"),
+            ("panasonic.rw2", "2019-06-07 08:09:10", "DC-GH5"),
+            ("fujifilm.raf", "2019-07-08 09:10:11", "X-T4"),
+        ];
+        let extractor = DateSource::Exif.extractor(&ExtractorSettings::default());
+        for (file_name, date_time, camera) in expected {
+            let path = Path::new(FIXTURES).join(file_name);
+            assert!(is_image(&path), "{} is not an image", file_name);
+            let date = extractor
+                .extract(&path)
+                .unwrap_or_else(|e| panic!("{}: {}", file_name, e));
+            assert_eq!(
+                date.capture_info.date_time.to_string(),
+                date_time,
+                "{}",
+                file_name
+            );
+            assert_eq!(
+                date.capture_info.camera.as_deref(),
+                Some(camera),
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_garbage_files() {
+        let canon = fs::read(Path::new(FIXTURES).join("canon.cr2")).unwrap();
+        let fujifilm = fs::read(Path::new(FIXTURES).join("fujifilm.raf")).unwrap();
+        let tiff = |data: &[u8]| read_tiff_exif(&mut Cursor::new(data)).is_err();
+        assert!(tiff(b""));
+        assert!(tiff(b"II"));
+        assert!(tiff(b"MM\0\x2a\0\0\0\x08"));
+        assert!(tiff(b"not a raw file at all"));
+        assert!(tiff(&canon[..64]));
+        assert!(read_raf_exif(&mut Cursor::new(&fujifilm[..100])).is_err());
+        assert!(read_cr3_exif(&mut Cursor::new(b"\0\0\0\x18ftypcrx ")).is_err());
+    }
+}
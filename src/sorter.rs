@@ -0,0 +1,1368 @@
+use crate::capture::{CaptureInfo, Confidence, DatePrecision, ExtractedDate};
+use crate::companion::{companion_target, find_companions, find_pair_partner, find_primary};
+use crate::compare::{files_are_identical, hash_file, CompareMode};
+use crate::events::{
+    ConflictResolution, SimilarImageResolution, SkipReason, SortEvent, SortHandler, SortOutcome,
+};
+use crate::index::TargetIndex;
+use crate::journal::{Journal, JournalAction};
+use crate::live_photo::{is_live_photo_video, LivePhotoIndex};
+use crate::options::{CollisionSuffix, FileConflictResolutionMode, Mode, Options};
+use crate::perceptual::{can_decode, difference_hash, PerceptualIndex, SimilarImage};
+use crate::progress::RunProgress;
+use crate::raw::is_raw;
+use crate::takeout::takeout_media;
+use crate::template::{PathTemplate, TemplateContext};
+use crate::{is_supported_file_type, visit_dirs};
+use chrono::{DateTime, Local};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirEntry};
+use std::path::{Path, PathBuf};
+
+/// Files of a RAW+JPEG pair whose capture dates differ by more seconds are not a pair.
+const PAIR_TOLERANCE_SECONDS: i64 = 2;
+/// The same for Live Photos that are only paired by their stem, as the video starts earlier.
+const LIVE_PHOTO_TOLERANCE_SECONDS: i64 = 60;
+
+/// Sorts the files of the source folder into the target folder.
+pub struct Sorter {
+    options: Options,
+}
+
+/// Result of a finished run.
+pub struct SortSummary {
+    /// Id of the run in the journal, e.g. to undo it.
+    pub run_id: String,
+    /// Outcome of every file that was found in the source folder.
+    pub outcomes: Vec<SortOutcome>,
+}
+
+/// State of the current run that is persisted in the target folder.
+struct Run<'a> {
+    journal: Journal,
+    progress: RunProgress,
+    handler: RefCell<&'a mut dyn SortHandler>,
+    /// Companion files that were sorted together with their media file, by the media file.
+    sorted_companions: RefCell<HashMap<PathBuf, PathBuf>>,
+    live_photos: RefCell<LivePhotoIndex>,
+}
+
+/// Where a source file goes, unless it is skipped.
+enum Destination {
+    Path(PathBuf),
+    Skip(SkipReason),
+}
+
+impl Run<'_> {
+    fn emit(&self, event: SortEvent) {
+        self.handler.borrow_mut().event(event);
+    }
+
+    fn warn(&self, message: String) {
+        self.emit(SortEvent::Warning { message });
+    }
+
+    /// Leaves out the companion files that were already sorted with another file.
+    fn unsorted(&self, companions: Vec<PathBuf>) -> Vec<PathBuf> {
+        let sorted_companions = self.sorted_companions.borrow();
+        companions
+            .into_iter()
+            .filter(|companion| !sorted_companions.contains_key(companion))
+            .collect()
+    }
+}
+
+impl Sorter {
+    pub fn new(options: Options) -> Sorter {
+        Sorter { options }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Sorts every file of the source folder. An interrupted run with the same options is
+    /// resumed. Errors of single files are reported as [`SortOutcome::Failed`], only problems
+    /// with the target folder or the journal end the run early.
+    pub fn run(&self, handler: &mut dyn SortHandler) -> Result<SortSummary, String> {
+        let options = &self.options;
+        let progress = match options.mode {
+            Mode::DryRun => RunProgress::disabled(),
+            Mode::Move | Mode::Copy => {
+                RunProgress::open(&options.target_folder, &options.fingerprint())
+                    .map_err(|e| format!("Can't open the progress: {}", e))?
+            }
+        };
+        let run_id = match progress.resumed_run_id() {
+            Some(run_id) => {
+                handler.event(SortEvent::RunResumed {
+                    run_id: run_id.to_string(),
+                    done: progress.done_count(),
+                });
+                run_id.to_string()
+            }
+            None => {
+                let run_id = Journal::new_run_id(&options.target_folder);
+                handler.event(SortEvent::RunStarted {
+                    run_id: run_id.clone(),
+                });
+                run_id
+            }
+        };
+        progress
+            .start(&run_id)
+            .map_err(|e| format!("Can't store progress: {}", e))?;
+        let journal = match options.mode {
+            Mode::DryRun => Journal::disabled(run_id),
+            Mode::Move | Mode::Copy => Journal::open(&options.target_folder, run_id)
+                .map_err(|e| format!("Can't open the journal: {}", e))?,
+        };
+
+        let mut target_index = if options.index_target {
+            let index = TargetIndex::build(&options.target_folder)
+                .map_err(|e| format!("Can't index the target folder: {}", e))?;
+            handler.event(SortEvent::TargetIndexed { files: index.len() });
+            Some(index)
+        } else {
+            None
+        };
+        let mut perceptual_index = if options.similar_distance.is_some() {
+            let index = PerceptualIndex::build(&options.target_folder, &mut |path, error| {
+                handler.event(SortEvent::TargetImageNotHashed {
+                    path: path.to_path_buf(),
+                    error,
+                })
+            })
+            .map_err(|e| format!("Can't hash the images of the target folder: {}", e))?;
+            handler.event(SortEvent::TargetImagesHashed {
+                images: index.len(),
+            });
+            Some(index)
+        } else {
+            None
+        };
+
+        let run = Run {
+            journal,
+            progress,
+            handler: RefCell::new(handler),
+            sorted_companions: RefCell::new(HashMap::new()),
+            live_photos: RefCell::new(LivePhotoIndex::default()),
+        };
+        let mut target_parents = HashSet::new();
+        let mut outcomes = Vec::new();
+        visit_dirs(&options.source_folder, &mut |dir_entry: &DirEntry| {
+            let outcome = handle_file(
+                options,
+                &dir_entry.path(),
+                &mut target_parents,
+                &mut target_index,
+                &mut perceptual_index,
+                &run,
+            );
+            run.emit(SortEvent::Handled(outcome.clone()));
+            outcomes.push(outcome);
+        })
+        .map_err(|e| format!("Can't read the source folder: {}", e))?;
+
+        let Run {
+            journal, progress, ..
+        } = run;
+        progress
+            .finish()
+            .map_err(|e| format!("Can't remove the progress: {}", e))?;
+        Ok(SortSummary {
+            run_id: journal.run_id().to_string(),
+            outcomes,
+        })
+    }
+}
+
+fn handle_file(
+    options: &Options,
+    source_path: &Path,
+    target_parents: &mut HashSet<PathBuf>,
+    target_index: &mut Option<TargetIndex>,
+    perceptual_index: &mut Option<PerceptualIndex>,
+    run: &Run,
+) -> SortOutcome {
+    let sorted_primary = run.sorted_companions.borrow().get(source_path).cloned();
+    if let Some(primary) =
+        sorted_primary.or_else(|| find_primary(source_path, &options.companion_groups))
+    {
+        return SortOutcome::Skipped {
+            source: source_path.to_path_buf(),
+            reason: SkipReason::Companion { primary },
+        };
+    }
+    if options.date_pipeline.uses("takeout") {
+        if let Some(media) = takeout_media(source_path) {
+            return SortOutcome::Skipped {
+                source: source_path.to_path_buf(),
+                reason: SkipReason::TakeoutMetadata { media },
+            };
+        }
+    }
+    if !is_supported_file_type(source_path) && !options.include_unsupported_file_types {
+        return SortOutcome::Unsupported {
+            source: source_path.to_path_buf(),
+        };
+    }
+    if run.progress.is_done(source_path) {
+        return SortOutcome::Skipped {
+            source: source_path.to_path_buf(),
+            reason: SkipReason::AlreadyHandled,
+        };
+    }
+    run.emit(SortEvent::FileFound {
+        source: source_path.to_path_buf(),
+    });
+    let result = process_file(
+        options,
+        source_path,
+        target_parents,
+        target_index,
+        perceptual_index,
+        run,
+    );
+    if result.is_ok() {
+        if let Err(e) = run.progress.mark_done(source_path) {
+            run.warn(format!("Can't store progress: {}", e));
+        }
+    }
+    match result {
+        Ok(Destination::Path(target_file)) => {
+            let parent = target_file
+                .parent()
+                .expect("File and parent exist")
+                .to_owned();
+            target_parents.insert(parent);
+            SortOutcome::Sorted {
+                source: source_path.to_path_buf(),
+                target: target_file,
+            }
+        }
+        Ok(Destination::Skip(reason)) => SortOutcome::Skipped {
+            source: source_path.to_path_buf(),
+            reason,
+        },
+        Err(error) => SortOutcome::Failed {
+            source: source_path.to_path_buf(),
+            error,
+        },
+    }
+}
+
+fn process_file(
+    options: &Options,
+    source_path: &Path,
+    target_parents: &HashSet<PathBuf>,
+    target_index: &mut Option<TargetIndex>,
+    perceptual_index: &mut Option<PerceptualIndex>,
+    run: &Run,
+) -> Result<Destination, String> {
+    if let Some(existing) = find_in_target_index(target_index, source_path)? {
+        skip_source(options, source_path, &existing, target_parents, run)?;
+        return Ok(Destination::Skip(SkipReason::Duplicate { existing }));
+    }
+
+    let perceptual_hash = match perceptual_index {
+        Some(_) if can_decode(source_path) => difference_hash(source_path).ok(),
+        _ => None,
+    };
+    let similar_image = perceptual_index
+        .as_ref()
+        .zip(perceptual_hash)
+        .zip(options.similar_distance)
+        .and_then(|((index, hash), distance)| index.find_similar(source_path, hash, distance));
+    let replaced_target = match similar_image {
+        Some(similar_image) => match handle_similar_image_at_target(
+            source_path,
+            &similar_image,
+            &options.similar_resolution_mode,
+            run,
+        )? {
+            SimilarImageResolution::ReplaceTarget => Some(similar_image.path),
+            SimilarImageResolution::SkipSource => {
+                skip_source(
+                    options,
+                    source_path,
+                    &similar_image.path,
+                    target_parents,
+                    run,
+                )?;
+                return Ok(Destination::Skip(SkipReason::KeptTarget {
+                    target: similar_image.path,
+                }));
+            }
+            SimilarImageResolution::KeepBoth => None,
+        },
+        None => None,
+    };
+
+    let (partner, date) = find_partner(
+        options,
+        source_path,
+        options.date_pipeline.extract(source_path),
+        run,
+    );
+    let capture_info = extract_date_time(options, source_path, date, run)?;
+    let target_file = match sort_file(
+        options,
+        source_path,
+        partner.as_ref(),
+        target_parents,
+        run,
+        &capture_info,
+    )? {
+        Destination::Path(target_file) => target_file,
+        skip => return Ok(skip),
+    };
+
+    if let Some(replaced_target) = replaced_target {
+        if !is_same_file(&replaced_target, &target_file) {
+            remove_replaced_target(options, &replaced_target, run)?;
+            if let Some(index) = target_index {
+                index.remove(&replaced_target);
+            }
+            if let Some(index) = perceptual_index {
+                index.remove(&replaced_target);
+            }
+        }
+    }
+    if let Some(index) = target_index {
+        let content_path = match options.mode {
+            Mode::DryRun => source_path,
+            Mode::Move | Mode::Copy => &target_file,
+        };
+        index
+            .add(target_file.clone(), content_path)
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some((index, hash)) = perceptual_index.as_mut().zip(perceptual_hash) {
+        index.add(target_file.clone(), hash);
+    }
+    Ok(Destination::Path(target_file))
+}
+
+fn find_in_target_index(
+    target_index: &mut Option<TargetIndex>,
+    source_path: &Path,
+) -> Result<Option<PathBuf>, String> {
+    match target_index {
+        Some(index) => index.find_duplicate(source_path).map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+/// The other file of a pair that is sorted as one unit with the source file.
+struct Partner {
+    path: PathBuf,
+    kind: PairKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PairKind {
+    /// A RAW file and an image the camera wrote at the same time.
+    Raw,
+    /// The image and the short video of an Apple Live Photo.
+    LivePhoto,
+}
+
+/// The partner of `source_path` that is not sorted yet, and the more reliable capture date of
+/// both. Files with the same stem are only a pair if their dates match, unless one of them has no
+/// reliable date, e.g. because only the JPEG has EXIF data. Live Photos with the same content
+/// identifier are always a pair and use the date of the image, as the video starts earlier.
+fn find_partner(
+    options: &Options,
+    source_path: &Path,
+    date: Result<ExtractedDate, String>,
+    run: &Run,
+) -> (Option<Partner>, Result<ExtractedDate, String>) {
+    let mut candidates = Vec::new();
+    if options.raw_pairs {
+        if let Some(path) = find_pair_partner(source_path) {
+            candidates.push((path, PairKind::Raw, Some(PAIR_TOLERANCE_SECONDS)));
+        }
+    }
+    if options.live_photos {
+        if let Some(partner) = run.live_photos.borrow_mut().find_partner(source_path) {
+            let tolerance = (!partner.identified).then_some(LIVE_PHOTO_TOLERANCE_SECONDS);
+            candidates.push((partner.path, PairKind::LivePhoto, tolerance));
+        }
+    }
+    let reliable = |date: &Result<ExtractedDate, String>| {
+        date.as_ref()
+            .ok()
+            .filter(|date| date.confidence > Confidence::Low)
+            .map(|date| date.capture_info.date_time)
+    };
+    for (path, kind, tolerance) in candidates {
+        if run.sorted_companions.borrow().contains_key(&path) {
+            continue;
+        }
+        let partner_date = options.date_pipeline.extract(&path);
+        if let (Some(tolerance), Some(date_time), Some(partner_date_time)) =
+            (tolerance, reliable(&date), reliable(&partner_date))
+        {
+            if (date_time - partner_date_time).num_seconds().abs() > tolerance {
+                continue;
+            }
+        }
+        let prefer_image = kind == PairKind::LivePhoto && is_live_photo_video(source_path);
+        let date = match (&date, &partner_date) {
+            (Err(_), Ok(_)) => partner_date,
+            (Ok(date), Ok(other)) if other.confidence > date.confidence => partner_date,
+            (Ok(date), Ok(other)) if other.confidence == date.confidence && prefer_image => {
+                partner_date
+            }
+            _ => date,
+        };
+        return (Some(Partner { path, kind }), date);
+    }
+    (None, date)
+}
+
+/// The folder a file of a pair goes to, below the folder of the path template.
+fn pair_folder(options: &Options, folder: &Path, kind: PairKind, path: &Path) -> PathBuf {
+    let subfolder = match kind {
+        PairKind::Raw if is_raw(path) => &options.raw_pair_folder,
+        PairKind::Raw => &options.jpeg_pair_folder,
+        PairKind::LivePhoto if is_live_photo_video(path) => &options.live_photo_folder,
+        PairKind::LivePhoto => &None,
+    };
+    match subfolder {
+        Some(subfolder) => folder.join(subfolder),
+        None => folder.to_path_buf(),
+    }
+}
+
+fn sort_file(
+    options: &Options,
+    source_path: &Path,
+    partner: Option<&Partner>,
+    target_parents: &HashSet<PathBuf>,
+    run: &Run,
+    capture_info: &CaptureInfo,
+) -> Result<Destination, String> {
+    let template_folder =
+        options
+            .target_folder
+            .join(options.path_template.render(&TemplateContext {
+                capture_info,
+                source_path,
+                counter: 1,
+            }));
+    let target_folder = match partner {
+        Some(partner) => pair_folder(options, &template_folder, partner.kind, source_path),
+        None => template_folder.clone(),
+    };
+    let file_name = match &options.rename_template {
+        Some(rename_template) => {
+            let file_name = renamed_file_name(
+                options,
+                rename_template,
+                &target_folder,
+                source_path,
+                capture_info,
+            )?;
+            run.emit(SortEvent::Renamed {
+                source: source_path.to_path_buf(),
+                file_name: file_name.clone(),
+            });
+            OsString::from(file_name)
+        }
+        None => source_path
+            .file_name()
+            .expect("we only supply valid files.")
+            .to_owned(),
+    };
+    let target_path_unverified = target_folder.join(file_name);
+
+    let followers_at = |target_path: &Path| {
+        followers(
+            options,
+            source_path,
+            partner,
+            &template_folder,
+            target_path,
+            run,
+        )
+    };
+    let valid_path = match validate_and_resolve_path_problems(
+        options,
+        target_path_unverified,
+        source_path,
+        &followers_at,
+        target_parents,
+        run,
+    )? {
+        Destination::Path(valid_path) => valid_path,
+        skip => return Ok(skip),
+    };
+    // Found before the file is moved, as Takeout metadata is matched by the media file.
+    let followers = followers_at(&valid_path);
+    let overwritten = valid_path.exists();
+    if options.mode != Mode::DryRun {
+        handle_missing_parents(&valid_path, target_parents, run)?;
+    }
+    transfer_file(options, source_path, &valid_path, overwritten, run)?;
+    sort_companions(
+        options,
+        source_path,
+        &followers,
+        overwritten,
+        target_parents,
+        run,
+    )?;
+    Ok(Destination::Path(valid_path))
+}
+
+/// The files that follow a sorted file to `target_path`: its companions, its partner and the
+/// companions of the partner, each with its target.
+fn followers(
+    options: &Options,
+    source_path: &Path,
+    partner: Option<&Partner>,
+    template_folder: &Path,
+    target_path: &Path,
+    run: &Run,
+) -> Vec<(PathBuf, PathBuf)> {
+    let companions = run.unsorted(find_companions(source_path, &options.companion_groups));
+    let mut followers: Vec<(PathBuf, PathBuf)> = companions
+        .iter()
+        .map(|companion| {
+            let target = companion_target(companion, source_path, target_path);
+            (companion.clone(), target)
+        })
+        .collect();
+    if let Some(Partner {
+        path: partner,
+        kind,
+    }) = partner
+    {
+        let partner_target = pair_folder(options, template_folder, *kind, partner)
+            .join(partner_file_name(partner, source_path, target_path));
+        for companion in run.unsorted(find_companions(partner, &options.companion_groups)) {
+            if !companions.contains(&companion) {
+                let target = companion_target(&companion, partner, &partner_target);
+                followers.push((companion, target));
+            }
+        }
+        followers.insert(0, (partner.clone(), partner_target));
+    }
+    followers
+}
+
+/// The name of the partner at the target, the stem of the sorted file with the extension of the
+/// partner, so that pairs keep a common stem. Partners with another stem, like Live Photos paired
+/// by their content identifier, keep their name unless the sorted file was renamed.
+fn partner_file_name(partner: &Path, source_path: &Path, target_path: &Path) -> OsString {
+    if partner.file_stem() != source_path.file_stem()
+        && target_path.file_name() == source_path.file_name()
+    {
+        return partner.file_name().expect("Is a file.").to_owned();
+    }
+    let stem = target_path
+        .file_stem()
+        .expect("Should always have a file stem.");
+    change_file_name(partner, stem)
+        .file_name()
+        .expect("Is a file.")
+        .to_owned()
+}
+
+/// Moves or copies a file and records it in the journal. A transfer that can't be recorded is
+/// reversed, so that the journal holds every change. Nothing happens in dry runs.
+fn transfer_file(
+    options: &Options,
+    source_path: &Path,
+    target_path: &Path,
+    overwritten: bool,
+    run: &Run,
+) -> Result<(), String> {
+    let action = match options.mode {
+        Mode::DryRun => return Ok(()),
+        Mode::Move => JournalAction::Move,
+        Mode::Copy => JournalAction::Copy,
+    };
+    let hash = hash_file(source_path).map_err(|e| e.to_string())?;
+    match action {
+        JournalAction::Move => fs::rename(source_path, target_path),
+        _ => fs::copy(source_path, target_path).map(|_| ()),
+    }
+    .map_err(|e| e.to_string())?;
+    if let Err(e) = run
+        .journal
+        .record(action, source_path, Some(target_path), &hash, overwritten)
+    {
+        let reversed = match action {
+            JournalAction::Move => fs::rename(target_path, source_path),
+            _ => fs::remove_file(target_path),
+        };
+        return Err(match reversed {
+            Ok(()) => format!("Can't record {:?} in the journal: {}", source_path, e),
+            Err(reverse_error) => format!(
+                "Can't record {:?} in the journal: {}, and can't reverse the transfer to {:?}: {}",
+                source_path, e, target_path, reverse_error
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Moves or copies the companion files and the partner of a sorted file to their targets next to
+/// it. A different file at the target of a companion is only replaced if the sorted file replaced
+/// one too, otherwise the companion stays in the source folder. Only companions that were moved,
+/// copied or skipped as duplicates are recorded as sorted with the file.
+fn sort_companions(
+    options: &Options,
+    source_path: &Path,
+    companions: &[(PathBuf, PathBuf)],
+    overwritten: bool,
+    target_parents: &HashSet<PathBuf>,
+    run: &Run,
+) -> Result<(), String> {
+    for (companion, companion_target) in companions {
+        let exists = companion_target.exists();
+        if exists && !is_taken(companion, companion_target, &options.compare_mode)? {
+            handle_skipped_source(options, companion, run)?;
+        } else if exists && !overwritten {
+            run.warn(format!(
+                "Leaving companion file {:?} in place, a different file exists at {:?}",
+                companion, companion_target
+            ));
+            continue;
+        } else {
+            if options.mode != Mode::DryRun {
+                handle_missing_parents(companion_target, target_parents, run)?;
+            }
+            transfer_file(options, companion, companion_target, exists, run)?;
+            run.emit(SortEvent::CompanionSorted {
+                source: companion.clone(),
+                primary: source_path.to_path_buf(),
+                target: companion_target.clone(),
+            });
+        }
+        run.sorted_companions
+            .borrow_mut()
+            .insert(companion.clone(), source_path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Whether a different file than `source_path` exists at `target_path`.
+fn is_taken(
+    source_path: &Path,
+    target_path: &Path,
+    compare_mode: &CompareMode,
+) -> Result<bool, String> {
+    Ok(target_path.exists()
+        && !files_are_identical(source_path, target_path, compare_mode)
+            .map_err(|e| e.to_string())?)
+}
+
+fn any_taken(files: &[(PathBuf, PathBuf)], compare_mode: &CompareMode) -> Result<bool, String> {
+    for (source_path, target_path) in files {
+        if is_taken(source_path, target_path, compare_mode)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Renders the rename template. With a counter in the template, the first counter value whose
+/// name is free or already holds the same content is used.
+fn renamed_file_name(
+    options: &Options,
+    rename_template: &PathTemplate,
+    target_folder: &Path,
+    source_path: &Path,
+    capture_info: &CaptureInfo,
+) -> Result<String, String> {
+    let mut counter = 1;
+    loop {
+        let file_name = rename_template
+            .render_file_name(&TemplateContext {
+                capture_info,
+                source_path,
+                counter,
+            })
+            .ok_or_else(|| "The rename template produced an empty file name.".to_string())?;
+        let target_path = target_folder.join(&file_name);
+        if !rename_template.has_counter()
+            || !target_path.exists()
+            || files_are_identical(source_path, &target_path, &options.compare_mode)
+                .map_err(|e| e.to_string())?
+        {
+            return Ok(file_name);
+        }
+        counter += 1;
+    }
+}
+
+/// Resolves a collision at the target of the source file with the conflict mode. As the followers
+/// of the file must keep its name, a different file at the target of one of them moves the whole
+/// unit to the first alternative name that is free for all of them.
+fn validate_and_resolve_path_problems(
+    options: &Options,
+    target_path_unverified: PathBuf,
+    source_path: &Path,
+    followers: &dyn Fn(&Path) -> Vec<(PathBuf, PathBuf)>,
+    target_parents: &HashSet<PathBuf>,
+    run: &Run,
+) -> Result<Destination, String> {
+    if target_path_unverified.exists() {
+        match handle_file_exists_at_target(
+            source_path,
+            &target_path_unverified,
+            followers,
+            &options.file_conflict_resolution_mode,
+            &options.compare_mode,
+            &options.collision_suffix,
+            run,
+        )? {
+            // The target is replaced.
+            Destination::Path(path_resolution) if path_resolution == target_path_unverified => {
+                Ok(Destination::Path(path_resolution))
+            }
+            Destination::Path(path_resolution) => validate_and_resolve_path_problems(
+                options,
+                path_resolution,
+                source_path,
+                followers,
+                target_parents,
+                run,
+            ),
+            skip => {
+                skip_source(
+                    options,
+                    source_path,
+                    &target_path_unverified,
+                    target_parents,
+                    run,
+                )?;
+                Ok(skip)
+            }
+        }
+    } else {
+        let mut collision = false;
+        for (follower, follower_target) in followers(&target_path_unverified) {
+            if is_taken(&follower, &follower_target, &options.compare_mode)? {
+                run.emit(SortEvent::NameCollision {
+                    source: follower,
+                    target: follower_target,
+                });
+                collision = true;
+            }
+        }
+        if !collision {
+            return Ok(Destination::Path(target_path_unverified));
+        }
+        let alternative = create_alternative_path(
+            source_path,
+            &target_path_unverified,
+            followers,
+            &options.collision_suffix,
+            &options.compare_mode,
+        )?;
+        validate_and_resolve_path_problems(
+            options,
+            alternative,
+            source_path,
+            followers,
+            target_parents,
+            run,
+        )
+    }
+}
+
+/// Skips a source file because the file `kept` at the target is kept instead. Its companion files
+/// go next to `kept`, unless different ones are there already. The source file is then kept as
+/// well, so companions are never left behind without their media file.
+fn skip_source(
+    options: &Options,
+    source_path: &Path,
+    kept: &Path,
+    target_parents: &HashSet<PathBuf>,
+    run: &Run,
+) -> Result<(), String> {
+    let companions: Vec<(PathBuf, PathBuf)> = run
+        .unsorted(find_companions(source_path, &options.companion_groups))
+        .into_iter()
+        .map(|companion| {
+            let target = companion_target(&companion, source_path, kept);
+            (companion, target)
+        })
+        .collect();
+    sort_companions(
+        options,
+        source_path,
+        &companions,
+        false,
+        target_parents,
+        run,
+    )?;
+    let sorted_companions = run.sorted_companions.borrow();
+    if companions
+        .iter()
+        .all(|(companion, _)| sorted_companions.contains_key(companion))
+    {
+        handle_skipped_source(options, source_path, run)?;
+    }
+    Ok(())
+}
+
+fn handle_skipped_source(options: &Options, source_path: &Path, run: &Run) -> Result<(), String> {
+    if options.delete_skipped_source_duplicates {
+        match options.mode {
+            Mode::DryRun => run.emit(SortEvent::SourceDeleted {
+                source: source_path.to_path_buf(),
+            }),
+            Mode::Move => {
+                let hash = hash_file(source_path).map_err(|e| e.to_string())?;
+                fs::remove_file(source_path).map_err(|e| e.to_string())?;
+                run.journal
+                    .record(JournalAction::Delete, source_path, None, &hash, false)
+                    .map_err(|e| e.to_string())?;
+                run.emit(SortEvent::SourceDeleted {
+                    source: source_path.to_path_buf(),
+                });
+            }
+            Mode::Copy => {}
+        }
+    }
+    Ok(())
+}
+
+fn remove_replaced_target(options: &Options, target_path: &Path, run: &Run) -> Result<(), String> {
+    if let Mode::Move | Mode::Copy = options.mode {
+        let hash = hash_file(target_path).map_err(|e| e.to_string())?;
+        fs::remove_file(target_path).map_err(|e| e.to_string())?;
+        run.journal
+            .record(JournalAction::Delete, target_path, None, &hash, false)
+            .map_err(|e| e.to_string())?;
+    }
+    run.emit(SortEvent::TargetDeleted {
+        target: target_path.to_path_buf(),
+    });
+    Ok(())
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn handle_missing_parents(
+    target_path: &Path,
+    target_parents: &HashSet<PathBuf>,
+    run: &Run,
+) -> Result<(), String> {
+    let parent = target_path.parent().expect("is valid.");
+    if !target_parents.contains(&parent.to_path_buf()) && !parent.exists() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        run.emit(SortEvent::FolderCreated {
+            path: parent.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+/// Asks the handler to confirm extracted dates of low confidence.
+fn extract_date_time(
+    options: &Options,
+    path: &Path,
+    date: Result<ExtractedDate, String>,
+    run: &Run,
+) -> Result<CaptureInfo, String> {
+    let mut date = date?;
+    if date.confidence == Confidence::Low && !options.media_creation_date_file_creation_fallback {
+        let date_time = match run.progress.date(path) {
+            Some(date_time) => date_time,
+            None => {
+                let date_time = run.handler.borrow_mut().capture_date(path, &date);
+                if let Err(e) = run.progress.record_date(path, date_time) {
+                    run.warn(format!("Can't store progress: {}", e));
+                }
+                date_time
+            }
+        };
+        let date_time = date_time
+            .ok_or_else(|| "Could not determine a media file creation date.".to_owned())?;
+        if date_time != date.capture_info.date_time {
+            date = ExtractedDate {
+                capture_info: CaptureInfo {
+                    date_time,
+                    subsec: None,
+                    camera: date.capture_info.camera,
+                    offset: None,
+                },
+                source: "manual".to_string(),
+                precision: DatePrecision::Month,
+                confidence: Confidence::High,
+            };
+        }
+    }
+    run.emit(SortEvent::CaptureDateFound {
+        source: path.to_path_buf(),
+        date: date.clone(),
+    });
+    Ok(date.capture_info)
+}
+
+fn handle_file_exists_at_target(
+    source_path: &Path,
+    target_path: &Path,
+    followers: &dyn Fn(&Path) -> Vec<(PathBuf, PathBuf)>,
+    conflict_mode: &FileConflictResolutionMode,
+    compare_mode: &CompareMode,
+    collision_suffix: &CollisionSuffix,
+    run: &Run,
+) -> Result<Destination, String> {
+    if files_are_identical(source_path, target_path, compare_mode).map_err(|e| e.to_string())? {
+        return Ok(Destination::Skip(SkipReason::Duplicate {
+            existing: target_path.to_path_buf(),
+        }));
+    }
+    run.emit(SortEvent::NameCollision {
+        source: source_path.to_path_buf(),
+        target: target_path.to_path_buf(),
+    });
+    let alternative_new_path = || {
+        create_alternative_path(
+            source_path,
+            target_path,
+            followers,
+            collision_suffix,
+            compare_mode,
+        )
+    };
+    let kept_target = || {
+        Destination::Skip(SkipReason::KeptTarget {
+            target: target_path.to_path_buf(),
+        })
+    };
+    match conflict_mode {
+        FileConflictResolutionMode::Choose => {
+            let resolution = match run.progress.conflict_resolution(source_path, target_path) {
+                Some(resolution) => resolution,
+                None => {
+                    let resolution = run.handler.borrow_mut().resolve_conflict(
+                        source_path,
+                        target_path,
+                        &alternative_new_path()?,
+                    );
+                    run.progress
+                        .record_conflict_resolution(source_path, target_path, resolution)
+                        .map_err(|e| e.to_string())?;
+                    resolution
+                }
+            };
+            match resolution {
+                ConflictResolution::OverrideTarget => Ok(Destination::Path(target_path.to_owned())),
+                ConflictResolution::SkipSource => Ok(kept_target()),
+                ConflictResolution::KeepBoth => Ok(Destination::Path(alternative_new_path()?)),
+            }
+        }
+        FileConflictResolutionMode::KeepSource => Ok(Destination::Path(target_path.to_owned())),
+        FileConflictResolutionMode::KeepTarget => Ok(kept_target()),
+        FileConflictResolutionMode::KeepBoth => Ok(Destination::Path(alternative_new_path()?)),
+    }
+}
+
+fn handle_similar_image_at_target(
+    source_path: &Path,
+    similar_image: &SimilarImage,
+    conflict_mode: &FileConflictResolutionMode,
+    run: &Run,
+) -> Result<SimilarImageResolution, String> {
+    run.emit(SortEvent::SimilarImageFound {
+        source: source_path.to_path_buf(),
+        similar: similar_image.clone(),
+    });
+    match conflict_mode {
+        FileConflictResolutionMode::Choose => {
+            match run
+                .progress
+                .similar_image_resolution(source_path, &similar_image.path)
+            {
+                Some(resolution) => Ok(resolution),
+                None => {
+                    let resolution = run
+                        .handler
+                        .borrow_mut()
+                        .resolve_similar_image(source_path, similar_image);
+                    run.progress
+                        .record_similar_image_resolution(
+                            source_path,
+                            &similar_image.path,
+                            resolution,
+                        )
+                        .map_err(|e| e.to_string())?;
+                    Ok(resolution)
+                }
+            }
+        }
+        FileConflictResolutionMode::KeepSource => Ok(SimilarImageResolution::ReplaceTarget),
+        FileConflictResolutionMode::KeepTarget => Ok(SimilarImageResolution::SkipSource),
+        FileConflictResolutionMode::KeepBoth => Ok(SimilarImageResolution::KeepBoth),
+    }
+}
+
+/// Finds the first name next to `target_path` that is either free or already holds the content of
+/// the source file, and the same for the targets of its followers at that name, so keeping both
+/// files never needs another decision.
+fn create_alternative_path(
+    source_path: &Path,
+    target_path: &Path,
+    followers: &dyn Fn(&Path) -> Vec<(PathBuf, PathBuf)>,
+    collision_suffix: &CollisionSuffix,
+    compare_mode: &CompareMode,
+) -> Result<PathBuf, String> {
+    let stem = target_path
+        .file_stem()
+        .expect("Should always have a file stem.");
+    let base_name = match collision_suffix {
+        CollisionSuffix::Number | CollisionSuffix::Paren => None,
+        CollisionSuffix::Hash => {
+            let hash = hash_file(source_path).map_err(|e| e.to_string())?;
+            Some(with_suffix(stem, &format!("_{}", &hash.to_hex()[..8])))
+        }
+        CollisionSuffix::Timestamp => {
+            let modified = source_path
+                .metadata()
+                .and_then(|m| m.modified())
+                .map_err(|e| e.to_string())?;
+            let timestamp = DateTime::<Local>::from(modified).format("%Y%m%d-%H%M%S");
+            Some(with_suffix(stem, &format!("_{}", timestamp)))
+        }
+    };
+    let numbered_names = (1..).map(|n| {
+        let base = base_name.as_deref().unwrap_or(stem);
+        match collision_suffix {
+            CollisionSuffix::Paren => with_suffix(base, &format!(" ({})", n)),
+            _ => with_suffix(base, &format!("_{}", n)),
+        }
+    });
+    for name in base_name.clone().into_iter().chain(numbered_names) {
+        let path = change_file_name(target_path, &name);
+        if !is_taken(source_path, &path, compare_mode)?
+            && !any_taken(&followers(&path), compare_mode)?
+        {
+            return Ok(path);
+        }
+    }
+    unreachable!("There is always a free numbered name.")
+}
+
+fn with_suffix(name: &OsStr, suffix: &str) -> OsString {
+    let mut name = name.to_owned();
+    name.push(suffix);
+    name
+}
+
+fn change_file_name(path: &Path, name: &OsStr) -> PathBuf {
+    let mut file_name = name.to_owned();
+    if let Some(ext) = path.extension() {
+        file_name.push(".");
+        file_name.push(ext);
+    }
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live_photo::{live_photo_image, live_photo_video};
+    use crate::options::OptionsBuilder;
+
+    struct Handler;
+
+    impl SortHandler for Handler {}
+
+    /// Empty source and target folders, the target with the folder of the path template.
+    fn folders(name: &str) -> (PathBuf, PathBuf) {
+        let folder = std::env::temp_dir().join(format!("sorter_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        let (source, target) = (folder.join("source"), folder.join("target"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(target.join("sorted")).unwrap();
+        (source, target)
+    }
+
+    fn options(source: &Path, target: &Path) -> OptionsBuilder {
+        Options::builder(target)
+            .source_folder(source)
+            .conflict_mode(FileConflictResolutionMode::KeepTarget)
+            .file_creation_fallback(true)
+            .path_template("sorted")
+    }
+
+    fn sort(options: OptionsBuilder) -> SortSummary {
+        Sorter::new(options.build().unwrap())
+            .run(&mut Handler)
+            .unwrap()
+    }
+
+    fn is_empty(folder: &Path) -> bool {
+        fs::read_dir(folder).unwrap().next().is_none()
+    }
+
+    #[test]
+    fn resumes_interrupted_runs_with_the_same_options() {
+        let (source, target) = folders("resume");
+        fs::write(source.join("a.jpg"), b"a").unwrap();
+        fs::write(source.join("b.jpg"), b"b").unwrap();
+        let progress = RunProgress::open(
+            &target,
+            &options(&source, &target).build().unwrap().fingerprint(),
+        )
+        .unwrap();
+        progress.start("interrupted").unwrap();
+        progress.mark_done(&source.join("a.jpg")).unwrap();
+        drop(progress);
+
+        let summary = sort(options(&source, &target));
+
+        assert_eq!(summary.run_id, "interrupted");
+        assert!(source.join("a.jpg").exists());
+        assert!(target.join("sorted/b.jpg").exists());
+
+        // The progress is gone after the run finished.
+        let summary = sort(options(&source, &target));
+        assert_ne!(summary.run_id, "interrupted");
+        assert!(is_empty(&source));
+    }
+
+    #[test]
+    fn starts_over_if_the_options_changed() {
+        let (source, target) = folders("options_changed");
+        fs::write(source.join("a.jpg"), b"a").unwrap();
+        let progress = RunProgress::open(
+            &target,
+            &options(&source, &target).build().unwrap().fingerprint(),
+        )
+        .unwrap();
+        progress.start("interrupted").unwrap();
+        progress.mark_done(&source.join("a.jpg")).unwrap();
+        drop(progress);
+
+        let summary = sort(options(&source, &target).path_template("other"));
+
+        assert_ne!(summary.run_id, "interrupted");
+        assert!(target.join("other/a.jpg").exists());
+    }
+
+    /// The alternative name of `source/IMG.jpg` in a target folder holding other files with the
+    /// given names.
+    fn alternative_name(test: &str, taken: &[&str], collision_suffix: CollisionSuffix) -> String {
+        let (source, target) = folders(test);
+        fs::write(source.join("IMG.jpg"), b"jpeg").unwrap();
+        for name in taken {
+            fs::write(target.join(name), b"another jpeg").unwrap();
+        }
+        let path = create_alternative_path(
+            &source.join("IMG.jpg"),
+            &target.join("IMG.jpg"),
+            &|_| Vec::new(),
+            &collision_suffix,
+            &CompareMode::Hash,
+        )
+        .unwrap();
+        assert_eq!(path.parent(), Some(target.as_path()));
+        path.file_name().unwrap().to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn numbers_alternative_names() {
+        let taken = ["IMG.jpg", "IMG_1.jpg", "IMG (1).jpg"];
+        assert_eq!(
+            alternative_name("number", &taken, CollisionSuffix::Number),
+            "IMG_2.jpg"
+        );
+        assert_eq!(
+            alternative_name("paren", &taken, CollisionSuffix::Paren),
+            "IMG (2).jpg"
+        );
+    }
+
+    #[test]
+    fn suffixes_alternative_names_with_the_hash_or_modification_time() {
+        let hash = &blake3::hash(b"jpeg").to_hex()[..8];
+        assert_eq!(
+            alternative_name("hash", &["IMG.jpg"], CollisionSuffix::Hash),
+            format!("IMG_{}.jpg", hash)
+        );
+        let hashed = format!("IMG_{}.jpg", hash);
+        assert_eq!(
+            alternative_name("hash_taken", &["IMG.jpg", &hashed], CollisionSuffix::Hash),
+            format!("IMG_{}_1.jpg", hash)
+        );
+        let name = alternative_name("timestamp", &["IMG.jpg"], CollisionSuffix::Timestamp);
+        let timestamp = name
+            .strip_prefix("IMG_")
+            .and_then(|name| name.strip_suffix(".jpg"))
+            .unwrap();
+        assert!(chrono::NaiveDateTime::parse_from_str(timestamp, "%Y%m%d-%H%M%S").is_ok());
+    }
+
+    #[test]
+    fn reuses_alternative_names_holding_the_same_content() {
+        let (source, target) = folders("alternative_identical");
+        fs::write(source.join("IMG.jpg"), b"jpeg").unwrap();
+        fs::write(target.join("IMG.jpg"), b"another jpeg").unwrap();
+        fs::write(target.join("IMG_1.jpg"), b"jpeg").unwrap();
+        let path = create_alternative_path(
+            &source.join("IMG.jpg"),
+            &target.join("IMG.jpg"),
+            &|_| Vec::new(),
+            &CollisionSuffix::Number,
+            &CompareMode::Hash,
+        );
+        assert_eq!(path.unwrap(), target.join("IMG_1.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn numbers_file_names_that_are_no_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+        let (source, target) = folders("alternative_no_unicode");
+        let name = |name: &[u8]| OsStr::from_bytes(name).to_owned();
+        fs::write(source.join(name(b"IMG\xff.jpg")), b"jpeg").unwrap();
+        fs::write(target.join(name(b"IMG\xff.jpg")), b"another jpeg").unwrap();
+        let path = create_alternative_path(
+            &source.join(name(b"IMG\xff.jpg")),
+            &target.join(name(b"IMG\xff.jpg")),
+            &|_| Vec::new(),
+            &CollisionSuffix::Paren,
+            &CompareMode::Hash,
+        );
+        assert_eq!(path.unwrap(), target.join(name(b"IMG\xff (1).jpg")));
+    }
+
+    #[test]
+    fn keeps_raw_pairs_together_if_the_name_of_one_file_is_taken() {
+        let (source, target) = folders("raw_pair");
+        fs::write(source.join("DSC.JPG"), b"jpeg").unwrap();
+        fs::write(source.join("DSC.NEF"), b"raw").unwrap();
+        fs::write(target.join("sorted/DSC.NEF"), b"another raw").unwrap();
+
+        sort(options(&source, &target));
+
+        assert!(is_empty(&source));
+        assert_eq!(fs::read(target.join("sorted/DSC_1.JPG")).unwrap(), b"jpeg");
+        assert_eq!(fs::read(target.join("sorted/DSC_1.NEF")).unwrap(), b"raw");
+        assert_eq!(
+            fs::read(target.join("sorted/DSC.NEF")).unwrap(),
+            b"another raw"
+        );
+        assert!(!target.join("sorted/DSC.JPG").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sorts_pairs_with_file_names_that_are_no_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+        let (source, target) = folders("raw_pair_no_unicode");
+        let name =
+            |extension: &[u8]| OsStr::from_bytes(&[b"DSC\xff.", extension].concat()).to_owned();
+        fs::write(source.join(name(b"JPG")), b"jpeg").unwrap();
+        fs::write(source.join(name(b"NEF")), b"raw").unwrap();
+
+        sort(options(&source, &target));
+
+        assert!(is_empty(&source));
+        assert_eq!(
+            fs::read(target.join("sorted").join(name(b"JPG"))).unwrap(),
+            b"jpeg"
+        );
+        assert_eq!(
+            fs::read(target.join("sorted").join(name(b"NEF"))).unwrap(),
+            b"raw"
+        );
+    }
+
+    #[test]
+    fn moves_companions_with_their_file_if_the_name_of_one_is_taken() {
+        let (source, target) = folders("companion_taken");
+        fs::write(source.join("IMG_0001.JPG"), b"jpeg").unwrap();
+        fs::write(source.join("IMG_0001.xmp"), b"sidecar").unwrap();
+        fs::write(target.join("sorted/IMG_0001.xmp"), b"another sidecar").unwrap();
+
+        sort(options(&source, &target));
+
+        assert!(is_empty(&source));
+        assert_eq!(
+            fs::read(target.join("sorted/IMG_0001_1.JPG")).unwrap(),
+            b"jpeg"
+        );
+        assert_eq!(
+            fs::read(target.join("sorted/IMG_0001_1.xmp")).unwrap(),
+            b"sidecar"
+        );
+        assert!(!target.join("sorted/IMG_0001.JPG").exists());
+    }
+
+    #[test]
+    fn keeps_skipped_duplicates_whose_companions_were_not_sorted() {
+        let (source, target) = folders("companion_of_duplicate");
+        fs::write(source.join("IMG_0001.JPG"), b"jpeg").unwrap();
+        fs::write(source.join("IMG_0001.xmp"), b"sidecar").unwrap();
+        fs::write(target.join("sorted/IMG_0001.JPG"), b"jpeg").unwrap();
+        fs::write(target.join("sorted/IMG_0001.xmp"), b"another sidecar").unwrap();
+        for folder in [source.clone(), target.join("sorted")] {
+            fs::write(folder.join("IMG_0002.JPG"), b"another jpeg").unwrap();
+            fs::write(folder.join("IMG_0002.xmp"), b"another sidecar").unwrap();
+        }
+
+        sort(options(&source, &target).delete_skipped_source_duplicates(true));
+
+        assert!(!source.join("IMG_0002.JPG").exists());
+        assert!(!source.join("IMG_0002.xmp").exists());
+
+        assert_eq!(fs::read(source.join("IMG_0001.JPG")).unwrap(), b"jpeg");
+        assert_eq!(fs::read(source.join("IMG_0001.xmp")).unwrap(), b"sidecar");
+        assert_eq!(
+            fs::read(target.join("sorted/IMG_0001.xmp")).unwrap(),
+            b"another sidecar"
+        );
+    }
+
+    #[test]
+    fn pairs_live_photos_by_content_identifier() {
+        let (source, target) = folders("live_photo_identifier");
+        fs::write(source.join("photo.JPG"), live_photo_image("ABC-123")).unwrap();
+        fs::write(source.join("clip.MOV"), live_photo_video("ABC-123")).unwrap();
+        fs::write(source.join("other.MOV"), live_photo_video("DEF-456")).unwrap();
+
+        sort(options(&source, &target).live_photo_folder("live"));
+
+        assert!(is_empty(&source));
+        assert!(target.join("sorted/photo.JPG").is_file());
+        assert!(target.join("sorted/live/clip.MOV").is_file());
+        assert!(target.join("sorted/other.MOV").is_file());
+    }
+
+    #[test]
+    fn keeps_live_photos_together_if_the_name_of_the_video_is_taken() {
+        let (source, target) = folders("live_photo_taken");
+        fs::write(source.join("IMG_0001.JPG"), live_photo_image("ABC-123")).unwrap();
+        fs::write(source.join("IMG_0001.MOV"), live_photo_video("ABC-123")).unwrap();
+        fs::write(target.join("sorted/IMG_0001.MOV"), b"another video").unwrap();
+
+        sort(options(&source, &target));
+
+        assert!(is_empty(&source));
+        assert!(target.join("sorted/IMG_0001_1.JPG").is_file());
+        assert_eq!(
+            fs::read(target.join("sorted/IMG_0001_1.MOV")).unwrap(),
+            live_photo_video("ABC-123")
+        );
+        assert!(!target.join("sorted/IMG_0001.JPG").exists());
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{is_image, is_video, APP_FOLDER};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct FileCount {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Number and size of the files in a target folder.
+#[derive(Default)]
+pub struct TargetStats {
+    pub total: FileCount,
+    pub images: FileCount,
+    pub videos: FileCount,
+    pub other: FileCount,
+    /// Files per folder, relative to the target folder.
+    pub folders: BTreeMap<PathBuf, FileCount>,
+}
+
+impl FileCount {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+pub fn collect_stats(target_folder: &Path) -> io::Result<TargetStats> {
+    let mut stats = TargetStats::default();
+    add_folder(target_folder, target_folder, &mut stats)?;
+    Ok(stats)
+}
+
+fn add_folder(folder: &Path, target_folder: &Path, stats: &mut TargetStats) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name() != Some(OsStr::new(APP_FOLDER)) {
+                add_folder(&path, target_folder, stats)?;
+            }
+            continue;
+        }
+        let bytes = path.metadata()?.len();
+        stats.total.add(bytes);
+        if is_image(&path) {
+            stats.images.add(bytes);
+        } else if is_video(&path) {
+            stats.videos.add(bytes);
+        } else {
+            stats.other.add(bytes);
+        }
+        let relative_folder = folder
+            .strip_prefix(target_folder)
+            .unwrap_or(folder)
+            .to_path_buf();
+        stats.folders.entry(relative_folder).or_default().add(bytes);
+    }
+    Ok(())
+}
@@ -0,0 +1,285 @@
+use chrono::{DateTime, NaiveDateTime};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Google truncates the names of the JSON files to this many characters before `.json`.
+const MAX_NAME_LENGTH: usize = 46;
+/// Suffix of the JSON files of newer exports, e.g. `IMG_1234.jpg.supplemental-metadata.json`.
+const SUPPLEMENTAL_SUFFIX: &str = ".supplemental-metadata";
+/// Suffixes of edited copies, which share the JSON file of the original, in some languages.
+const EDITED_SUFFIXES: [&str; 6] = [
+    "-edited",
+    "-bearbeitet",
+    "-modifié",
+    "-editado",
+    "-modificato",
+    "-bewerkt",
+];
+
+/// The metadata Google Photos exports next to each media file in a Takeout archive.
+#[derive(Debug, Clone)]
+pub struct TakeoutMetadata {
+    /// The original name of the media file.
+    pub title: Option<String>,
+    /// When the photo was taken, in UTC.
+    pub photo_taken_time: Option<NaiveDateTime>,
+    /// Latitude and longitude, if the photo has a location.
+    pub location: Option<(f64, f64)>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TakeoutJson {
+    title: Option<String>,
+    photo_taken_time: Option<TakeoutTime>,
+    geo_data: Option<GeoData>,
+}
+
+#[derive(Deserialize)]
+struct TakeoutTime {
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+pub fn read_takeout_metadata(path: &Path) -> Result<TakeoutMetadata, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let json: TakeoutJson = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid Takeout metadata {:?}: {}", path, e))?;
+    Ok(TakeoutMetadata {
+        title: json.title,
+        photo_taken_time: json
+            .photo_taken_time
+            .and_then(|time| time.timestamp.parse().ok())
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map(|date_time| date_time.naive_utc()),
+        // Photos without location have a location of 0, 0.
+        location: json
+            .geo_data
+            .map(|geo| (geo.latitude, geo.longitude))
+            .filter(|&location| location != (0.0, 0.0)),
+    })
+}
+
+/// The JSON file of a media file of a Takeout archive. Edited copies like `IMG_1234-edited.jpg`
+/// use the one of the original, duplicates like `IMG_1234(1).jpg` have the counter after the
+/// extension, e.g. `IMG_1234.jpg(1).json`, and long names are truncated.
+pub fn find_takeout_json(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    if file_name.to_lowercase().ends_with(".json") {
+        return None;
+    }
+    let (stem, extension) = match file_name.rfind('.') {
+        Some(dot) if dot > 0 => file_name.split_at(dot),
+        _ => (file_name, ""),
+    };
+    let stem = EDITED_SUFFIXES
+        .iter()
+        .find_map(|suffix| stem.strip_suffix(suffix))
+        .unwrap_or(stem);
+    let (base, counter) = split_counter(stem);
+    let original = format!("{}{}", base, extension);
+    [
+        format!("{}{}", original, SUPPLEMENTAL_SUFFIX),
+        original,
+        base.to_string(),
+    ]
+    .iter()
+    .flat_map(|name| {
+        [
+            format!("{}{}.json", truncate(name, MAX_NAME_LENGTH), counter),
+            format!(
+                "{}{}.json",
+                truncate(name, MAX_NAME_LENGTH.saturating_sub(counter.len())),
+                counter
+            ),
+        ]
+    })
+    .map(|json_name| path.with_file_name(json_name))
+    .find(|json| json.is_file())
+}
+
+/// The media file whose metadata a JSON file of a Takeout archive holds, if it exists.
+pub fn takeout_media(json: &Path) -> Option<PathBuf> {
+    let json_name = json.file_name()?.to_str()?;
+    let json_stem = json_name.strip_suffix(".json")?;
+    let title = read_takeout_metadata(json)
+        .ok()
+        .filter(|metadata| metadata.photo_taken_time.is_some())?
+        .title?;
+    let (_, counter) = split_counter(json_stem);
+    let (stem, extension) = match title.rfind('.') {
+        Some(dot) if dot > 0 => title.split_at(dot),
+        _ => (title.as_str(), ""),
+    };
+    let mut candidates = vec![format!("{}{}{}", stem, counter, extension)];
+    candidates.extend(
+        EDITED_SUFFIXES
+            .iter()
+            .map(|suffix| format!("{}{}{}{}", stem, suffix, counter, extension)),
+    );
+    candidates
+        .into_iter()
+        .map(|name| json.with_file_name(name))
+        .find(|media| media.is_file() && find_takeout_json(media).as_deref() == Some(json))
+}
+
+/// Splits a counter like `(1)` off the end of `stem`.
+fn split_counter(stem: &str) -> (&str, &str) {
+    let counter_start = stem
+        .strip_suffix(')')
+        .and_then(|rest| rest.rfind('('))
+        .filter(|&start| {
+            let digits = &stem[start + 1..stem.len() - 1];
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+        });
+    match counter_start {
+        Some(start) => stem.split_at(start),
+        None => (stem, ""),
+    }
+}
+
+fn truncate(name: &str, max_chars: usize) -> &str {
+    match name.char_indices().nth(max_chars) {
+        Some((end, _)) => &name[..end],
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Long enough that Takeout truncates the names of its JSON files.
+    const LONG_STEM: &str = "Screenshot_20210704-123456_com.google.android.youtube";
+
+    /// A folder with the media files and the JSON file of `title`.
+    fn takeout(test: &str, media: &[&str], json: &str, title: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("takeout_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        for media in media {
+            fs::write(folder.join(media), b"").unwrap();
+        }
+        let content = format!(
+            r#"{{"title": "{}", "photoTakenTime": {{"timestamp": "1577934245"}}}}"#,
+            title
+        );
+        fs::write(folder.join(json), content).unwrap();
+        folder
+    }
+
+    fn assert_pair(test: &str, media: &str, json: &str, title: &str) {
+        let folder = takeout(test, &[media], json, title);
+        let (media, json) = (folder.join(media), folder.join(json));
+        assert_eq!(
+            find_takeout_json(&media).as_ref(),
+            Some(&json),
+            "{:?}",
+            media
+        );
+        assert_eq!(takeout_media(&json), Some(media));
+        assert_eq!(find_takeout_json(&json), None);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn pairs_media_and_json_files() {
+        assert_pair("plain", "IMG_1234.jpg", "IMG_1234.jpg.json", "IMG_1234.jpg");
+        assert_pair("no_extension", "IMG_1234", "IMG_1234.json", "IMG_1234");
+        assert_pair(
+            "supplemental",
+            "IMG_1234.jpg",
+            "IMG_1234.jpg.supplemental-metadata.json",
+            "IMG_1234.jpg",
+        );
+        assert_pair(
+            "duplicate",
+            "IMG_1234(1).jpg",
+            "IMG_1234.jpg(1).json",
+            "IMG_1234.jpg",
+        );
+        assert_pair(
+            "edited",
+            "IMG_1234-edited.jpg",
+            "IMG_1234.jpg.json",
+            "IMG_1234.jpg",
+        );
+    }
+
+    #[test]
+    fn pairs_truncated_json_files() {
+        let media = format!("{}.png", LONG_STEM);
+        // The name is cut to 46 characters, a counter is added after that or within.
+        assert_pair(
+            "truncated",
+            &media,
+            &format!("{}.json", &media[..46]),
+            &media,
+        );
+        assert_pair(
+            "truncated_supplemental",
+            "PXL_20210704_123456789.jpg",
+            "PXL_20210704_123456789.jpg.supplemental-metada.json",
+            "PXL_20210704_123456789.jpg",
+        );
+        let duplicate = format!("{}(1).png", LONG_STEM);
+        assert_pair(
+            "truncated_duplicate",
+            &duplicate,
+            &format!("{}(1).json", &media[..46]),
+            &media,
+        );
+        assert_pair(
+            "truncated_short_duplicate",
+            &duplicate,
+            &format!("{}(1).json", &media[..43]),
+            &media,
+        );
+    }
+
+    #[test]
+    fn prefers_the_original_over_edited_copies() {
+        let folder = takeout(
+            "original",
+            &["IMG_1234.jpg", "IMG_1234-edited.jpg"],
+            "IMG_1234.jpg.json",
+            "IMG_1234.jpg",
+        );
+        let json = folder.join("IMG_1234.jpg.json");
+        assert_eq!(
+            find_takeout_json(&folder.join("IMG_1234-edited.jpg")).as_ref(),
+            Some(&json)
+        );
+        assert_eq!(takeout_media(&json), Some(folder.join("IMG_1234.jpg")));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn ignores_unrelated_json_files() {
+        let folder = takeout(
+            "unrelated",
+            &["IMG_1234.jpg"],
+            "IMG_1234.jpg.json",
+            "IMG_5678.jpg",
+        );
+        assert_eq!(takeout_media(&folder.join("IMG_1234.jpg.json")), None);
+        fs::write(folder.join("metadata.json"), r#"{"title": "IMG_1234.jpg"}"#).unwrap();
+        assert_eq!(takeout_media(&folder.join("metadata.json")), None);
+        assert_eq!(find_takeout_json(&folder.join("IMG_5678.jpg")), None);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn splits_counters() {
+        assert_eq!(split_counter("IMG_1234(12)"), ("IMG_1234", "(12)"));
+        assert_eq!(split_counter("IMG_1234()"), ("IMG_1234()", ""));
+        assert_eq!(split_counter("IMG_1234(a)"), ("IMG_1234(a)", ""));
+        assert_eq!(split_counter("(1)"), ("", "(1)"));
+    }
+}
@@ -0,0 +1,437 @@
+use crate::{is_image, is_video, CaptureInfo};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, NaiveDate, Timelike};
+use std::ffi::OsStr;
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PATH_TEMPLATE: &str = "{year}/{month}";
+
+/// A template like `{year}/{month:02}-{month_name}` that describes where a file is sorted to.
+///
+/// Placeholders take an optional format specifier after a colon: a width for numbers (`02` pads
+/// with zeros), `lower`, `upper` and `short` for texts or a strftime format for `{date}`.
+/// `{{` and `}}` produce literal braces.
+pub struct PathTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+enum Segment {
+    Literal(String),
+    Placeholder(Token, Format),
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Year,
+    Month,
+    MonthName,
+    Day,
+    Week,
+    WeekYear,
+    Quarter,
+    Weekday,
+    Hour,
+    Minute,
+    Second,
+    Date,
+    Subsec,
+    Camera,
+    Counter,
+    Stem,
+    Ext,
+    Parent,
+    Type,
+}
+
+enum Format {
+    Default,
+    Width { width: usize, zero_padded: bool },
+    Lower,
+    Upper,
+    Short,
+    Strftime(String),
+}
+
+enum Value {
+    Number(i64),
+    Text(String),
+}
+
+/// Everything a template can refer to.
+pub struct TemplateContext<'a> {
+    pub capture_info: &'a CaptureInfo,
+    pub source_path: &'a Path,
+    /// Disambiguates files that would get the same name, only used by file name templates.
+    pub counter: u32,
+}
+
+impl PathTemplate {
+    /// Parses a template for the folders below the target folder.
+    pub fn parse(template: &str) -> Result<PathTemplate, String> {
+        let path_template = PathTemplate::parse_template(template)?;
+        if path_template.has_counter() {
+            return Err("{counter} can only be used in file name templates.".to_string());
+        }
+        Ok(path_template)
+    }
+
+    /// Parses a template for file names, e.g. `{date:%Y%m%d_%H%M%S}_{camera}.{ext}`.
+    pub fn parse_file_name(template: &str) -> Result<PathTemplate, String> {
+        let file_name_template = PathTemplate::parse_template(template)?;
+        let has_separator = file_name_template.segments.iter().any(
+            |segment| matches!(segment, Segment::Literal(literal) if literal.contains(['/', '\\'])),
+        );
+        if has_separator {
+            return Err(format!(
+                "The file name template {:?} must not contain folders.",
+                template
+            ));
+        }
+        Ok(file_name_template)
+    }
+
+    fn parse_template(template: &str) -> Result<PathTemplate, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(format!("Unclosed '{{' in template {:?}.", template))
+                            }
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_placeholder(&placeholder)?);
+                }
+                '}' => return Err(format!("Unmatched '}}' in template {:?}.", template)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(PathTemplate {
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    /// Renders the template to a relative path. Empty path components are left out.
+    pub fn render(&self, context: &TemplateContext) -> PathBuf {
+        self.render_string(context)
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != "." && *component != "..")
+            .collect()
+    }
+
+    /// Renders a file name template, `None` if the result is not a usable file name.
+    pub fn render_file_name(&self, context: &TemplateContext) -> Option<String> {
+        Some(self.render_string(context))
+            .filter(|name| !name.trim().is_empty() && name != "." && name != "..")
+    }
+
+    pub fn has_counter(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder(Token::Counter, _)))
+    }
+
+    fn render_string(&self, context: &TemplateContext) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Placeholder(token, format) => {
+                    let value = format_value(token.value(context), format, context);
+                    // Values must not introduce additional folders.
+                    rendered.push_str(&value.replace(['/', '\\'], "_"));
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl fmt::Debug for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.template)
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment, String> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name.trim(), Some(spec.trim())),
+        None => (placeholder.trim(), None),
+    };
+    let token = Token::from_name(name)
+        .ok_or_else(|| format!("Unknown placeholder {{{}}} in template.", name))?;
+    let format = match spec {
+        None => Format::Default,
+        Some(spec) if matches!(token, Token::Date) => {
+            // Capture dates have no time zone, formatting `%z` or `%Z` fails.
+            let sample = NaiveDate::from_ymd_opt(2000, 1, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .expect("Is a valid date.");
+            if StrftimeItems::new(spec).any(|item| item == Item::Error)
+                || write!(String::new(), "{}", sample.format(spec)).is_err()
+            {
+                return Err(format!("Invalid date format {:?} for {{date}}.", spec));
+            }
+            Format::Strftime(spec.to_string())
+        }
+        Some("lower") if !token.is_number() => Format::Lower,
+        Some("upper") if !token.is_number() => Format::Upper,
+        Some("short") if token.has_short_form() => Format::Short,
+        Some(width) if token.is_number() && width.chars().all(|c| c.is_ascii_digit()) => {
+            Format::Width {
+                width: width
+                    .parse()
+                    .map_err(|_| format!("Invalid width {:?} for {{{}}}.", width, name))?,
+                zero_padded: width.starts_with('0'),
+            }
+        }
+        Some(spec) => {
+            return Err(format!(
+                "Invalid format specifier {:?} for {{{}}}.",
+                spec, name
+            ))
+        }
+    };
+    Ok(Segment::Placeholder(token, format))
+}
+
+fn format_value(value: Value, format: &Format, context: &TemplateContext) -> String {
+    match (value, format) {
+        (_, Format::Strftime(spec)) => context.capture_info.date_time.format(spec).to_string(),
+        (Value::Number(n), Format::Width { width, zero_padded }) => {
+            if *zero_padded {
+                format!("{:0width$}", n, width = width)
+            } else {
+                format!("{:width$}", n, width = width)
+            }
+        }
+        (Value::Number(n), _) => n.to_string(),
+        (Value::Text(text), Format::Lower) => text.to_lowercase(),
+        (Value::Text(text), Format::Upper) => text.to_uppercase(),
+        (Value::Text(text), Format::Short) => text.chars().take(3).collect(),
+        (Value::Text(text), _) => text,
+    }
+}
+
+impl Token {
+    fn from_name(name: &str) -> Option<Token> {
+        match name {
+            "year" => Some(Token::Year),
+            "month" => Some(Token::Month),
+            "month_name" => Some(Token::MonthName),
+            "day" => Some(Token::Day),
+            "week" => Some(Token::Week),
+            "week_year" => Some(Token::WeekYear),
+            "quarter" => Some(Token::Quarter),
+            "weekday" => Some(Token::Weekday),
+            "stem" => Some(Token::Stem),
+            "ext" => Some(Token::Ext),
+            "parent" => Some(Token::Parent),
+            "type" => Some(Token::Type),
+            "hour" => Some(Token::Hour),
+            "minute" => Some(Token::Minute),
+            "second" => Some(Token::Second),
+            "date" => Some(Token::Date),
+            "subsec" => Some(Token::Subsec),
+            "camera" => Some(Token::Camera),
+            "counter" => Some(Token::Counter),
+            _ => None,
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Token::Year
+                | Token::Month
+                | Token::Day
+                | Token::Week
+                | Token::WeekYear
+                | Token::Quarter
+                | Token::Hour
+                | Token::Minute
+                | Token::Second
+                | Token::Counter
+        )
+    }
+
+    fn has_short_form(&self) -> bool {
+        matches!(self, Token::MonthName | Token::Weekday)
+    }
+
+    fn value(&self, context: &TemplateContext) -> Value {
+        let date_time = &context.capture_info.date_time;
+        let date = date_time.date();
+        let file_part = |part: Option<&OsStr>| {
+            Value::Text(part.and_then(OsStr::to_str).unwrap_or("").to_string())
+        };
+        match self {
+            Token::Year => Value::Number(date.year() as i64),
+            Token::Month => Value::Number(date.month() as i64),
+            Token::MonthName => Value::Text(date.format("%B").to_string()),
+            Token::Day => Value::Number(date.day() as i64),
+            Token::Week => Value::Number(date.iso_week().week() as i64),
+            Token::WeekYear => Value::Number(date.iso_week().year() as i64),
+            Token::Quarter => Value::Number(date.month0() as i64 / 3 + 1),
+            Token::Weekday => Value::Text(date.format("%A").to_string()),
+            Token::Hour => Value::Number(date_time.hour() as i64),
+            Token::Minute => Value::Number(date_time.minute() as i64),
+            Token::Second => Value::Number(date_time.second() as i64),
+            Token::Date => Value::Text(date_time.format("%Y-%m-%d").to_string()),
+            Token::Subsec => Value::Text(context.capture_info.subsec.clone().unwrap_or_default()),
+            Token::Camera => Value::Text(context.capture_info.camera.clone().unwrap_or_default()),
+            Token::Counter => Value::Number(context.counter as i64),
+            Token::Stem => file_part(context.source_path.file_stem()),
+            Token::Ext => file_part(context.source_path.extension()),
+            Token::Parent => file_part(context.source_path.parent().and_then(Path::file_name)),
+            Token::Type => Value::Text(
+                if is_image(context.source_path) {
+                    "image"
+                } else if is_video(context.source_path) {
+                    "video"
+                } else {
+                    "other"
+                }
+                .to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn capture_info(camera: Option<&str>) -> CaptureInfo {
+        CaptureInfo {
+            date_time: NaiveDateTime::parse_from_str("2020-03-04 05:06:07", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            subsec: Some("12".to_string()),
+            camera: camera.map(str::to_string),
+            offset: None,
+        }
+    }
+
+    fn render(template: &str, camera: Option<&str>) -> PathBuf {
+        PathTemplate::parse(template)
+            .unwrap()
+            .render(&TemplateContext {
+                capture_info: &capture_info(camera),
+                source_path: Path::new("/photos/holiday/IMG_0001.JPG"),
+                counter: 1,
+            })
+    }
+
+    fn render_file_name(template: &str, camera: Option<&str>, counter: u32) -> Option<String> {
+        PathTemplate::parse_file_name(template)
+            .unwrap()
+            .render_file_name(&TemplateContext {
+                capture_info: &capture_info(camera),
+                source_path: Path::new("/photos/holiday/IMG_0001.JPG"),
+                counter,
+            })
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in [
+            "{unknown}",
+            "{year",
+            "{year}/{month",
+            "year}",
+            "{year:lower}",
+            "{year:-2}",
+            "{month_name:02}",
+            "{camera:short}",
+            "{date:%Q}",
+            "{date:%z}",
+            "{date:%Z}",
+            "{counter}",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{}", template);
+        }
+        assert!(PathTemplate::parse_file_name("{year}/{stem}").is_err());
+        assert!(PathTemplate::parse_file_name("{year}\\{stem}").is_err());
+    }
+
+    #[test]
+    fn renders_placeholders_with_format_specifiers() {
+        assert_eq!(
+            render("{year}/{month:02}-{month_name}", None),
+            Path::new("2020/03-March")
+        );
+        assert_eq!(
+            render("{month_name:lower}/{weekday:short}/{day:3}", None),
+            Path::new("march/Wed/  4")
+        );
+        assert_eq!(
+            render("{week_year}-W{week}/Q{quarter}/{type}", None),
+            Path::new("2020-W10/Q1/image")
+        );
+        assert_eq!(
+            render("{date:%Y%m%d}/{{{hour}}}", None),
+            Path::new("20200304/{5}")
+        );
+    }
+
+    #[test]
+    fn renders_no_additional_folders() {
+        assert_eq!(
+            render("{camera:upper}/../{parent}/./{year}", Some("Pixel 4/XL")),
+            Path::new("PIXEL 4_XL/holiday/2020")
+        );
+        assert_eq!(render("{camera}/{year}", Some("..")), Path::new("2020"));
+        assert_eq!(render("{camera}//{year}", None), Path::new("2020"));
+    }
+
+    #[test]
+    fn renders_file_names() {
+        assert_eq!(
+            render_file_name("{date:%Y%m%d_%H%M%S}_{counter:02}.{ext:lower}", None, 3).as_deref(),
+            Some("20200304_050607_03.jpg")
+        );
+        assert_eq!(
+            render_file_name("{stem}_{subsec}_{camera}.{ext}", Some("A/B"), 1).as_deref(),
+            Some("IMG_0001_12_A_B.JPG")
+        );
+        assert_eq!(render_file_name("{camera}", None, 1), None);
+        assert_eq!(render_file_name("{camera}", Some(".."), 1), None);
+        assert_eq!(render_file_name(" {camera} ", None, 1), None);
+    }
+
+    #[test]
+    fn finds_counters() {
+        assert!(PathTemplate::parse_file_name("{stem}_{counter}")
+            .unwrap()
+            .has_counter());
+        assert!(!PathTemplate::parse_file_name("{stem}_{{counter}}")
+            .unwrap()
+            .has_counter());
+        assert!(!PathTemplate::parse("{year}").unwrap().has_counter());
+    }
+}
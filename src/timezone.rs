@@ -0,0 +1,171 @@
+use chrono::{FixedOffset, Local, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// The time zone capture times are converted to before a folder is chosen. Dates without a
+/// known offset are taken to be in this zone already.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum CaptureZone {
+    /// The time zone of the system.
+    #[default]
+    Local,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl CaptureZone {
+    /// The local time of the UTC `instant` in this zone.
+    pub fn from_utc(&self, instant: &NaiveDateTime) -> (NaiveDateTime, FixedOffset) {
+        let offset = match self {
+            CaptureZone::Local => Local.offset_from_utc_datetime(instant),
+            CaptureZone::Fixed(offset) => *offset,
+            CaptureZone::Named(tz) => tz.offset_from_utc_datetime(instant).fix(),
+        };
+        (*instant + offset, offset)
+    }
+
+    /// The offset of the local time `date_time` in this zone, if that time exists.
+    pub fn offset_of(&self, date_time: &NaiveDateTime) -> Option<FixedOffset> {
+        match self {
+            CaptureZone::Local => Local.offset_from_local_datetime(date_time).earliest(),
+            CaptureZone::Fixed(offset) => Some(*offset),
+            CaptureZone::Named(tz) => tz
+                .offset_from_local_datetime(date_time)
+                .earliest()
+                .map(|offset| offset.fix()),
+        }
+    }
+}
+
+impl FromStr for CaptureZone {
+    type Err = String;
+
+    /// `local`, a UTC offset like `+02:00` or a zone name like `Europe/Berlin` or `UTC`.
+    fn from_str(value: &str) -> Result<CaptureZone, String> {
+        if value.eq_ignore_ascii_case("local") {
+            return Ok(CaptureZone::Local);
+        }
+        if let Some(offset) = parse_offset(value) {
+            return Ok(CaptureZone::Fixed(offset));
+        }
+        value.parse::<Tz>().map(CaptureZone::Named).map_err(|_| {
+            format!(
+                "Unknown time zone {:?}, use local, an offset like +02:00 or a name like Europe/Berlin.",
+                value
+            )
+        })
+    }
+}
+
+impl TryFrom<String> for CaptureZone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<CaptureZone, String> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for CaptureZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureZone::Local => write!(f, "local"),
+            CaptureZone::Fixed(offset) => write!(f, "{}", offset),
+            CaptureZone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+/// Parses UTC offsets as written in EXIF and ISO 8601 dates: `+02:00`, `-0530`, `+01` or `Z`.
+pub(crate) fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    if value == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = match value[1..].split_once(':') {
+        Some((hours, minutes)) if hours.len() == 2 && minutes.len() == 2 => {
+            hours.to_string() + minutes
+        }
+        Some(_) => return None,
+        None => value[1..].to_string(),
+    };
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i32>().ok()?,
+            digits[2..].parse::<i32>().ok()?,
+        ),
+        _ => return None,
+    };
+    if hours >= 24 || minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(hours: i32, minutes: i32) -> Option<FixedOffset> {
+        FixedOffset::east_opt(hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+02:00"), offset(2, 0));
+        assert_eq!(parse_offset(" +05:45 "), offset(5, 45));
+        assert_eq!(parse_offset("-0530"), offset(-5, -30));
+        assert_eq!(parse_offset("+01"), offset(1, 0));
+        assert_eq!(parse_offset("-00:00"), offset(0, 0));
+        assert_eq!(parse_offset("Z"), offset(0, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_offsets() {
+        for value in [
+            "",
+            "02:00",
+            "+2:00",
+            "+0:200",
+            "+02:0",
+            "+02:",
+            "+020",
+            "+02:00:00",
+            "+ab:cd",
+            "z",
+            "+24:00",
+            "-2400",
+            "+23:60",
+            "+01:99",
+        ] {
+            assert_eq!(parse_offset(value), None, "{:?}", value);
+        }
+        assert_eq!(parse_offset("+23:59"), offset(23, 59));
+    }
+
+    #[test]
+    fn parses_capture_zones() {
+        assert_eq!("Local".parse(), Ok(CaptureZone::Local));
+        assert_eq!(
+            "+02:00".parse(),
+            Ok(CaptureZone::Fixed(offset(2, 0).unwrap()))
+        );
+        assert_eq!(
+            "Europe/Berlin".parse(),
+            Ok(CaptureZone::Named(chrono_tz::Europe::Berlin))
+        );
+        assert!("+25:00".parse::<CaptureZone>().is_err());
+        assert!("Mars/Olympus".parse::<CaptureZone>().is_err());
+    }
+}
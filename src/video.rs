@@ -0,0 +1,343 @@
+use crate::avchd::read_avchd_metadata;
+use crate::avi::read_avi_metadata;
+use crate::bmff::{atoms, read_top_level_box, MAX_MOOV_SIZE};
+use crate::capture::{parse_iso8601, DatePrecision};
+use crate::matroska::read_matroska_metadata;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+/// Seconds between 1904-01-01, the epoch of QuickTime, and 1970-01-01.
+const QUICKTIME_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+/// How the metadata of videos is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoBackend {
+    /// Parse the video containers directly
+    #[default]
+    Native,
+    /// Run the external ffprobe binary
+    Ffprobe,
+    /// Parse directly, and run ffprobe for files without date
+    Auto,
+}
+
+/// The dates and tags of a video file.
+#[derive(Debug, Clone, Default)]
+pub struct VideoMetadata {
+    /// Creation time of the movie header (`mvhd`) or the Matroska `DateUTC`, in UTC.
+    pub creation_time: Option<NaiveDateTime>,
+    /// Creation time of the first track header (`tkhd`) that has one, in UTC.
+    pub track_creation_time: Option<NaiveDateTime>,
+    /// Local time written by the camera into the `IDIT` chunk of AVI files or the stream of AVCHD
+    /// files.
+    pub recording_time: Option<NaiveDateTime>,
+    /// Text tags of `udta` and `meta` boxes, e.g. `©day` or `com.apple.quicktime.creationdate`, or
+    /// the text chunks and elements of the other containers.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl VideoMetadata {
+    /// The capture date as written by the camera, with its UTC offset if known. Tags and the
+    /// recording time hold the local time, the headers UTC.
+    pub fn capture_time(&self) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+        let utc = FixedOffset::east_opt(0);
+        ["com.apple.quicktime.creationdate", "©day"]
+            .iter()
+            .filter_map(|tag| self.tags.get(*tag))
+            .filter_map(|value| parse_iso8601(value))
+            .find(|(_, _, precision)| *precision >= DatePrecision::Day)
+            .map(|(date_time, offset, _)| (date_time, offset))
+            .or_else(|| self.recording_time.map(|date_time| (date_time, None)))
+            .or_else(|| self.creation_time.map(|date_time| (date_time, utc)))
+            .or_else(|| self.track_creation_time.map(|date_time| (date_time, utc)))
+    }
+
+    pub fn camera(&self) -> Option<String> {
+        ["com.apple.quicktime.model", "©mod"]
+            .iter()
+            .find_map(|tag| self.tags.get(*tag))
+            .cloned()
+    }
+}
+
+/// Reads the metadata of a video file, by the container its extension names.
+pub fn read_video_metadata(path: &Path) -> Result<VideoMetadata, String> {
+    let extension = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("avi") => read_avi_metadata(path),
+        Some("mkv" | "webm") => read_matroska_metadata(path),
+        Some("mts" | "m2ts") => read_avchd_metadata(path),
+        _ => read_bmff_metadata(path),
+    }
+}
+
+/// Reads the `moov` box of an ISO base media (MP4, 3GP) or QuickTime (MOV) file.
+fn read_bmff_metadata(path: &Path) -> Result<VideoMetadata, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    read_moov_metadata(&mut file)
+}
+
+fn read_moov_metadata<R: Read + Seek>(reader: &mut R) -> Result<VideoMetadata, String> {
+    let moov = read_top_level_box(reader, b"moov", MAX_MOOV_SIZE).map_err(|e| e.to_string())?;
+    let moov = moov.ok_or("No moov box found.".to_string())?;
+
+    let mut metadata = VideoMetadata::default();
+    for atom in atoms(&moov) {
+        match &atom.kind {
+            b"mvhd" => metadata.creation_time = header_creation_time(atom.data),
+            b"trak" if metadata.track_creation_time.is_none() => {
+                metadata.track_creation_time = atoms(atom.data)
+                    .find(|child| &child.kind == b"tkhd")
+                    .and_then(|tkhd| header_creation_time(tkhd.data));
+            }
+            b"udta" => read_user_data(atom.data, &mut metadata.tags),
+            b"meta" => read_meta(atom.data, &mut metadata.tags),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Creation time of a `mvhd` or `tkhd` box, which start the same way.
+fn header_creation_time(data: &[u8]) -> Option<NaiveDateTime> {
+    let seconds = match data.first()? {
+        0 => u32::from_be_bytes(data.get(4..8)?.try_into().ok()?) as u64,
+        1 => u64::from_be_bytes(data.get(4..12)?.try_into().ok()?),
+        _ => return None,
+    };
+    // Zero means unset, smaller values are written by broken software using the Unix epoch.
+    let seconds = seconds.checked_sub(QUICKTIME_EPOCH_OFFSET)?;
+    DateTime::from_timestamp(i64::try_from(seconds).ok()?, 0).map(|date_time| date_time.naive_utc())
+}
+
+/// QuickTime user data holds text boxes like `©day` directly, MP4 files an iTunes style `meta`.
+fn read_user_data(data: &[u8], tags: &mut BTreeMap<String, String>) {
+    for atom in atoms(data) {
+        if &atom.kind == b"meta" {
+            read_meta(atom.data, tags);
+        } else if atom.kind[0] == 0xA9 {
+            // Text boxes start with the length of the text and a language code.
+            let text = atom
+                .data
+                .get(0..2)
+                .map(|size| u16::from_be_bytes([size[0], size[1]]) as usize)
+                .and_then(|size| atom.data.get(4..4 + size));
+            if let Some(text) = text {
+                tags.insert(atom_name(&atom.kind), text_value(text));
+            }
+        }
+    }
+}
+
+/// Reads the `ilst` items of a `meta` box, named by its `keys` box if it has one.
+fn read_meta(data: &[u8], tags: &mut BTreeMap<String, String>) {
+    // The ISO `meta` box has version and flags before its children, the QuickTime one has not.
+    let data = match data.get(4..8) {
+        Some(b"hdlr") => data,
+        _ => data.get(4..).unwrap_or_default(),
+    };
+    let keys: Vec<String> = atoms(data)
+        .find(|atom| &atom.kind == b"keys")
+        .map(|keys| read_keys(keys.data))
+        .unwrap_or_default();
+    let Some(items) = atoms(data).find(|atom| &atom.kind == b"ilst") else {
+        return;
+    };
+    for item in atoms(items.data) {
+        let index = u32::from_be_bytes(item.kind) as usize;
+        let name = match keys.get(index.wrapping_sub(1)) {
+            Some(key) => key.clone(),
+            None => atom_name(&item.kind),
+        };
+        // Data boxes start with the type of the value and a locale. Type 1 is UTF-8 text.
+        let value = atoms(item.data)
+            .find(|atom| &atom.kind == b"data")
+            .filter(|data| data.data.get(0..4) == Some(&[0, 0, 0, 1]))
+            .and_then(|data| data.data.get(8..));
+        if let Some(value) = value {
+            tags.insert(name, text_value(value));
+        }
+    }
+}
+
+fn read_keys(data: &[u8]) -> Vec<String> {
+    // Version, flags and the number of entries.
+    let Some(mut data) = data.get(8..) else {
+        return Vec::new();
+    };
+    let mut keys = Vec::new();
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        if size < 8 || size > data.len() {
+            break;
+        }
+        keys.push(text_value(&data[8..size]));
+        data = &data[size..];
+    }
+    keys
+}
+
+fn atom_name(kind: &[u8; 4]) -> String {
+    kind.iter()
+        .map(|&byte| if byte == 0xA9 { '©' } else { byte as char })
+        .collect()
+}
+
+fn text_value(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bmff::atom;
+    use std::io::Cursor;
+
+    /// 2020-01-02 03:04:05 UTC.
+    const CREATION_TIME: u64 = 1_577_934_245 + QUICKTIME_EPOCH_OFFSET;
+
+    fn date_time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    /// A QuickTime `meta` box with the key `com.apple.quicktime.model`.
+    fn quicktime_meta() -> Vec<u8> {
+        let key = b"com.apple.quicktime.model";
+        let mut keys = b"\0\0\0\0\0\0\0\x01".to_vec();
+        keys.extend((key.len() as u32 + 8).to_be_bytes());
+        keys.extend(b"mdta");
+        keys.extend(key);
+        let data = atom(b"data", b"\0\0\0\x01\0\0\0\0iPhone 12");
+        let mut meta = atom(b"hdlr", b"\0\0\0\0\0\0\0\0mdta");
+        meta.extend(atom(b"keys", &keys));
+        meta.extend(atom(b"ilst", &atom(&1u32.to_be_bytes(), &data)));
+        meta
+    }
+
+    fn moov() -> Vec<u8> {
+        let mut mvhd = vec![0; 4];
+        mvhd.extend((CREATION_TIME as u32).to_be_bytes());
+        mvhd.extend([0; 8]);
+        let mut tkhd = vec![1, 0, 0, 0];
+        tkhd.extend((CREATION_TIME + 60).to_be_bytes());
+        let mut moov = atom(b"mvhd", &mvhd);
+        moov.extend(atom(b"trak", &atom(b"tkhd", &tkhd)));
+        moov.extend(atom(
+            b"udta",
+            &atom(b"\xa9day", b"\0\x18\x15\xc72020-01-02T04:04:05+0100"),
+        ));
+        moov.extend(atom(b"meta", &quicktime_meta()));
+        let mut file = atom(b"ftyp", b"qt  \0\0\0\0");
+        file.extend(atom(b"moov", &moov));
+        file
+    }
+
+    fn read(data: &[u8]) -> Result<VideoMetadata, String> {
+        read_moov_metadata(&mut Cursor::new(data))
+    }
+
+    #[test]
+    fn reads_movie_metadata() {
+        let metadata = read(&moov()).unwrap();
+        assert_eq!(
+            metadata.creation_time,
+            Some(date_time("2020-01-02 03:04:05"))
+        );
+        assert_eq!(
+            metadata.track_creation_time,
+            Some(date_time("2020-01-02 03:05:05"))
+        );
+        assert_eq!(
+            metadata.tags.get("©day").map(String::as_str),
+            Some("2020-01-02T04:04:05+0100")
+        );
+        assert_eq!(metadata.camera().as_deref(), Some("iPhone 12"));
+        let (date_time, offset) = metadata.capture_time().unwrap();
+        assert_eq!(date_time.to_string(), "2020-01-02 04:04:05");
+        assert_eq!(offset, FixedOffset::east_opt(3600));
+    }
+
+    #[test]
+    fn reads_truncated_files() {
+        let file = moov();
+        for length in 0..file.len() {
+            let metadata = read(&file[..length]);
+            // The moov box is incomplete, so it is never read.
+            assert!(metadata.is_err(), "{}", length);
+        }
+    }
+
+    #[test]
+    fn rejects_too_large_moov_boxes() {
+        let mut file = atom(b"ftyp", b"qt  \0\0\0\0");
+        file.extend(b"\0\0\0\x01moov");
+        file.extend((MAX_MOOV_SIZE + 17).to_be_bytes());
+        assert_eq!(read(&file).unwrap_err(), "moov box too large");
+        assert_eq!(read(&file[..8]).unwrap_err(), "No moov box found.");
+    }
+
+    #[test]
+    fn reads_header_creation_times() {
+        let mut version_0 = vec![0; 4];
+        version_0.extend((CREATION_TIME as u32).to_be_bytes());
+        let mut version_1 = vec![1, 0, 0, 0];
+        version_1.extend(CREATION_TIME.to_be_bytes());
+        for data in [version_0, version_1] {
+            assert_eq!(
+                header_creation_time(&data),
+                Some(date_time("2020-01-02 03:04:05"))
+            );
+            for length in 0..data.len() {
+                assert_eq!(header_creation_time(&data[..length]), None);
+            }
+        }
+        // Unset, before the QuickTime epoch, unknown versions and out of range.
+        assert_eq!(header_creation_time(&[0; 8]), None);
+        assert_eq!(header_creation_time(&[0, 0, 0, 0, 0, 0, 0, 1]), None);
+        assert_eq!(header_creation_time(&[2, 0, 0, 0, 0, 0, 0, 1]), None);
+        assert_eq!(
+            header_creation_time(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_malformed_user_data() {
+        let mut tags = BTreeMap::new();
+        // Text longer than its box, and a text box without length.
+        read_user_data(&atom(b"\xa9day", b"\0\x20\0\x002020"), &mut tags);
+        read_user_data(&atom(b"\xa9mod", b"\0"), &mut tags);
+        read_user_data(&atom(b"meta", b""), &mut tags);
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn reads_truncated_meta_boxes() {
+        let meta = quicktime_meta();
+        let mut tags = BTreeMap::new();
+        read_meta(&meta, &mut tags);
+        assert_eq!(
+            tags.get("com.apple.quicktime.model").map(String::as_str),
+            Some("iPhone 12")
+        );
+        for length in 0..meta.len() {
+            read_meta(&meta[..length], &mut BTreeMap::new());
+        }
+        assert!(read_keys(b"\0\0\0\0\0\0\0\x01\0\0\0\x04mdta").is_empty());
+        assert!(read_keys(b"\0\0\0\0\0\0\0\x01\0\0\0\x20mdtakey").is_empty());
+        assert!(read_keys(b"\0\0\0").is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::capture::{parse_iso8601, read_exif, DatePrecision};
+use crate::image_text::{has_image_text, read_image_text};
+use chrono::{FixedOffset, NaiveDateTime};
+use exif::{Context, In, Tag};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Start of the APP1 segment of JPEG images that holds the XMP packet.
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// The TIFF tag of XMP packets, used by TIFF and most RAW files.
+const XMP_TAG: Tag = Tag(Context::Tiff, 700);
+
+/// Properties of an XMP packet holding the capture date, in order of preference.
+const DATE_PROPERTIES: [&str; 3] = [
+    "exif:DateTimeOriginal",
+    "photoshop:DateCreated",
+    "xmp:CreateDate",
+];
+
+/// The capture date of an XMP packet.
+pub(crate) fn xmp_capture_date(
+    properties: &BTreeMap<String, String>,
+) -> Option<(NaiveDateTime, Option<FixedOffset>, DatePrecision)> {
+    DATE_PROPERTIES
+        .iter()
+        .filter_map(|property| properties.get(*property))
+        .find_map(|value| parse_iso8601(value))
+}
+
+/// The simple properties of an XMP packet, written either as attributes or as elements with text,
+/// by their qualified name, e.g. `xmp:CreateDate`. Of arrays like the `rdf:Alt` of `dc:title` the
+/// first item is taken.
+pub fn xmp_properties(packet: &str) -> BTreeMap<String, String> {
+    static PROPERTY_REGEX: OnceLock<Regex> = OnceLock::new();
+    let property_regex = PROPERTY_REGEX.get_or_init(|| {
+        Regex::new(
+            r#"(?P<attribute>[A-Za-z][\w.-]*:[A-Za-z][\w.-]*)\s*=\s*(?:"(?P<double>[^"]*)"|'(?P<single>[^']*)')|<(?P<array>[A-Za-z][\w.-]*:[A-Za-z][\w.-]*)>\s*<rdf:(?:Alt|Bag|Seq)>\s*<rdf:li(?:\s[^>]*)?>(?P<item>[^<]*)</rdf:li>|<(?P<element>[A-Za-z][\w.-]*:[A-Za-z][\w.-]*)>(?P<text>[^<]*)</(?P<end>[A-Za-z][\w.-]*:[A-Za-z][\w.-]*)>"#,
+        )
+        .unwrap()
+    });
+    let mut properties = BTreeMap::new();
+    for captures in property_regex.captures_iter(packet) {
+        let property = match (
+            captures.name("attribute"),
+            captures.name("array"),
+            captures.name("element"),
+        ) {
+            (Some(name), _, _) => captures
+                .name("double")
+                .or_else(|| captures.name("single"))
+                .map(|value| (name.as_str(), value.as_str())),
+            (None, Some(name), _) => captures
+                .name("item")
+                .map(|value| (name.as_str(), value.as_str())),
+            (None, None, Some(name))
+                if captures.name("end").map(|m| m.as_str()) == Some(name.as_str()) =>
+            {
+                captures
+                    .name("text")
+                    .map(|value| (name.as_str(), value.as_str()))
+            }
+            _ => None,
+        };
+        if let Some((name, value)) = property {
+            let value = value.trim();
+            // Namespace declarations and RDF syntax like `xml:lang` are no properties.
+            let syntax = ["xmlns:", "xml:", "rdf:"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix));
+            if !syntax && !value.is_empty() {
+                properties
+                    .entry(name.to_string())
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+    }
+    properties
+}
+
+/// The sidecar of a file, `IMG_1234.jpg.xmp` as written by darktable and digiKam or `IMG_1234.xmp`
+/// as written by Lightroom.
+pub fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    ["xmp", "XMP"]
+        .iter()
+        .flat_map(|extension| {
+            [
+                path.with_file_name(format!("{}.{}", file_name, extension)),
+                path.with_extension(extension),
+            ]
+        })
+        .find(|sidecar| sidecar != path && sidecar.is_file())
+}
+
+/// Reads the XMP packet embedded in an image.
+pub fn read_embedded_xmp(path: &Path) -> Result<String, String> {
+    if has_image_text(path) {
+        return read_image_text(path)?
+            .xmp
+            .ok_or("No XMP packet found.".to_string());
+    }
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut signature = [0; 2];
+    file.read_exact(&mut signature).map_err(|e| e.to_string())?;
+    if signature == [0xFF, 0xD8] {
+        return read_jpeg_xmp(&mut file)
+            .map_err(|e| e.to_string())?
+            .ok_or("No XMP packet found.".to_string());
+    }
+    let exif = read_exif(path)?;
+    match exif
+        .get_field(XMP_TAG, In::PRIMARY)
+        .map(|field| &field.value)
+    {
+        Some(exif::Value::Byte(bytes) | exif::Value::Undefined(bytes, _)) => {
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        }
+        _ => Err("No XMP packet found.".to_string()),
+    }
+}
+
+/// Walks the segments of a JPEG image up to the image data, after the start of image marker.
+fn read_jpeg_xmp<R: Read + Seek>(reader: &mut R) -> io::Result<Option<String>> {
+    loop {
+        let mut marker = [0; 4];
+        reader.read_exact(&mut marker)?;
+        // Start of scan, the image data follows.
+        if marker[0] != 0xFF || marker[1] == 0xDA {
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([marker[2], marker[3]]) as usize;
+        let Some(length) = length.checked_sub(2) else {
+            return Ok(None);
+        };
+        if marker[1] == 0xE1 && length > JPEG_XMP_HEADER.len() {
+            let mut segment = vec![0; length];
+            reader.read_exact(&mut segment)?;
+            if let Some(packet) = segment.strip_prefix(JPEG_XMP_HEADER) {
+                return Ok(Some(String::from_utf8_lossy(packet).into_owned()));
+            }
+        } else {
+            reader.seek(SeekFrom::Current(length as i64))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmp:CreateDate="2021-03-14T12:00:00"
+    photoshop:DateCreated='2021-03-14T11:00:00+01:00'>
+   <exif:DateTimeOriginal>2021-03-14T10:00:00</exif:DateTimeOriginal>
+   <xmp:Label> </xmp:Label>
+   <dc:title>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">Sunset</rdf:li>
+     <rdf:li xml:lang="de">Sonnenuntergang</rdf:li>
+    </rdf:Alt>
+   </dc:title>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>beach</rdf:li>
+     <rdf:li>evening</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn reads_attributes_elements_and_arrays() {
+        let properties = xmp_properties(PACKET);
+        let expected = [
+            ("dc:subject", "beach"),
+            ("dc:title", "Sunset"),
+            ("exif:DateTimeOriginal", "2021-03-14T10:00:00"),
+            ("photoshop:DateCreated", "2021-03-14T11:00:00+01:00"),
+            ("xmp:CreateDate", "2021-03-14T12:00:00"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(properties, BTreeMap::from(expected));
+    }
+
+    #[test]
+    fn keeps_the_first_value_of_a_property() {
+        let properties = xmp_properties(
+            r#"<rdf:Description xmp:Rating="3"><xmp:Rating>5</xmp:Rating></rdf:Description>"#,
+        );
+        assert_eq!(properties["xmp:Rating"], "3");
+        let properties = xmp_properties("<xmp:Rating>5</exif:Rating>");
+        assert!(properties.is_empty());
+    }
+
+    #[test]
+    fn prefers_the_original_capture_date() {
+        let (date_time, offset, _) = xmp_capture_date(&xmp_properties(PACKET)).unwrap();
+        assert_eq!(date_time.to_string(), "2021-03-14 10:00:00");
+        assert_eq!(offset, None);
+        let mut properties = xmp_properties(PACKET);
+        properties.remove("exif:DateTimeOriginal");
+        let (_, offset, _) = xmp_capture_date(&properties).unwrap();
+        assert_eq!(offset, FixedOffset::east_opt(3600));
+    }
+}